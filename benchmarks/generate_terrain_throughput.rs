@@ -18,13 +18,13 @@ extern crate time;
 use common::surroundings_loader;
 use common::surroundings_loader::LoadType;
 use client_lib::{chunk, lod, terrain_mesh};
-use server_lib::{server, update_gaia};
+use server_lib::{clock, server, update_gaia};
 use update_gaia::LoadDestination;
 
 fn main() {
   env_logger::init().unwrap();
 
-  let server = server::new();
+  let server = server::new(clock::real(), server::random_session_seed());
 
   let load_position = cgmath::Point3::new(0.0, 512.0, 0.0);
   let load_position = chunk::position::of_world_position(&load_position);
@@ -21,8 +21,13 @@ static ALLOCATOR: std::heap::System = std::heap::System;
 fn main() {
   env_logger::init().unwrap();
 
-  let mut args = env::args();
+  let mut args = env::args().peekable();
   args.next().unwrap();
+  // `--spectate` watches from a free camera, without adding a player.
+  let spectate = args.peek().map(|s| s == "--spectate") == Some(true);
+  if spectate {
+    args.next();
+  }
   let listen_url = args.next().unwrap_or_else(|| String::from("ipc:///tmp/client.ipc"));
   let server_url = args.next().unwrap_or_else(|| String::from("ipc:///tmp/server.ipc"));
   assert!(args.next().is_none());
@@ -30,5 +35,5 @@ fn main() {
   info!("Sending to {}.", server_url);
   info!("Listening on {}.", listen_url);
 
-  client_lib::run(listen_url.borrow(), server_url.borrow());
+  client_lib::run(listen_url.borrow(), server_url.borrow(), spectate);
 }
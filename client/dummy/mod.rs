@@ -65,7 +65,8 @@ pub fn run(listen_url: &str, server_url: &str) {
           &mut |msg| {
             if let client_lib::terrain::Load::Voxels { time_requested: Some(_), .. } = msg {
               *loaded_count.lock().unwrap() += 1;
-              *client.pending_terrain_requests.lock().unwrap() -= 1;
+              let mut pending = client.pending_terrain_requests.lock().unwrap();
+              *pending = pending.saturating_sub(1);
             }
           },
         );
@@ -78,6 +79,11 @@ pub fn run(listen_url: &str, server_url: &str) {
   }
 
   println!("{} bytes sent", *server.talk.bytes_sent.lock().unwrap());
+  println!("{} bytes received", *server.listen.bytes_received.lock().unwrap());
+
+  // We're leaving on purpose, so don't try to reattach when the server hangs up.
+  *server.session.lock().unwrap() = None;
+  server.talk.tell(&protocol::ClientToServer::Leave(client.id));
 
   // View thread returned, so we got a quit event.
   *quit.lock().unwrap() = true;
@@ -88,16 +94,26 @@ pub fn run(listen_url: &str, server_url: &str) {
 
 fn connect_client(listen_url: &str, server: &server::T) -> client::T {
   // TODO: Consider using RPCs to solidify the request-response patterns.
-  server.talk.tell(&protocol::ClientToServer::Init(listen_url.to_owned()));
+  server.talk.tell(
+    &protocol::ClientToServer::Init {
+      version  : protocol::VERSION,
+      url      : listen_url.to_owned(),
+      features : server::FEATURES.iter().map(|&f| f.to_owned()).collect(),
+    }
+  );
   loop {
     match server.listen.wait() {
-      protocol::ServerToClient::LeaseId(client_id) => {
+      protocol::ServerToClient::InitRejected(rejection) => {
+        panic!("Server rejected connection: {:?}", rejection);
+      },
+      protocol::ServerToClient::LeaseId(client_id, token) => {
+        *server.session.lock().unwrap() = Some((client_id, token));
         server.talk.tell(&protocol::ClientToServer::AddPlayer(client_id));
         let client_id = client_id;
         loop {
           match server.listen.wait() {
             protocol::ServerToClient::PlayerAdded(player_id, position) => {
-              return client::new(client_id, player_id, position);
+              return client::new(client_id, Some(player_id), position);
             },
             msg => {
               // Ignore other messages in the meantime.
@@ -7,10 +7,15 @@ use rand::{Rng, SeedableRng};
 use std::sync::Mutex;
 
 use common::id_allocator;
+use common::movement;
 use common::protocol;
 use common::surroundings_loader;
 
+use interpolation;
 use lod;
+use prediction;
+use snapshots;
+use spectator;
 use terrain;
 use view;
 
@@ -21,10 +26,18 @@ const MAX_LOAD_DISTANCE: u32 = 80;
 pub struct T {
   #[allow(missing_docs)]
   pub id                       : protocol::ClientId,
-  /// id for the player in vram
-  pub player_id                : view::entity::id::Player,
+  /// id for the player in vram, or None if we're only spectating
+  pub player_id                : Option<view::entity::id::Player>,
+  /// the free-flying camera we watch from, if we're spectating
+  pub spectator                : Option<Mutex<spectator::T>>,
   /// position of the player in world coordinates
   pub player_position          : Mutex<Point3<f32>>,
+  /// where we expect the player to be, before the server confirms it
+  pub prediction               : Mutex<prediction::T>,
+  /// the world snapshots received from the server
+  pub snapshots                : Mutex<snapshots::T>,
+  /// buffered updates for other entities, to draw them smoothly
+  pub interpolation            : Mutex<interpolation::T>,
   /// the location where we last played a footstep sound
   pub last_footstep            : Mutex<Point3<f32>>,
   /// world position to center terrain loading around
@@ -43,6 +56,8 @@ pub struct T {
   pub pending_terrain_requests : Mutex<u32>,
   #[allow(missing_docs)]
   pub rng                      : Mutex<rand::XorShiftRng>,
+  /// Set when the server has forgotten our session, so this client has to be replaced by a new one.
+  pub session_lost             : Mutex<bool>,
 }
 
 fn load_distance(mut polygon_budget: i32) -> u32 {
@@ -90,8 +105,15 @@ fn load_distance(mut polygon_budget: i32) -> u32 {
   load_distance
 }
 
-#[allow(missing_docs)]
-pub fn new(client_id: protocol::ClientId, player_id: view::entity::id::Player, position: Point3<f32>) -> T {
+fn new_surroundings_loader(load_distance: u32) -> surroundings_loader::T {
+  surroundings_loader::new(
+    load_distance,
+    lod::THRESHOLDS.iter().map(|&x| x as i32).collect(),
+  )
+}
+
+/// Create the client state. Without a `player_id`, the client spectates from a free camera starting at `position`.
+pub fn new(client_id: protocol::ClientId, player_id: Option<view::entity::id::Player>, position: Point3<f32>) -> T {
   let mut rng: rand::XorShiftRng = rand::SeedableRng::from_seed([1, 2, 3, 4]);
   let s1 = rng.next_u32();
   let s2 = rng.next_u32();
@@ -108,26 +130,60 @@ pub fn new(client_id: protocol::ClientId, player_id: view::entity::id::Player, p
     info!("load_distance {}", load_distance);
   }
 
-  let surroundings_loader = {
-    surroundings_loader::new(
-      load_distance,
-      lod::THRESHOLDS.iter().map(|&x| x as i32).collect(),
-    )
-  };
-
   T {
     id                       : client_id,
     player_id                : player_id,
+    spectator                :
+      match player_id {
+        None => Some(Mutex::new(spectator::new(position))),
+        Some(_) => None,
+      },
     player_position          : Mutex::new(position),
+    prediction               : Mutex::new(prediction::new(position)),
+    snapshots                : Mutex::new(snapshots::new()),
+    interpolation            : Mutex::new(interpolation::new()),
     last_footstep            : Mutex::new(position),
     load_position            : Mutex::new(None),
     terrain_allocator        : Mutex::new(id_allocator::new()),
     grass_allocator          : Mutex::new(id_allocator::new()),
-    surroundings_loader      : Mutex::new(surroundings_loader),
+    surroundings_loader      : Mutex::new(new_surroundings_loader(load_distance)),
     max_load_distance        : load_distance,
     terrain                  : Mutex::new(terrain::new(load_distance as u32)),
     pending_terrain_requests : Mutex::new(0),
     rng                      : Mutex::new(rng),
+    session_lost             : Mutex::new(false),
+  }
+}
+
+impl T {
+  /// Forget about terrain requests that may have been lost, and go over our surroundings again
+  /// to request whatever is still missing. Chunks we already have aren't requested again.
+  pub fn retry_terrain_requests(&self) {
+    *self.pending_terrain_requests.lock().unwrap() = 0;
+    *self.surroundings_loader.lock().unwrap() = new_surroundings_loader(self.max_load_distance);
+  }
+
+  /// Apply a movement input to our own player right away, and send it to the server.
+  /// Spectators fly their camera instead, and tell the server where it ends up in `update_thread`.
+  pub fn input<UpdateServer>(&self, update_server: &mut UpdateServer, input: movement::Input) where
+    UpdateServer: FnMut(protocol::ClientToServer),
+  {
+    // Hold ourselves to the same limits the server does, so our predictions match.
+    let input =
+      match input.clamped() {
+        None => return,
+        Some(input) => input,
+      };
+    let player_id =
+      match self.player_id {
+        Some(player_id) => player_id,
+        None => {
+          self.spectator.as_ref().map(|spectator| spectator.lock().unwrap().apply(&input));
+          return
+        },
+      };
+    let sequence = self.prediction.lock().unwrap().input(input);
+    update_server(protocol::ClientToServer::input(self.id, player_id, input, sequence));
   }
 }
 
@@ -3,21 +3,54 @@
 use cgmath::Point2;
 
 use common::color::Color4;
+use common::inventory;
+use common::protocol;
 
 use vertex::{ColoredVertex};
 use view;
 
+const VERTICES_PER_SQUARE: usize = 6;
+/// Where the health bar's fill starts in `hud_triangles`, after the cursor and the bar's background.
+const HEALTH_FILL_OFFSET: usize = 2 * VERTICES_PER_SQUARE;
+
+/// The corners of the health bar, centered along the top of the screen.
+const HEALTH_BAR_MIN: Point2<f32> = Point2 { x: -0.4, y: 0.9 };
+const HEALTH_BAR_MAX: Point2<f32> = Point2 { x:  0.4, y: 0.95 };
+
+fn health_fill(health: u32) -> [ColoredVertex; VERTICES_PER_SQUARE] {
+  let fraction = health as f32 / protocol::MAX_HEALTH as f32;
+  let width = (HEALTH_BAR_MAX.x - HEALTH_BAR_MIN.x) * fraction;
+  ColoredVertex::square(
+    HEALTH_BAR_MIN,
+    Point2 { x: HEALTH_BAR_MIN.x + width, y: HEALTH_BAR_MAX.y },
+    Color4::of_rgba(0.8, 0.0, 0.0, 0.75),
+  )
+}
+
 /// Add HUD data into `view`.
 pub fn make_hud<'a, 'b:'a>(view: &'a mut view::T<'b>) {
   let cursor_color = Color4::of_rgba(0.0, 0.0, 0.0, 0.75);
 
-  let triangles: Vec<_> =
+  let mut triangles: Vec<_> =
     ColoredVertex::square(
       Point2 { x: -0.02, y: -0.02 },
       Point2 { x:  0.02, y:  0.02 },
       cursor_color
     ).iter().cloned().collect();
 
+  triangles.extend(
+    ColoredVertex::square(HEALTH_BAR_MIN, HEALTH_BAR_MAX, Color4::of_rgba(0.0, 0.0, 0.0, 0.5)).iter().cloned()
+  );
+  triangles.extend(health_fill(protocol::MAX_HEALTH).iter().cloned());
+
   view.hud_triangles.bind(&mut view.gl);
   view.hud_triangles.push(&mut view.gl, triangles.as_ref());
+
+  view.inventory.set(&view.gl, &view.fonts.mono, &inventory::new());
+}
+
+/// Show `health` on the health bar.
+pub fn set_health<'a, 'b:'a>(view: &'a mut view::T<'b>, health: u32) {
+  view.hud_triangles.buffer.byte_buffer.bind(&mut view.gl);
+  view.hud_triangles.buffer.update(&mut view.gl, HEALTH_FILL_OFFSET, &health_fill(health));
 }
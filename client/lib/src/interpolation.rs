@@ -0,0 +1,168 @@
+//! Smooth out remote entities' movement by rendering them slightly in the past,
+//! between the timestamped updates the server sends.
+
+use collision::{Aabb3};
+use std::collections::VecDeque;
+use std::hash::Hash;
+
+use common::entity;
+use common::fnv_map;
+use common::mob_kind;
+use common::protocol;
+
+/// How far behind the server's clock remote entities are rendered.
+/// A few server ticks, so there's usually a later update to interpolate towards.
+const DELAY_NS: u64 = 100_000_000;
+/// How long to keep extrapolating an entity's movement when its updates are late.
+const MAX_EXTRAPOLATION_NS: u64 = 250_000_000;
+/// How often remote entities' meshes are recomputed.
+pub const RENDER_INTERVAL_NS: u64 = 1_000_000_000 / 60;
+
+fn lerp(a: &Aabb3<f32>, b: &Aabb3<f32>, t: f32) -> Aabb3<f32> {
+  Aabb3::new(
+    a.min + (b.min - a.min) * t,
+    a.max + (b.max - a.max) * t,
+  )
+}
+
+struct Track {
+  /// Updates from the server, ordered by time.
+  snapshots     : VecDeque<(protocol::ServerTime, Aabb3<f32>)>,
+  last_rendered : Option<Aabb3<f32>>,
+}
+
+impl Track {
+  fn sample(&mut self, at: protocol::ServerTime) -> Aabb3<f32> {
+    // Keep the last snapshot before `at`, and everything after it.
+    // Always keep two around, in case we need to extrapolate.
+    while self.snapshots.len() > 2 && self.snapshots[1].0 <= at {
+      self.snapshots.pop_front();
+    }
+
+    let (t0, b0) = self.snapshots[0];
+    if self.snapshots.len() == 1 || at <= t0 {
+      return b0
+    }
+
+    let (t1, b1) = self.snapshots[1];
+    let at =
+      if at < t1 {
+        at
+      } else {
+        // The next update is late. Keep going the way we were, for a little while.
+        let late = at - t1;
+        t1 + if late < MAX_EXTRAPOLATION_NS { late } else { MAX_EXTRAPOLATION_NS }
+      };
+    lerp(&b0, &b1, (at - t0) as f32 / (t1 - t0) as f32)
+  }
+}
+
+/// Buffered updates for one kind of entity.
+pub struct Tracks<Id> {
+  tracks : fnv_map::T<Id, Track>,
+}
+
+impl<Id> Tracks<Id> where Id: Copy + Eq + Hash {
+  fn new() -> Self {
+    Tracks {
+      tracks : fnv_map::new(),
+    }
+  }
+
+  /// Forget an entity's old positions; it has just (re)appeared at `bounds`.
+  pub fn reset(&mut self, id: Id, time: protocol::ServerTime, bounds: Aabb3<f32>) {
+    let mut snapshots = VecDeque::new();
+    snapshots.push_back((time, bounds));
+    self.tracks.insert(
+      id,
+      Track {
+        snapshots     : snapshots,
+        last_rendered : None,
+      },
+    );
+  }
+
+  /// Buffer an update for an entity. Updates that arrive out of order are dropped.
+  pub fn push(&mut self, id: Id, time: protocol::ServerTime, bounds: Aabb3<f32>) {
+    let is_new =
+      match self.tracks.get_mut(&id) {
+        None => true,
+        Some(track) => {
+          let in_order = track.snapshots.back().map(|&(t, _)| t < time) != Some(false);
+          if in_order {
+            track.snapshots.push_back((time, bounds));
+          }
+          false
+        },
+      };
+    if is_new {
+      self.reset(id, time, bounds);
+    }
+  }
+
+  /// Stop tracking an entity.
+  pub fn remove(&mut self, id: Id) {
+    self.tracks.remove(&id);
+  }
+
+  /// Find where each entity should be drawn at server time `at`.
+  /// Only entities that have moved since the last call are returned.
+  pub fn sample(&mut self, at: protocol::ServerTime) -> Vec<(Id, Aabb3<f32>)> {
+    let mut moved = Vec::new();
+    for (&id, track) in self.tracks.iter_mut() {
+      let bounds = track.sample(at);
+      if track.last_rendered != Some(bounds) {
+        track.last_rendered = Some(bounds);
+        moved.push((id, bounds));
+      }
+    }
+    moved
+  }
+}
+
+#[allow(missing_docs)]
+pub struct T {
+  /// The smallest (local time - server time) seen so far, i.e. the server's clock
+  /// offset plus the quickest any update has reached us.
+  clock_offset : Option<i64>,
+  #[allow(missing_docs)]
+  pub players  : Tracks<entity::id::Player>,
+  #[allow(missing_docs)]
+  pub mobs     : Tracks<entity::id::Mob>,
+  /// What kind each tracked mob is, so it can be drawn as one.
+  pub mob_kinds : fnv_map::T<entity::id::Mob, mob_kind::T>,
+}
+
+#[allow(missing_docs)]
+pub fn new() -> T {
+  T {
+    clock_offset : None,
+    players      : Tracks::new(),
+    mobs         : Tracks::new(),
+    mob_kinds    : fnv_map::new(),
+  }
+}
+
+impl T {
+  /// Note that an update stamped `server_time` arrived at `local_time_ns`.
+  pub fn observe(&mut self, server_time: protocol::ServerTime, local_time_ns: u64) {
+    let offset = local_time_ns as i64 - server_time as i64;
+    let is_min = self.clock_offset.map(|o| offset < o) != Some(false);
+    if is_min {
+      self.clock_offset = Some(offset);
+    }
+  }
+
+  /// The server time to draw remote entities at, given the local time.
+  /// None until we've heard from the server.
+  pub fn render_time(&self, local_time_ns: u64) -> Option<protocol::ServerTime> {
+    self.clock_offset.map(|offset| {
+      let server_now = local_time_ns as i64 - offset;
+      if server_now < DELAY_NS as i64 {
+        0
+      } else {
+        server_now as u64 - DELAY_NS
+      }
+    })
+  }
+}
@@ -38,12 +38,16 @@ pub mod chunk;
 pub mod chunk_stats;
 pub mod client;
 pub mod hud;
+pub mod interpolation;
 pub mod lod;
+pub mod prediction;
 pub mod process_event;
 pub mod record_book;
 pub mod run;
 pub mod server;
 pub mod server_update;
+pub mod snapshots;
+pub mod spectator;
 pub mod terrain;
 pub mod terrain_mesh;
 pub mod update_thread;
@@ -0,0 +1,239 @@
+//! Predict the local player's movement, and reconcile those predictions with the server.
+
+use cgmath;
+use cgmath::{Point3, Vector3, EuclideanSpace, InnerSpace, ElementWise};
+use collision::{Aabb3};
+use std::collections::VecDeque;
+use time;
+
+use common::interval_timer::IntervalTimer;
+use common::movement;
+use common::protocol;
+use common::voxel;
+
+/// How far a prediction can be from the server's result before it's corrected.
+const TOLERANCE: f32 = 0.01;
+/// How many ticks of predictions to keep around for comparing against the server.
+const MAX_HISTORY: usize = 256;
+
+#[derive(Debug, Clone, Copy)]
+struct PendingInput {
+  sequence : protocol::InputSequence,
+  /// The number of ticks that had been simulated when this input was applied.
+  tick     : u64,
+  input    : movement::Input,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Snapshot {
+  tick   : u64,
+  state  : movement::State,
+  bounds : Aabb3<f32>,
+}
+
+/// The player's body, moving through the voxels we've cached from the server.
+struct Body<'a> {
+  voxels : &'a voxel::tree::T,
+  bounds : Aabb3<f32>,
+}
+
+impl<'a> movement::Collider for Body<'a> {
+  type Obstacle = ();
+
+  fn bounds(&self) -> Aabb3<f32> {
+    self.bounds
+  }
+
+  fn sweep(&mut self, shift: Vector3<f32>) -> Option<movement::Collision<()>> {
+    let swept = movement::swept_bounds(&self.bounds, &shift);
+    let obstacles = solid_voxels_in(self.voxels, &swept).into_iter().map(|shape| (shape, ()));
+    let hit = movement::first_impact(&self.bounds, &shift, obstacles);
+    let t = hit.as_ref().map(|&(t, _, _, _)| t).unwrap_or(1.0);
+    self.bounds = Aabb3::new(self.bounds.min + shift * t, self.bounds.max + shift * t);
+    hit.map(|(_, normal, bounds, ())| {
+      movement::Collision {
+        obstacle   : (),
+        bounds     : bounds,
+        normal     : normal,
+        is_terrain : true,
+      }
+    })
+  }
+}
+
+/// The solid parts of the voxels that overlap `bounds`. Voxels we don't have are treated as solid boxes,
+/// the same way the server blocks off terrain it's still loading, so we don't predict walking into it.
+fn solid_voxels_in(voxels: &voxel::tree::T, bounds: &Aabb3<f32>) -> Vec<movement::Shape> {
+  let mut solid = Vec::new();
+  for x in bounds.min.x.floor() as i32 .. bounds.max.x.ceil() as i32 {
+  for y in bounds.min.y.floor() as i32 .. bounds.max.y.ceil() as i32 {
+  for z in bounds.min.z.floor() as i32 .. bounds.max.z.ceil() as i32 {
+    let voxel_bounds = voxel::bounds::new(x, y, z, 0);
+    match voxels.get(&voxel_bounds) {
+      None => {
+        let (low, high) = voxel_bounds.corners();
+        solid.push(movement::Shape::cuboid(Aabb3::new(low, high)));
+      },
+      Some(voxel) => solid.extend(movement::Shape::of_voxel(&voxel_bounds, voxel)),
+    }
+  }}}
+  solid
+}
+
+/// Where the camera should be for a player with the given bounds.
+pub fn eye_position(bounds: &Aabb3<f32>) -> Point3<f32> {
+  let position =
+    (bounds.min.to_vec().mul_element_wise(cgmath::Vector3::new(0.5, 0.1, 0.5))) +
+    (bounds.max.to_vec().mul_element_wise(cgmath::Vector3::new(0.5, 0.9, 0.5)));
+  Point3::from_vec(position)
+}
+
+#[allow(missing_docs)]
+pub struct T {
+  state         : movement::State,
+  bounds        : Aabb3<f32>,
+  /// The number of ticks simulated so far.
+  tick          : u64,
+  timer         : IntervalTimer,
+  next_sequence : protocol::InputSequence,
+  /// Inputs the server hasn't acknowledged yet, in order.
+  pending       : VecDeque<PendingInput>,
+  /// The tick at which the last acknowledged input was applied.
+  acked_tick    : u64,
+  /// The predictions we've made, in order.
+  history       : VecDeque<Snapshot>,
+}
+
+/// Start predicting a player centered at `position`.
+pub fn new(position: Point3<f32>) -> T {
+  let half_extents = Vector3::new(0.5, 1.0, 0.5);
+  let nanoseconds_per_second = 1000000000;
+  T {
+    state         : movement::new(),
+    bounds        : Aabb3::new(position + -half_extents, position + half_extents),
+    tick          : 0,
+    timer         :
+      IntervalTimer::new(
+        nanoseconds_per_second / movement::TICKS_PER_SECOND,
+        time::precise_time_ns(),
+      ),
+    next_sequence : 1,
+    pending       : VecDeque::new(),
+    acked_tick    : 0,
+    history       : VecDeque::new(),
+  }
+}
+
+impl T {
+  /// The predicted bounds of the player.
+  pub fn bounds(&self) -> Aabb3<f32> {
+    self.bounds
+  }
+
+  /// Apply an input locally. Returns the sequence number to send it to the server with.
+  pub fn input(&mut self, input: movement::Input) -> protocol::InputSequence {
+    let sequence = self.next_sequence;
+    self.next_sequence += 1;
+
+    self.state.apply(&input);
+    self.pending.push_back(
+      PendingInput {
+        sequence : sequence,
+        tick     : self.tick,
+        input    : input,
+      }
+    );
+
+    sequence
+  }
+
+  /// Simulate any ticks that are due. Returns true if any were.
+  pub fn update(&mut self, voxels: &voxel::tree::T) -> bool {
+    let ticks = self.timer.update(time::precise_time_ns());
+    for _ in 0 .. ticks {
+      self.step(voxels);
+    }
+    ticks > 0
+  }
+
+  fn step(&mut self, voxels: &voxel::tree::T) {
+    let mut body = Body { voxels: voxels, bounds: self.bounds };
+    self.state.tick(&mut body);
+    self.bounds = body.bounds;
+    self.tick += 1;
+
+    self.history.push_back(
+      Snapshot {
+        tick   : self.tick,
+        state  : self.state,
+        bounds : self.bounds,
+      }
+    );
+    if self.history.len() > MAX_HISTORY {
+      self.history.pop_front();
+    }
+  }
+
+  /// The server applied our inputs up to `sequence`, then simulated `ticks_since` ticks,
+  /// ending up at `state` and `bounds`. If we predicted differently, rewind to the server's
+  /// result and replay the inputs it hasn't seen yet.
+  pub fn reconcile(
+    &mut self,
+    voxels      : &voxel::tree::T,
+    sequence    : protocol::InputSequence,
+    ticks_since : u32,
+    state       : movement::State,
+    bounds      : Aabb3<f32>,
+  ) {
+    while let Some(&PendingInput { sequence: s, tick, .. }) = self.pending.front() {
+      if s > sequence {
+        break
+      }
+      self.acked_tick = tick;
+      self.pending.pop_front();
+    }
+
+    let target = self.acked_tick + ticks_since as u64;
+    while self.history.front().map(|snapshot| snapshot.tick < target) == Some(true) {
+      self.history.pop_front();
+    }
+
+    let predicted_correctly =
+      match self.history.front() {
+        Some(snapshot) if snapshot.tick == target =>
+          (snapshot.bounds.min - bounds.min).magnitude2() <= TOLERANCE * TOLERANCE &&
+          (snapshot.state.speed - state.speed).magnitude2() <= TOLERANCE * TOLERANCE,
+        _ => false,
+      };
+    if predicted_correctly {
+      return
+    }
+
+    debug!("Correcting predicted player movement at tick {}", target);
+
+    let now = self.tick;
+    self.state = state;
+    self.bounds = bounds;
+    self.history.clear();
+    self.tick = if target < now { target } else { now };
+
+    let pending: Vec<PendingInput> = self.pending.iter().cloned().collect();
+    let mut pending = pending.into_iter().peekable();
+    loop {
+      loop {
+        match pending.peek() {
+          Some(input) if input.tick <= self.tick => {},
+          _ => break,
+        }
+        let input = pending.next().unwrap();
+        self.state.apply(&input.input);
+      }
+
+      if self.tick >= now {
+        break
+      }
+
+      self.step(voxels);
+    }
+  }
+}
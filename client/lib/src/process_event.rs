@@ -1,13 +1,14 @@
 //! SDL input event processing code.
 
-use cgmath::{Vector2, Vector3};
+use cgmath::{Vector2};
 use sdl2::event::Event;
 use sdl2::keyboard::Keycode;
 use sdl2::mouse::MouseButton;
 use std::f32::consts::PI;
 use stopwatch;
 
-use common::entity;
+use common::fnv_set;
+use common::movement;
 use common::protocol;
 
 use client;
@@ -24,23 +25,37 @@ pub fn process_event<UpdateServer>(
   match event {
     Event::KeyDown{keycode, repeat, ..} => {
       keycode.map(|keycode| {
-        if !repeat {
-          key_press(update_server, view, client, keycode);
+        match view.input_mode {
+          view::InputMode::Chat => {
+            chat_key_press(update_server, view, client, keycode);
+          },
+          _ => {
+            if !repeat {
+              view.held_keys.insert(keycode);
+              key_press(update_server, view, client, keycode);
+            }
+          },
         }
       });
     },
     Event::KeyUp{keycode, repeat, ..} => {
       keycode.map(|keycode| {
-        if !repeat {
-          key_release(client.player_id, update_server, keycode);
+        // Only undo presses that were acted on (e.g. not ones made while typing a chat message).
+        if !repeat && view.held_keys.remove(&keycode) {
+          key_release(client, update_server, &view.held_keys, keycode);
         }
       });
     },
+    Event::TextInput{text, ..} => {
+      if let view::InputMode::Chat = view.input_mode {
+        view.chat.type_text(&view.gl, &view.fonts.mono, &text);
+      }
+    },
     Event::MouseMotion{xrel, yrel, ..} => {
-      mouse_move(client.player_id, update_server, view, xrel, yrel);
+      mouse_move(client, update_server, view, xrel, yrel);
     },
     Event::MouseButtonDown{mouse_btn, ..} => {
-      mouse_press(client.player_id, update_server, mouse_btn);
+      mouse_press(client, update_server, mouse_btn);
     },
     _ => {},
   }
@@ -53,18 +68,17 @@ fn key_press<UpdateServer>(
   key: Keycode,
 ) where UpdateServer: FnMut(protocol::ClientToServer)
 {
-  use common::protocol::ClientToServer::*;
-
   let lr = |update_server: &mut UpdateServer, view: &mut view::T, k| {
     match view.input_mode {
       view::InputMode::Camera => {
         let angle = k * PI / 12.0;
-        update_server(RotatePlayer(client.player_id, Vector2::new(angle, 0.0)));
+        client.input(update_server, movement::Input::Rotate(Vector2::new(angle, 0.0)));
         view.camera.rotate_lateral(angle);
       },
       view::InputMode::Sun => {
         view.sun.rotation += k * PI / 512.0;
       },
+      view::InputMode::Chat => {},
     }
   };
 
@@ -72,31 +86,20 @@ fn key_press<UpdateServer>(
     match view.input_mode {
       view::InputMode::Camera => {
         let angle = k * PI / 12.0;
-        update_server(RotatePlayer(client.player_id, Vector2::new(0.0, angle)));
+        client.input(update_server, movement::Input::Rotate(Vector2::new(0.0, angle)));
         view.camera.rotate_vertical(angle);
       },
       view::InputMode::Sun => {
         view.sun.progression += k * PI / 512.0;
       },
+      view::InputMode::Chat => {},
     }
   };
 
   stopwatch::time("event.key_press", || {
     match key {
-      Keycode::A => {
-        update_server(Walk(client.player_id, Vector3::new(-1.0, 0.0, 0.0)));
-      },
-      Keycode::D => {
-        update_server(Walk(client.player_id, Vector3::new(1.0, 0.0, 0.0)));
-      },
-      Keycode::Space => {
-        update_server(StartJump(client.player_id));
-      },
-      Keycode::W => {
-        update_server(Walk(client.player_id, Vector3::new(0.0, 0.0, -1.0)));
-      },
-      Keycode::S => {
-        update_server(Walk(client.player_id, Vector3::new(0.0, 0.0, 1.0)));
+      Keycode::A | Keycode::D | Keycode::W | Keycode::S | Keycode::Space => {
+        client.input(update_server, movement::Input::SetState(input_state(&view.held_keys)));
       },
       Keycode::Left => {
         lr(update_server, view, 1.0);
@@ -118,8 +121,13 @@ fn key_press<UpdateServer>(
           match view.input_mode {
             view::InputMode::Camera => view::InputMode::Sun,
             view::InputMode::Sun => view::InputMode::Camera,
+            view::InputMode::Chat => view::InputMode::Chat,
           };
       },
+      Keycode::Return => {
+        view.input_mode = view::InputMode::Chat;
+        view.chat.start_typing(&view.gl, &view.fonts.mono);
+      },
       Keycode::P => {
         let mut load_position = client.load_position.lock().unwrap();
         match *load_position {
@@ -132,22 +140,63 @@ fn key_press<UpdateServer>(
   })
 }
 
+/// Handle a key press while typing a chat message.
+fn chat_key_press<UpdateServer>(
+  update_server: &mut UpdateServer,
+  view: &mut view::T,
+  client: &client::T,
+  key: Keycode,
+) where UpdateServer: FnMut(protocol::ClientToServer)
+{
+  stopwatch::time("event.chat_key_press", || {
+    match key {
+      Keycode::Return => {
+        view.input_mode = view::InputMode::Camera;
+        view.chat.finish_typing().map(|text| {
+          if text.trim().is_empty() {
+            return
+          }
+          match client.player_id {
+            Some(player_id) => update_server(protocol::ClientToServer::Chat(client.id, player_id, text)),
+            None => warn!("Spectators can't chat."),
+          }
+        });
+      },
+      Keycode::Escape => {
+        view.input_mode = view::InputMode::Camera;
+        view.chat.finish_typing();
+      },
+      Keycode::Backspace => {
+        view.chat.backspace(&view.gl, &view.fonts.mono);
+      },
+      // Everything else arrives as text input.
+      _ => {},
+    }
+  })
+}
+
 fn mouse_press<UpdateServer>(
-  player_id: entity::id::Player,
+  client: &client::T,
   update_server: &mut UpdateServer,
   mouse_btn: MouseButton,
 ) where UpdateServer: FnMut(protocol::ClientToServer)
 {
   stopwatch::time("event.mouse_press", || {
+    // Spectators have nobody to brush-edit with.
+    let player_id =
+      match client.player_id {
+        None => return,
+        Some(player_id) => player_id,
+      };
     match mouse_btn {
       MouseButton::Left => {
         update_server(
-          protocol::ClientToServer::Add(player_id)
+          protocol::ClientToServer::Add(client.id, player_id)
         );
       },
       MouseButton::Right => {
         update_server(
-          protocol::ClientToServer::Remove(player_id)
+          protocol::ClientToServer::Remove(client.id, player_id)
         );
       },
       _ => {},
@@ -155,29 +204,28 @@ fn mouse_press<UpdateServer>(
   })
 }
 
+/// The movement controls being held, out of all the keys that are down.
+fn input_state(held_keys: &fnv_set::T<Keycode>) -> movement::InputState {
+  movement::InputState {
+    forward : held_keys.contains(&Keycode::W),
+    back    : held_keys.contains(&Keycode::S),
+    left    : held_keys.contains(&Keycode::A),
+    right   : held_keys.contains(&Keycode::D),
+    jump    : held_keys.contains(&Keycode::Space),
+  }
+}
+
 fn key_release<UpdateServer>(
-  player_id: entity::id::Player,
+  client: &client::T,
   update_server: &mut UpdateServer,
+  held_keys: &fnv_set::T<Keycode>,
   key: Keycode,
 ) where UpdateServer: FnMut(protocol::ClientToServer)
 {
   stopwatch::time("event.key_release", || {
     match key {
-      // accelerations are negated from those in key_press.
-      Keycode::A => {
-        update_server(protocol::ClientToServer::Walk(player_id, Vector3::new(1.0, 0.0, 0.0)));
-      },
-      Keycode::D => {
-        update_server(protocol::ClientToServer::Walk(player_id, Vector3::new(-1.0, 0.0, 0.0)));
-      },
-      Keycode::Space => {
-        update_server(protocol::ClientToServer::StopJump(player_id));
-      },
-      Keycode::W => {
-        update_server(protocol::ClientToServer::Walk(player_id, Vector3::new(0.0, 0.0, 1.0)));
-      },
-      Keycode::S => {
-        update_server(protocol::ClientToServer::Walk(player_id, Vector3::new(0.0, 0.0, -1.0)));
+      Keycode::A | Keycode::D | Keycode::W | Keycode::S | Keycode::Space => {
+        client.input(update_server, movement::Input::SetState(input_state(held_keys)));
       },
       _ => {}
     }
@@ -186,7 +234,7 @@ fn key_release<UpdateServer>(
 
 // x and y are relative to last position.
 fn mouse_move<UpdateServer>(
-  player_id: entity::id::Player,
+  client: &client::T,
   update_server: &mut UpdateServer,
   view: &mut view::T,
   dx: i32, dy: i32,
@@ -198,7 +246,7 @@ fn mouse_move<UpdateServer>(
     let to_radians = Vector2::new(-1.0 / 1000.0, -1.0 / 1600.0);
     let r = Vector2::new(d.x as f32 * to_radians.x, d.y as f32 * to_radians.y);
 
-    update_server(protocol::ClientToServer::RotatePlayer(player_id, r));
+    client.input(update_server, movement::Input::Rotate(r));
     view.camera.rotate_lateral(r.x);
     view.camera.rotate_vertical(r.y);
   })
@@ -1,6 +1,8 @@
 //! entry point
 
+use cgmath::{Point3};
 use std;
+use std::collections::VecDeque;
 use std::io::Write;
 use std::sync::{Mutex};
 use stopwatch;
@@ -15,138 +17,185 @@ use record_book;
 use server;
 use terrain;
 use update_thread::update_thread;
+use view;
 use view::thread::view_thread;
 
-#[allow(missing_docs)]
-pub fn run(listen_url: &str, server_url: &str) {
-  let view_updates0 = Mutex::new(std::collections::VecDeque::new());
-  let view_updates1 = Mutex::new(std::collections::VecDeque::new());
-  let audio_updates = Mutex::new(std::collections::VecDeque::new());
+/// Where spectators start out.
+const SPECTATOR_START: [f32; 3] = [0.0, 68.0, 4.0];
+
+/// Connect to a server and play. If `spectate` is set, watch from a free camera instead of adding a player.
+pub fn run(listen_url: &str, server_url: &str, spectate: bool) {
+  let view_updates0 = Mutex::new(VecDeque::new());
+  let view_updates1 = Mutex::new(VecDeque::new());
+  let audio_updates = Mutex::new(VecDeque::new());
 
   let quit = Mutex::new(false);
   let quit = &quit;
 
   let server = server::new(&server_url, &listen_url);
 
-  let client = connect_client(&listen_url, &server);
-  let client = &client;
-
-  {
-    let monitor_thread = {
-      unsafe {
-        thread_scoped::scoped(|| {
-          while !*quit.lock().unwrap() {
-            info!("Outstanding terrain requests: {}", *client.pending_terrain_requests.lock().unwrap());
-            info!("Outstanding voxel updates: {}", client.terrain.lock().unwrap().queued_update_count());
-            info!("Outstanding view0 updates: {}", view_updates0.lock().unwrap().len());
-            info!("Outstanding view1 updates: {}", view_updates1.lock().unwrap().len());
-            std::thread::sleep(std::time::Duration::from_secs(1));
-          }
-        })
-      }
-    };
-
-    let audio_thread = {
-      let audio_updates = &audio_updates;
-      unsafe {
-        thread_scoped::scoped(move || {
-          audio_thread::audio_thread(
-            quit,
-            &mut || { audio_updates.lock().unwrap().pop_front() },
-          );
-        })
-      }
-    };
-
-    if (1 + 1) - 1 == 0 {
-      audio_updates.lock().unwrap().push_back(audio_thread::Message::PlayLoop(audio_loader::SoundId::Rainforest));
+  let audio_thread = {
+    let audio_updates = &audio_updates;
+    unsafe {
+      thread_scoped::scoped(move || {
+        audio_thread::audio_thread(
+          quit,
+          &mut || { audio_updates.lock().unwrap().pop_front() },
+        );
+      })
     }
+  };
 
-    let update_thread = {
-      let client = &client;
-      let view_updates0 = &view_updates0;
-      let view_updates1 = &view_updates1;
-      let audio_updates = &audio_updates;
-      let server = server.clone();
-      unsafe {
-        thread_scoped::scoped(move || {
-          update_thread(
-            quit,
-            client,
-            &mut || { server.listen.try() },
-            &mut |up| { view_updates0.lock().unwrap().push_back(up) },
-            &mut |up| { view_updates1.lock().unwrap().push_back(up) },
-            &mut |up| { audio_updates.lock().unwrap().push_back(up) },
-  	        &mut |up| { server.talk.tell(&up) },
-            &mut |msg| {
-              match msg {
-                terrain::Load::Voxels { time_requested: None, .. } => {},
-                terrain::Load::Voxels { time_requested: Some(_), .. } => {
-                  *client.pending_terrain_requests.lock().unwrap() -= 1;
-                }
-              };
-              client.terrain.lock().unwrap().enqueue(msg);
-            },
-          );
+  if (1 + 1) - 1 == 0 {
+    audio_updates.lock().unwrap().push_back(audio_thread::Message::PlayLoop(audio_loader::SoundId::Rainforest));
+  }
 
-          let mut recorded = record_book::thread_local::clone();
-          recorded.chunk_loads.sort_by(|x, y| x.loaded_time_ns.cmp(&y.loaded_time_ns));
+  loop {
+    let client = connect_client(&listen_url, &server, spectate);
+    let session_lost = play(&client, &server, &view_updates0, &view_updates1, &audio_updates);
+    if !session_lost {
+      break
+    }
+
+    warn!("The server forgot our session; starting a new one.");
+    // These were meant for the old session's view.
+    view_updates0.lock().unwrap().clear();
+    view_updates1.lock().unwrap().clear();
+  }
 
-          let mut file = std::fs::File::create("chunk_loads.out").unwrap();
+  *quit.lock().unwrap() = true;
+  audio_thread.join();
+}
 
-          file.write_all(b"records = [").unwrap();
-          for (i, record) in recorded.chunk_loads.iter().enumerate() {
-            if i > 0 {
-              file.write_all(b", ").unwrap();
-            }
-            let record_book::ChunkLoad { time_requested_ns, response_time_ns, stored_time_ns, loaded_time_ns } = *record;
-            file.write_fmt(format_args!("[{}; {}; {}; {}]", time_requested_ns, response_time_ns, stored_time_ns, loaded_time_ns)).unwrap();
+/// Play as `client` until we quit, or the server forgets our session.
+/// Returns true if the session was lost, and a new one should be started.
+fn play(
+  client        : &client::T,
+  server        : &server::T,
+  view_updates0 : &Mutex<VecDeque<view::update::T>>,
+  view_updates1 : &Mutex<VecDeque<view::update::T>>,
+  audio_updates : &Mutex<VecDeque<audio_thread::Message>>,
+) -> bool {
+  let session_over = Mutex::new(false);
+  let session_over = &session_over;
+
+  let monitor_thread = {
+    unsafe {
+      thread_scoped::scoped(|| {
+        while !*session_over.lock().unwrap() {
+          info!("Outstanding terrain requests: {}", *client.pending_terrain_requests.lock().unwrap());
+          info!("Outstanding voxel updates: {}", client.terrain.lock().unwrap().queued_update_count());
+          info!("Outstanding view0 updates: {}", view_updates0.lock().unwrap().len());
+          info!("Outstanding view1 updates: {}", view_updates1.lock().unwrap().len());
+          std::thread::sleep(std::time::Duration::from_secs(1));
+        }
+      })
+    }
+  };
+
+  let update_thread = {
+    let server = server.clone();
+    unsafe {
+      thread_scoped::scoped(move || {
+        update_thread(
+          session_over,
+          client,
+          &mut || { server.listen.try() },
+          &mut |up| { view_updates0.lock().unwrap().push_back(up) },
+          &mut |up| { view_updates1.lock().unwrap().push_back(up) },
+          &mut |up| { audio_updates.lock().unwrap().push_back(up) },
+          &mut |up| { server.talk.tell(&up) },
+          &mut |msg| {
+            match msg {
+              terrain::Load::Voxels { time_requested: None, .. } => {},
+              terrain::Load::Voxels { time_requested: Some(_), .. } => {
+                // This may answer a request we stopped counting in `retry_terrain_requests`.
+                let mut pending = client.pending_terrain_requests.lock().unwrap();
+                *pending = pending.saturating_sub(1);
+              }
+            };
+            client.terrain.lock().unwrap().enqueue(msg);
+          },
+        );
+
+        let mut recorded = record_book::thread_local::clone();
+        recorded.chunk_loads.sort_by(|x, y| x.loaded_time_ns.cmp(&y.loaded_time_ns));
+
+        let mut file = std::fs::File::create("chunk_loads.out").unwrap();
+
+        file.write_all(b"records = [").unwrap();
+        for (i, record) in recorded.chunk_loads.iter().enumerate() {
+          if i > 0 {
+            file.write_all(b", ").unwrap();
           }
-          file.write_all(b"];\n").unwrap();
-          file.write_fmt(format_args!("plot([1:{}], records);", recorded.chunk_loads.len())).unwrap();
-
-          stopwatch::clone()
-        })
-      }
-    };
-
-    {
-      let client = &client;
-      let server = server.clone();
-      view_thread(
-        client,
-        &mut || { view_updates0.lock().unwrap().pop_front() },
-        &mut || { view_updates1.lock().unwrap().pop_front() },
-        &mut |server_update| { server.talk.tell(&server_update) },
-      );
-
-      stopwatch::clone().print();
+          let record_book::ChunkLoad { time_requested_ns, response_time_ns, stored_time_ns, loaded_time_ns } = *record;
+          file.write_fmt(format_args!("[{}; {}; {}; {}]", time_requested_ns, response_time_ns, stored_time_ns, loaded_time_ns)).unwrap();
+        }
+        file.write_all(b"];\n").unwrap();
+        file.write_fmt(format_args!("plot([1:{}], records);", recorded.chunk_loads.len())).unwrap();
+
+        stopwatch::clone()
+      })
     }
+  };
+
+  {
+    let server = server.clone();
+    view_thread(
+      client,
+      &mut || { view_updates0.lock().unwrap().pop_front() },
+      &mut || { view_updates1.lock().unwrap().pop_front() },
+      &mut |server_update| { server.talk.tell(&server_update) },
+    );
+
+    stopwatch::clone().print();
+  }
 
-    // View thread returned, so we got a quit event.
-    *quit.lock().unwrap() = true;
+  // View thread returned, so either we got a quit event, or the session was lost.
+  let session_lost = *client.session_lost.lock().unwrap();
+  if !session_lost {
+    // We're leaving on purpose, so don't try to reattach when the server hangs up.
+    *server.session.lock().unwrap() = None;
+    server.talk.tell(&protocol::ClientToServer::Leave(client.id));
+  }
+  *session_over.lock().unwrap() = true;
 
-    audio_thread.join();
-    monitor_thread.join();
+  monitor_thread.join();
 
-    let stopwatch = update_thread.join();
+  let stopwatch = update_thread.join();
 
-    stopwatch.print();
-  }
+  stopwatch.print();
+
+  session_lost
 }
 
-fn connect_client(listen_url: &str, server: &server::T) -> client::T {
+fn connect_client(listen_url: &str, server: &server::T, spectate: bool) -> client::T {
   // TODO: Consider using RPCs to solidify the request-response patterns.
-  server.talk.tell(&protocol::ClientToServer::Init(listen_url.to_owned()));
+  server.talk.tell(
+    &protocol::ClientToServer::Init {
+      version  : protocol::VERSION,
+      url      : listen_url.to_owned(),
+      features : server::FEATURES.iter().map(|&f| f.to_owned()).collect(),
+    }
+  );
   loop {
     match server.listen.wait() {
-      protocol::ServerToClient::LeaseId(client_id) => {
+      protocol::ServerToClient::InitRejected(rejection) => {
+        panic!("Server rejected connection: {:?}", rejection);
+      },
+      protocol::ServerToClient::LeaseId(client_id, token) => {
+        *server.session.lock().unwrap() = Some((client_id, token));
+        if spectate {
+          let position = Point3::from(SPECTATOR_START);
+          server.talk.tell(&protocol::ClientToServer::MoveCamera(client_id, position));
+          return client::new(client_id, None, position);
+        }
         server.talk.tell(&protocol::ClientToServer::AddPlayer(client_id));
         let client_id = client_id;
         loop {
           match server.listen.wait() {
             protocol::ServerToClient::PlayerAdded(player_id, position) => {
-              return client::new(client_id, player_id, position);
+              return client::new(client_id, Some(player_id), position);
             },
             msg => {
               // Ignore other messages in the meantime.
@@ -2,7 +2,13 @@
 
 use std;
 
-use common::socket::{SendSocket, ReceiveSocket};
+use common::protocol;
+use common::socket;
+
+/// The optional protocol features this client supports.
+pub const FEATURES: &'static [&'static str] = &[
+  protocol::feature::COMPRESSED_VOXELS,
+];
 
 #[allow(missing_docs)]
 pub mod send {
@@ -45,23 +51,60 @@ pub mod recv {
   use common::protocol;
 
   #[derive(Clone)]
-  pub struct T (pub std::sync::Arc<Receiver<Vec<u8>>>);
+  pub struct T {
+    pub receiver       : std::sync::Arc<Receiver<Vec<u8>>>,
+    pub bytes_received : std::sync::Arc<std::sync::Mutex<u64>>,
+  }
+
+  pub fn new(receiver: Receiver<Vec<u8>>) -> T {
+    T {
+      receiver       : std::sync::Arc::new(receiver),
+      bytes_received : std::sync::Arc::new(std::sync::Mutex::new(0)),
+    }
+  }
 
   impl T {
-    pub fn try(&self) -> Option<protocol::ServerToClient> {
-      match self.0.try_recv() {
-        Ok(msg) => Some(bincode::deserialize(&msg).unwrap()),
-        Err(TryRecvError::Empty) => None,
-        e => {
-          e.unwrap();
-          unreachable!();
+    fn deserialize(&self, msg: &[u8]) -> bincode::Result<protocol::ServerToClient> {
+      *self.bytes_received.lock().unwrap() += msg.len() as u64;
+      bincode::deserialize(msg)
+    }
+
+    /// Decode `msg`, or log and drop it if it's malformed.
+    fn decode(&self, msg: &[u8]) -> Option<protocol::ServerToClient> {
+      match self.deserialize(msg) {
+        Ok(msg) => Some(msg),
+        Err(err) => {
+          warn!("Dropping malformed message from server ({} bytes): {:?}", msg.len(), err);
+          None
         },
       }
     }
 
+    pub fn try(&self) -> Option<protocol::ServerToClient> {
+      loop {
+        match self.receiver.try_recv() {
+          Ok(msg) =>
+            match self.decode(&msg) {
+              None => {},
+              msg => return msg,
+            },
+          Err(TryRecvError::Empty) => return None,
+          e => {
+            e.unwrap();
+            unreachable!();
+          },
+        }
+      }
+    }
+
     pub fn wait(&self) -> protocol::ServerToClient {
-      let msg = self.0.recv().unwrap();
-      bincode::deserialize(msg.as_ref()).unwrap()
+      loop {
+        let msg = self.receiver.recv().unwrap();
+        match self.decode(msg.as_ref()) {
+          None => {},
+          Some(msg) => return msg,
+        }
+      }
     }
   }
 }
@@ -69,12 +112,85 @@ pub mod recv {
 #[allow(missing_docs)]
 #[derive(Clone)]
 pub struct T {
-  pub talk   : send::T,
-  pub listen : recv::T,
+  pub talk    : send::T,
+  pub listen  : recv::T,
+  /// The id and token the server leased us, once it has. While this is set, a dropped
+  /// connection is reopened and the session `Reattach`ed instead of giving up.
+  pub session : std::sync::Arc<std::sync::Mutex<Option<(protocol::ClientId, protocol::SessionToken)>>>,
 }
 
 unsafe impl Send for T {}
 
+const SOCKET_TIMEOUT_SECS: u64 = 30;
+
+fn connect(server_url: &str, listen_url: &str) -> std::io::Result<(socket::SendSocket, socket::ReceiveSocket)> {
+  socket::connect(server_url, listen_url, Some(std::time::Duration::from_secs(SOCKET_TIMEOUT_SECS)))
+}
+
+enum Reattach {
+  /// The server took us back. Here's the new connection, and the first message that came over it.
+  Accepted(socket::SendSocket, socket::ReceiveSocket, Vec<u8>),
+  /// The server has forgotten our session. Here's a fresh connection, and the rejection.
+  Rejected(socket::SendSocket, socket::ReceiveSocket, Vec<u8>),
+}
+
+/// Reconnect to the server and ask for our old session back. Keeps trying until the server
+/// would have given up on us.
+fn reattach(
+  server_url : &str,
+  listen_url : &str,
+  client_id  : protocol::ClientId,
+  token      : protocol::SessionToken,
+) -> Option<Reattach> {
+  use bincode;
+  use bincode::serialize;
+
+  let msg =
+    protocol::ClientToServer::Reattach {
+      client_id : client_id,
+      token     : token,
+      url       : listen_url.to_owned(),
+    };
+  let msg = serialize(&msg, bincode::Infinite).unwrap();
+
+  let give_up = std::time::Instant::now() + std::time::Duration::from_secs(protocol::REATTACH_GRACE_SECS);
+  while std::time::Instant::now() < give_up {
+    match connect(server_url, listen_url) {
+      Err(err) => debug!("Error reconnecting to {}: {:?}", server_url, err),
+      Ok((mut talk_socket, mut listen_socket)) => {
+        match talk_socket.write(msg.as_ref()) {
+          Err(err) => debug!("Error reattaching to {}: {:?}", server_url, err),
+          Ok(()) => {
+            match listen_socket.read() {
+              None => debug!("No answer to reattaching to {}", server_url),
+              Some(answer) => {
+                let is_rejected =
+                  match bincode::deserialize(&answer) {
+                    Ok(protocol::ServerToClient::ReattachRejected) => true,
+                    _ => false,
+                  };
+                if !is_rejected {
+                  return Some(Reattach::Accepted(talk_socket, listen_socket, answer))
+                }
+                // The server hangs up after rejecting us, so start over on a new connection.
+                drop(talk_socket);
+                drop(listen_socket);
+                match connect(server_url, listen_url) {
+                  Err(err) => debug!("Error reconnecting to {}: {:?}", server_url, err),
+                  Ok((talk_socket, listen_socket)) => return Some(Reattach::Rejected(talk_socket, listen_socket, answer)),
+                }
+              },
+            }
+          },
+        }
+      },
+    }
+    std::thread::sleep(std::time::Duration::from_secs(1));
+  }
+
+  None
+}
+
 #[allow(missing_docs)]
 pub fn new(
   server_url: &str,
@@ -83,40 +199,67 @@ pub fn new(
   let (send_send, send_recv) = std::sync::mpsc::channel();
   let (recv_send, recv_recv) = std::sync::mpsc::channel();
 
+  let (talk_socket, mut listen_socket) = connect(server_url, listen_url).unwrap();
+  let talk_socket = std::sync::Arc::new(std::sync::Mutex::new(talk_socket));
+  let session = std::sync::Arc::new(std::sync::Mutex::new(None));
+
   let _recv_thread ={
-    let listen_url = listen_url.to_owned();
     let recv_send = recv_send.clone();
+    let talk_socket = talk_socket.clone();
+    let session = session.clone();
+    let server_url = server_url.to_owned();
+    let listen_url = listen_url.to_owned();
     std::thread::spawn(move || {
-      let mut listen_socket =
-        ReceiveSocket::new(
-          listen_url.clone().as_ref(),
-          Some(std::time::Duration::from_secs(30)),
-        );
       loop {
         match listen_socket.read() {
-          None => break,
           Some(msg) => {
             recv_send.send(msg).unwrap()
           },
+          None => {
+            let (client_id, token) =
+              match *session.lock().unwrap() {
+                None => break,
+                Some(session) => session,
+              };
+            warn!("Lost connection to {}; trying to reattach", server_url);
+            // Free up `listen_url` before binding it again.
+            drop(listen_socket);
+            match reattach(&server_url, &listen_url, client_id, token) {
+              None => {
+                warn!("Couldn't reattach to {}", server_url);
+                break
+              },
+              Some(Reattach::Accepted(new_talk_socket, new_listen_socket, answer)) => {
+                *talk_socket.lock().unwrap() = new_talk_socket;
+                listen_socket = new_listen_socket;
+                recv_send.send(answer).unwrap();
+              },
+              Some(Reattach::Rejected(new_talk_socket, new_listen_socket, rejection)) => {
+                // Whoever handles the rejection starts a new session, with a fresh `Init`.
+                *session.lock().unwrap() = None;
+                *talk_socket.lock().unwrap() = new_talk_socket;
+                listen_socket = new_listen_socket;
+                recv_send.send(rejection).unwrap();
+              },
+            }
+          },
         }
       }
     })
   };
 
   let _send_thread = {
-    let server_url = server_url.to_owned();
     std::thread::spawn(move || {
-      let mut talk_socket =
-        SendSocket::new(
-          server_url.as_ref(),
-          Some(std::time::Duration::from_secs(30)),
-        );
       loop {
         match send_recv.recv() {
           Err(_) => break,
           Ok(msg) => {
             let msg: Vec<u8> = msg;
-            talk_socket.write(msg.as_ref()).unwrap();
+            match talk_socket.lock().unwrap().write(msg.as_ref()) {
+              Ok(()) => {},
+              // If the connection's gone, the recv thread will reattach.
+              Err(err) => warn!("Error sending to server: {:?}", err),
+            }
           },
         }
       }
@@ -124,7 +267,8 @@ pub fn new(
   };
 
   T {
-    talk: send::new(send_send),
-    listen: recv::T (std::sync::Arc::new(recv_recv)),
+    talk    : send::new(send_send),
+    listen  : recv::new(recv_recv),
+    session : session,
   }
 }
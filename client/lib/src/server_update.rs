@@ -1,14 +1,16 @@
 #![allow(missing_docs)]
 
-use cgmath;
-use cgmath::{Point3, EuclideanSpace, InnerSpace, ElementWise};
+use cgmath::{Point3, InnerSpace};
 use collision::{Aabb3};
 use rand::Rng;
 use stopwatch;
 use time;
 
 use common::color::Color4;
+use common::mob_kind;
 use common::protocol;
+use common::voxel;
+use common::voxel_encoding;
 
 use audio_loader;
 use audio_thread;
@@ -37,36 +39,100 @@ pub fn apply_server_update<UpdateView, UpdateAudio, UpdateServer, EnqueueTerrain
 {
   stopwatch::time("apply_server_update", move || {
     match update {
-      protocol::ServerToClient::LeaseId(_) => {
+      protocol::ServerToClient::InitRejected(rejection) => {
+        warn!("Unexpected InitRejected event: {:?}.", rejection);
+      },
+      protocol::ServerToClient::LeaseId(_, _) => {
         warn!("Client ID has already been leased.");
       },
+      protocol::ServerToClient::Reattached => {
+        info!("Reattached to the server.");
+        client.retry_terrain_requests();
+      },
+      protocol::ServerToClient::ReattachRejected => {
+        warn!("Server rejected our reattach; our session has expired.");
+        *client.session_lost.lock().unwrap() = true;
+      },
       protocol::ServerToClient::Ping => {
         update_server(protocol::ClientToServer::Ping(client.id));
       },
       protocol::ServerToClient::PlayerAdded(id, _) => {
         warn!("Unexpected PlayerAdded event: {:?}.", id);
       },
-      protocol::ServerToClient::UpdatePlayer(player_id, bounds) => {
-        let mesh = to_triangles(&bounds, &Color4::of_rgba(0.0, 0.0, 1.0, 1.0));
-        update_view(view::update::UpdatePlayer(player_id, mesh));
-
-        // We "lock" the client to client.player_id, so for updates to that player only,
-        // there is more client-specific logic.
-        if player_id != client.player_id {
+      protocol::ServerToClient::PlayerRemoved(id) => {
+        if Some(id) == client.player_id {
+          warn!("Our own player {:?} was removed.", id);
+        }
+        client.interpolation.lock().unwrap().players.remove(id);
+        update_view(view::update::RemovePlayer(id));
+      },
+      protocol::ServerToClient::UpdateHealth(id, health) => {
+        if Some(id) != client.player_id {
+          warn!("Got the health of someone else's player {:?}.", id);
+          return
+        }
+        update_view(view::update::SetHealth(health));
+      },
+      protocol::ServerToClient::PlayerDied(id) => {
+        if Some(id) != client.player_id {
+          warn!("Got the death of someone else's player {:?}.", id);
+          return
+        }
+        update_view(view::update::LogChat("You died.".to_string()));
+      },
+      protocol::ServerToClient::UpdateInventory(id, inventory) => {
+        if Some(id) != client.player_id {
+          warn!("Got the inventory of someone else's player {:?}.", id);
           return
         }
+        update_view(view::update::SetInventory(inventory));
+      },
+      protocol::ServerToClient::WorldSnapshot(snapshot) => {
+        let (previous, current) =
+          match client.snapshots.lock().unwrap().apply(&snapshot) {
+            None => return,
+            Some(views) => views,
+          };
+        update_server(protocol::ClientToServer::AckSnapshot(client.id, snapshot.sequence));
 
-        let position =
-          (bounds.min.to_vec().mul_element_wise(cgmath::Vector3::new(0.5, 0.1, 0.5))) +
-          (bounds.max.to_vec().mul_element_wise(cgmath::Vector3::new(0.5, 0.9, 0.5)));
-        let position = Point3::from_vec(position);
+        let mut interpolation = client.interpolation.lock().unwrap();
+        interpolation.observe(snapshot.time, time::precise_time_ns());
+
+        // Our own player is drawn where we predict it to be.
+        for (&id, player) in current.players.iter() {
+          if Some(id) != client.player_id {
+            interpolation.players.push(id, snapshot.time, player.bounds.to_aabb());
+          }
+        }
+        for (&id, mob) in current.mobs.iter() {
+          interpolation.mobs.push(id, snapshot.time, mob.bounds.to_aabb());
+          interpolation.mob_kinds.insert(id, mob.kind);
+        }
 
-        *client.player_position.lock().unwrap() = position;
-        update_view(view::update::MoveCamera(position));
+        for &id in previous.players.keys() {
+          if Some(id) != client.player_id && !current.players.contains_key(&id) {
+            interpolation.players.remove(id);
+            update_view(view::update::RemovePlayer(id));
+          }
+        }
+        for &id in previous.mobs.keys() {
+          if !current.mobs.contains_key(&id) {
+            interpolation.mobs.remove(id);
+            interpolation.mob_kinds.remove(&id);
+            update_view(view::update::RemoveMob(id));
+          }
+        }
       },
-      protocol::ServerToClient::UpdateMob(id, bounds) => {
-        let mesh = to_triangles(&bounds, &Color4::of_rgba(1.0, 0.0, 0.0, 1.0));
-        update_view(view::update::UpdateMob(id, mesh));
+      protocol::ServerToClient::AckInput { player, sequence, ticks_since, state, bounds } => {
+        if Some(player) != client.player_id {
+          warn!("Got an input acknowledgement for someone else's player {:?}.", player);
+          return
+        }
+
+        // The camera follows the prediction, which is moved along in the update thread.
+        let mut prediction = client.prediction.lock().unwrap();
+        let terrain = client.terrain.lock().unwrap();
+        prediction.reconcile(terrain.voxels(), sequence, ticks_since, state, bounds);
       },
       protocol::ServerToClient::UpdateSun(fraction) => {
         update_view(view::update::SetSun(
@@ -77,23 +143,16 @@ pub fn apply_server_update<UpdateView, UpdateAudio, UpdateServer, EnqueueTerrain
         ));
       },
       protocol::ServerToClient::Voxels { voxels, reason } => {
-        let time_requested;
-        match reason {
-          protocol::VoxelReason::Updated => {
-            time_requested = None;
-          },
-          protocol::VoxelReason::Requested { at } => {
-            time_requested = Some(at);
-            debug!("Receiving a voxel request after {}ns", time::precise_time_ns() - at);
-          },
+        enqueue_voxels(enqueue_terrain_load, voxels, reason);
+      },
+      protocol::ServerToClient::CompressedVoxels { voxels, reason } => {
+        match voxel_encoding::decode(&voxels) {
+          Err(err) => warn!("Dropping voxels that couldn't be decoded: {:?}", err),
+          Ok(voxels) => enqueue_voxels(enqueue_terrain_load, voxels, reason),
         }
-
-        enqueue_terrain_load(
-          terrain::Load::Voxels {
-            voxels       : voxels,
-            time_requested : time_requested,
-          }
-        );
+      },
+      protocol::ServerToClient::ChatMessage(player_id, text) => {
+        update_view(view::update::LogChat(format!("Player {}: {}", player_id.to_u32(), text)));
       },
       protocol::ServerToClient::Collision(collision_type) => {
         if let protocol::Collision::PlayerTerrain(..) = collision_type {
@@ -110,6 +169,42 @@ pub fn apply_server_update<UpdateView, UpdateAudio, UpdateServer, EnqueueTerrain
   })
 }
 
+fn enqueue_voxels<EnqueueTerrainLoad>(
+  enqueue_terrain_load : &mut EnqueueTerrainLoad,
+  voxels               : Vec<(voxel::bounds::T, voxel::T)>,
+  reason               : protocol::VoxelReason,
+) where
+  EnqueueTerrainLoad : FnMut(terrain::Load),
+{
+  let time_requested;
+  match reason {
+    protocol::VoxelReason::Updated => {
+      time_requested = None;
+    },
+    protocol::VoxelReason::Requested { at } => {
+      time_requested = Some(at);
+      debug!("Receiving a voxel request after {}ns", time::precise_time_ns() - at);
+    },
+  }
+
+  enqueue_terrain_load(
+    terrain::Load::Voxels {
+      voxels       : voxels,
+      time_requested : time_requested,
+    }
+  );
+}
+
+/// The mesh for a player with the given bounds.
+pub fn player_triangles(bounds: &Aabb3<f32>) -> [ColoredVertex; TRIANGLE_VERTICES_PER_BOX as usize] {
+  to_triangles(bounds, &Color4::of_rgba(0.0, 0.0, 1.0, 1.0))
+}
+
+/// The mesh for a mob of the given kind with the given bounds.
+pub fn mob_triangles(bounds: &Aabb3<f32>, kind: mob_kind::T) -> [ColoredVertex; TRIANGLE_VERTICES_PER_BOX as usize] {
+  to_triangles(bounds, &kind.color())
+}
+
 fn to_triangles(
   bounds: &Aabb3<f32>,
   c: &Color4<f32>,
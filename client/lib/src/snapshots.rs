@@ -0,0 +1,64 @@
+//! Rebuild the server's world snapshots from the deltas it sends.
+
+use std::collections::VecDeque;
+
+use common::snapshot;
+
+/// How many received views to keep around as possible baselines.
+const MAX_RECEIVED: usize = 64;
+
+#[allow(missing_docs)]
+pub struct T {
+  /// Views we've rebuilt, oldest first.
+  received : VecDeque<(snapshot::Sequence, snapshot::View)>,
+}
+
+#[allow(missing_docs)]
+pub fn new() -> T {
+  T {
+    received : VecDeque::new(),
+  }
+}
+
+impl T {
+  /// Rebuild the view a snapshot describes. Returns the previous latest view and the new one,
+  /// or None if the snapshot is out of date, or relative to a view we no longer have.
+  pub fn apply(&mut self, snapshot: &snapshot::WorldSnapshot) -> Option<(snapshot::View, snapshot::View)> {
+    let is_stale = self.received.back().map(|&(s, _)| snapshot.sequence <= s) == Some(true);
+    if is_stale {
+      return None
+    }
+
+    let view = {
+      let baseline =
+        match snapshot.baseline {
+          None => None,
+          Some(baseline) => {
+            match self.received.iter().find(|&&(s, _)| s == baseline) {
+              None => {
+                debug!("Dropping snapshot {} relative to unknown snapshot {}", snapshot.sequence, baseline);
+                return None
+              },
+              Some(&(_, ref view)) => Some(view),
+            }
+          },
+        };
+      match baseline {
+        None => snapshot::empty().apply(snapshot),
+        Some(baseline) => baseline.apply(snapshot),
+      }
+    };
+
+    let previous =
+      self.received.back()
+      .map(|&(_, ref view)| view.clone())
+      .unwrap_or_else(snapshot::empty);
+
+    self.received.push_back((snapshot.sequence, view.clone()));
+    if self.received.len() > MAX_RECEIVED {
+      self.received.pop_front();
+    }
+
+    Some((previous, view))
+  }
+}
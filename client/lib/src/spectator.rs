@@ -0,0 +1,79 @@
+//! A free-flying camera, for clients that watch the world without a player of their own.
+
+use cgmath;
+use cgmath::{Matrix3, Point3, Vector3};
+use std::f32::consts::PI;
+use time;
+
+use common::interval_timer::IntervalTimer;
+use common::movement;
+
+/// How far the camera flies per tick along each axis that has a key held.
+const SPEED: f32 = 1.0;
+
+#[allow(missing_docs)]
+pub struct T {
+  position          : Point3<f32>,
+  input             : movement::InputState,
+  /// rotation around the y-axis, in radians
+  lateral_rotation  : f32,
+  /// "pitch", in radians
+  vertical_rotation : f32,
+  timer             : IntervalTimer,
+}
+
+/// Start a camera at `position`.
+pub fn new(position: Point3<f32>) -> T {
+  let nanoseconds_per_second = 1000000000;
+  T {
+    position          : position,
+    input             : Default::default(),
+    lateral_rotation  : 0.0,
+    vertical_rotation : 0.0,
+    timer             :
+      IntervalTimer::new(
+        nanoseconds_per_second / movement::TICKS_PER_SECOND,
+        time::precise_time_ns(),
+      ),
+  }
+}
+
+impl T {
+  #[allow(missing_docs)]
+  pub fn position(&self) -> Point3<f32> {
+    self.position
+  }
+
+  /// Steer the camera with the same inputs a player would get.
+  /// Forward and back follow the camera's pitch, and jump flies straight up.
+  pub fn apply(&mut self, input: &movement::Input) {
+    match *input {
+      movement::Input::SetState(state) => self.input = state,
+      movement::Input::Rotate(r) => {
+        self.lateral_rotation += r.x;
+        let vertical_rotation = self.vertical_rotation + r.y;
+        if -PI / 2.0 <= vertical_rotation && vertical_rotation <= PI / 2.0 {
+          self.vertical_rotation = vertical_rotation;
+        }
+      },
+    }
+  }
+
+  /// Fly for any ticks that are due. Returns true if any were.
+  pub fn update(&mut self) -> bool {
+    let ticks = self.timer.update(time::precise_time_ns());
+    self.position += self.velocity() * ticks as f32;
+    ticks > 0
+  }
+
+  fn velocity(&self) -> Vector3<f32> {
+    let x_axis = Vector3::new(1.0, 0.0, 0.0);
+    let y_axis = Vector3::new(0.0, 1.0, 0.0);
+    let rotation =
+      Matrix3::from_axis_angle(y_axis, cgmath::Rad(self.lateral_rotation)) *
+      Matrix3::from_axis_angle(x_axis, cgmath::Rad(self.vertical_rotation));
+    let walk = rotation * (self.input.walk_accel() * (SPEED / movement::WALK_ACCEL));
+    let rise = if self.input.jump { SPEED } else { 0.0 };
+    walk + Vector3::new(0.0, rise, 0.0)
+  }
+}
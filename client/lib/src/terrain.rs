@@ -8,6 +8,7 @@ use time;
 
 use common::{fnv_set, fnv_map};
 use common::id_allocator;
+use common::protocol;
 use common::surroundings_loader;
 use common::voxel;
 
@@ -37,6 +38,10 @@ pub struct T {
   chunk_voxels_loaded : fnv_map::T<(chunk::position::T, lod::T), u32>,
   /// The voxels we have cached from the server.
   voxels              : voxel::tree::T,
+  /// The LOD each chunk's voxels were last requested at, and the region id they were requested under.
+  /// The server sends us updates to these regions.
+  subscriptions       : fnv_map::T<chunk::position::T, (lod::T, protocol::RegionId)>,
+  next_region         : protocol::RegionId,
   max_load_distance   : u32,
   queue               : std::collections::VecDeque<Load>,
 }
@@ -47,6 +52,8 @@ pub fn new(max_load_distance: u32) -> T {
     loaded_chunks       : fnv_map::new(),
     chunk_voxels_loaded : fnv_map::new(),
     voxels              : voxel::tree::new(),
+    subscriptions       : fnv_map::new(),
+    next_region         : 0,
     max_load_distance   : max_load_distance,
     queue               : std::collections::VecDeque::new(),
   }
@@ -60,6 +67,11 @@ impl T {
       .map(|&(_, lod)| lod)
   }
 
+  /// The voxels we have cached from the server.
+  pub fn voxels(&self) -> &voxel::tree::T {
+    &self.voxels
+  }
+
   /// get the count of queued messages
   pub fn queued_update_count(&self) -> usize {
     self.queue.len()
@@ -200,27 +212,41 @@ impl T {
       );
       Ok(())
     } else {
-      let voxel_size = 1 << lod.lg_sample_size();
-      let voxels =
-        terrain_mesh::voxels_in(
-          &collision::Aabb3::new(
-            cgmath::Point3::new(
-              (chunk_position.as_pnt().x << chunk::LG_WIDTH) - voxel_size,
-              (chunk_position.as_pnt().y << chunk::LG_WIDTH) - voxel_size,
-              (chunk_position.as_pnt().z << chunk::LG_WIDTH) - voxel_size,
-            ),
-            cgmath::Point3::new(
-              ((chunk_position.as_pnt().x + 1) << chunk::LG_WIDTH) + voxel_size,
-              ((chunk_position.as_pnt().y + 1) << chunk::LG_WIDTH) + voxel_size,
-              ((chunk_position.as_pnt().z + 1) << chunk::LG_WIDTH) + voxel_size,
-            ),
-          ),
-          lod.lg_sample_size(),
-        );
-      Err(voxels)
+      Err(chunk_voxels(chunk_position, lod))
     }
   }
 
+  /// Note that we're requesting a chunk's voxels, which subscribes us to updates in its region.
+  /// Returns the region id to request them under, and the id of any subscription this replaces
+  /// (i.e. at a different LOD).
+  pub fn subscribe(
+    &mut self,
+    chunk_position : &chunk::position::T,
+    lod            : lod::T,
+  ) -> (protocol::RegionId, Option<protocol::RegionId>) {
+    let replaced =
+      match self.subscriptions.get(chunk_position) {
+        Some(&(old_lod, id)) if old_lod == lod => return (id, None),
+        Some(&(_, id)) => Some(id),
+        None => None,
+      };
+    let id = self.next_region;
+    self.next_region += 1;
+    self.subscriptions.insert(*chunk_position, (lod, id));
+    (id, replaced)
+  }
+
+  /// Are we subscribed to updates for this chunk at this LOD?
+  pub fn is_subscribed(&self, chunk_position: &chunk::position::T, lod: lod::T) -> bool {
+    self.subscriptions.get(chunk_position).map(|&(l, _)| l) == Some(lod)
+  }
+
+  /// Forget a chunk's subscription, returning the region id to unsubscribe from.
+  pub fn unsubscribe(&mut self, chunk_position: &chunk::position::T) -> Option<protocol::RegionId> {
+    self.subscriptions.remove(chunk_position)
+      .map(|(_, id)| id)
+  }
+
   #[inline(never)]
   fn load_voxel<UpdateChunk>(
     &mut self,
@@ -379,6 +405,28 @@ impl T {
   }
 }
 
+/// The region a chunk needs voxels from (including a one-voxel border), at a given LOD.
+pub fn voxel_region(chunk_position: &chunk::position::T, lod: lod::T) -> collision::Aabb3<i32> {
+  let voxel_size = 1 << lod.lg_sample_size();
+  collision::Aabb3::new(
+    cgmath::Point3::new(
+      (chunk_position.as_pnt().x << chunk::LG_WIDTH) - voxel_size,
+      (chunk_position.as_pnt().y << chunk::LG_WIDTH) - voxel_size,
+      (chunk_position.as_pnt().z << chunk::LG_WIDTH) - voxel_size,
+    ),
+    cgmath::Point3::new(
+      ((chunk_position.as_pnt().x + 1) << chunk::LG_WIDTH) + voxel_size,
+      ((chunk_position.as_pnt().y + 1) << chunk::LG_WIDTH) + voxel_size,
+      ((chunk_position.as_pnt().z + 1) << chunk::LG_WIDTH) + voxel_size,
+    ),
+  )
+}
+
+/// All the voxels a chunk needs, at a given LOD.
+pub fn chunk_voxels(chunk_position: &chunk::position::T, lod: lod::T) -> Vec<voxel::bounds::T> {
+  terrain_mesh::voxels_in(&voxel_region(chunk_position, lod), lod.lg_sample_size())
+}
+
 #[inline(never)]
 fn updated_chunk_positions(
   voxel: &voxel::bounds::T,
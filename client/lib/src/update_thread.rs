@@ -4,6 +4,7 @@ use std::sync::Mutex;
 use stopwatch;
 use time;
 
+use common::interval_timer::IntervalTimer;
 use common::protocol;
 use common::surroundings_loader;
 use common::surroundings_loader::LoadType;
@@ -12,7 +13,10 @@ use audio_thread;
 use chunk;
 use chunk_stats;
 use client;
+use interpolation;
 use lod;
+use prediction;
+use server_update;
 use server_update::apply_server_update;
 use terrain;
 use view;
@@ -38,6 +42,7 @@ pub fn update_thread<RecvServer, UpdateView0, UpdateView1, UpdateAudio, UpdateSe
   EnqueueTerrainLoad : FnMut(terrain::Load),
 {
   let mut chunk_stats = chunk_stats::new();
+  let mut render_timer = IntervalTimer::new(interpolation::RENDER_INTERVAL_NS, time::precise_time_ns());
 
   'update_loop: loop {
     let should_quit = *quit.lock().unwrap();
@@ -49,6 +54,16 @@ pub fn update_thread<RecvServer, UpdateView0, UpdateView1, UpdateAudio, UpdateSe
           process_server_updates(client, recv_server, update_view0, update_audio, update_server, enqueue_terrain_load);
         });
 
+        stopwatch::time("predict_movement", || {
+          predict_movement(client, update_view0, update_server);
+        });
+
+        stopwatch::time("interpolate_entities", || {
+          if render_timer.update(time::precise_time_ns()) > 0 {
+            interpolate_entities(client, update_view0);
+          }
+        });
+
         stopwatch::time("update_surroundings", || {
           update_surroundings(client, &mut chunk_stats, update_view1, update_server);
         });
@@ -64,6 +79,101 @@ pub fn update_thread<RecvServer, UpdateView0, UpdateView1, UpdateAudio, UpdateSe
   chunk_stats.output_to("vram_chunk_loads.out");
 }
 
+/// Move our own player along, ahead of the server. Spectators fly their camera, and tell the server where it went.
+#[inline(never)]
+fn predict_movement<UpdateView, UpdateServer>(
+  client        : &client::T,
+  update_view   : &mut UpdateView,
+  update_server : &mut UpdateServer,
+) where
+  UpdateView   : FnMut(view::update::T),
+  UpdateServer : FnMut(protocol::ClientToServer),
+{
+  let player_id =
+    match client.player_id {
+      Some(player_id) => player_id,
+      None => {
+        fly_camera(client, update_view, update_server);
+        return
+      },
+    };
+
+  let bounds = {
+    let mut prediction = client.prediction.lock().unwrap();
+    let terrain = client.terrain.lock().unwrap();
+    if !prediction.update(terrain.voxels()) {
+      return
+    }
+    prediction.bounds()
+  };
+
+  update_view(view::update::UpdatePlayer(player_id, server_update::player_triangles(&bounds)));
+
+  let position = prediction::eye_position(&bounds);
+  *client.player_position.lock().unwrap() = position;
+  update_view(view::update::MoveCamera(position));
+}
+
+/// Move a spectator's camera along, and tell the server where it went.
+fn fly_camera<UpdateView, UpdateServer>(
+  client        : &client::T,
+  update_view   : &mut UpdateView,
+  update_server : &mut UpdateServer,
+) where
+  UpdateView   : FnMut(view::update::T),
+  UpdateServer : FnMut(protocol::ClientToServer),
+{
+  let (before, after) =
+    match client.spectator {
+      None => return,
+      Some(ref spectator) => {
+        let mut spectator = spectator.lock().unwrap();
+        let before = spectator.position();
+        if !spectator.update() {
+          return
+        }
+        (before, spectator.position())
+      },
+    };
+
+  *client.player_position.lock().unwrap() = after;
+  update_view(view::update::MoveCamera(after));
+  if after != before {
+    update_server(protocol::ClientToServer::MoveCamera(client.id, after));
+  }
+}
+
+/// Redraw other players and mobs where they were a moment ago, according to the server.
+#[inline(never)]
+fn interpolate_entities<UpdateView>(
+  client      : &client::T,
+  update_view : &mut UpdateView,
+) where
+  UpdateView: FnMut(view::update::T),
+{
+  let (players, mobs) = {
+    let mut interpolation = client.interpolation.lock().unwrap();
+    let interpolation = &mut *interpolation;
+    let at =
+      match interpolation.render_time(time::precise_time_ns()) {
+        None => return,
+        Some(at) => at,
+      };
+    let mobs: Vec<_> =
+      interpolation.mobs.sample(at).into_iter()
+      .filter_map(|(id, bounds)| interpolation.mob_kinds.get(&id).map(|&kind| (id, bounds, kind)))
+      .collect();
+    (interpolation.players.sample(at), mobs)
+  };
+
+  for (id, bounds) in players {
+    update_view(view::update::UpdatePlayer(id, server_update::player_triangles(&bounds)));
+  }
+  for (id, bounds, kind) in mobs {
+    update_view(view::update::UpdateMob(id, server_update::mob_triangles(&bounds, kind)));
+  }
+}
+
 #[inline(never)]
 fn update_surroundings<UpdateView, UpdateServer>(
   client        : &client::T,
@@ -132,7 +242,16 @@ fn update_surroundings<UpdateView, UpdateServer>(
       },
       LoadType::Unload => {
         stopwatch::time("update_thread.unload", || {
-          client.terrain.lock().unwrap().unload(update_view, &chunk_position);
+          let mut terrain = client.terrain.lock().unwrap();
+          terrain.unload(update_view, &chunk_position);
+          terrain.unsubscribe(&chunk_position).map(|region| {
+            update_server(
+              protocol::ClientToServer::UnsubscribeVoxels {
+                client_id : client.id,
+                region    : region,
+              }
+            );
+          });
         })
       },
     };
@@ -170,19 +289,36 @@ fn load_or_request_chunk<UpdateServer, UpdateView>(
       chunk_position,
       lod,
     );
-  match r {
-    Ok(()) => {},
-    Err(voxels) => {
-      update_server(
-        protocol::ClientToServer::RequestVoxels {
-          time_requested_ns : time::precise_time_ns(),
-          client_id       : client.id,
-          voxels          : voxels,
+  let voxels =
+    match r {
+      Ok(()) => {
+        if terrain.is_subscribed(chunk_position, lod) {
+          return
         }
-      );
-      *client.pending_terrain_requests.lock().unwrap() += 1;
-    },
-  }
+        // We drew this from our cache, but the server hasn't been keeping it up to date.
+        terrain::chunk_voxels(chunk_position, lod)
+      },
+      Err(voxels) => voxels,
+    };
+
+  let (region, replaced) = terrain.subscribe(chunk_position, lod);
+  replaced.map(|replaced| {
+    update_server(
+      protocol::ClientToServer::UnsubscribeVoxels {
+        client_id : client.id,
+        region    : replaced,
+      }
+    );
+  });
+  update_server(
+    protocol::ClientToServer::RequestVoxels {
+      time_requested_ns : time::precise_time_ns(),
+      client_id       : client.id,
+      voxels          : voxels,
+      region          : region,
+    }
+  );
+  *client.pending_terrain_requests.lock().unwrap() += 1;
 }
 
 #[inline(never)]
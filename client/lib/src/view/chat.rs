@@ -0,0 +1,154 @@
+//! The chat log, and the message being typed, drawn as text on the HUD.
+
+use cgmath::{Vector2};
+use std::collections::VecDeque;
+use yaglw::gl_context::GLContext;
+use yaglw::vertex_buffer::{GLArray};
+
+use common::protocol;
+
+use vertex::TextureVertex;
+use view;
+use view::text_line::{Line, MARGIN_PX, VERTICES_PER_LINE, draw_slot, render_line};
+use view::ttf;
+
+/// The number of received messages to show.
+pub const MAX_LINES: usize = 8;
+
+/// One slot per logged message, plus one for the message being typed.
+const SLOTS: usize = MAX_LINES + 1;
+const INPUT_SLOT: usize = MAX_LINES;
+
+#[allow(missing_docs)]
+pub struct T<'a> {
+  /// Received messages, oldest first.
+  log         : VecDeque<Line<'a>>,
+  /// The message being typed, if we're in chat input mode.
+  input       : Option<String>,
+  input_line  : Option<Line<'a>>,
+  quads       : GLArray<'a, TextureVertex>,
+  window_size : Vector2<i32>,
+  /// Whether `quads` needs to be rebuilt.
+  dirty       : bool,
+}
+
+#[allow(missing_docs)]
+pub fn new<'a, 'b>(
+  gl: &'b mut GLContext,
+  shader: &view::shaders::texture::T<'a>,
+  window_size: Vector2<i32>,
+) -> T<'a> where
+  'a: 'b,
+{
+  T {
+    log         : VecDeque::new(),
+    input       : None,
+    input_line  : None,
+    quads       : view::text_line::new_quads(gl, shader, SLOTS),
+    window_size : window_size,
+    dirty       : false,
+  }
+}
+
+impl<'a> T<'a> {
+  /// Add a message to the log, scrolling out the oldest one if it's full.
+  pub fn push<'b>(&mut self, gl: &'b GLContext, font: &ttf::Font, text: &str) where 'a: 'b {
+    if text.is_empty() {
+      return
+    }
+    self.log.push_back(render_line(gl, font, text));
+    if self.log.len() > MAX_LINES {
+      self.log.pop_front();
+    }
+    self.dirty = true;
+  }
+
+  #[allow(missing_docs)]
+  pub fn start_typing<'b>(&mut self, gl: &'b GLContext, font: &ttf::Font) where 'a: 'b {
+    self.input = Some(String::new());
+    self.update_input_line(gl, font);
+  }
+
+  /// Add some typed text to the message being typed, unless it would make the message too long to send.
+  pub fn type_text<'b>(&mut self, gl: &'b GLContext, font: &ttf::Font, text: &str) where 'a: 'b {
+    match self.input {
+      None => return,
+      Some(ref mut input) => {
+        if input.len() + text.len() > protocol::MAX_CHAT_MESSAGE_BYTES {
+          return
+        }
+        input.push_str(text);
+      },
+    }
+    self.update_input_line(gl, font);
+  }
+
+  /// Delete the last character of the message being typed.
+  pub fn backspace<'b>(&mut self, gl: &'b GLContext, font: &ttf::Font) where 'a: 'b {
+    match self.input {
+      None => return,
+      Some(ref mut input) => { input.pop(); },
+    }
+    self.update_input_line(gl, font);
+  }
+
+  /// Stop typing, and return what was typed.
+  pub fn finish_typing(&mut self) -> Option<String> {
+    self.input_line = None;
+    self.dirty = true;
+    self.input.take()
+  }
+
+  fn update_input_line<'b>(&mut self, gl: &'b GLContext, font: &ttf::Font) where 'a: 'b {
+    self.input_line =
+      self.input.as_ref().map(|input| render_line(gl, font, &format!("> {}", input)));
+    self.dirty = true;
+  }
+
+  fn quad(&self, bottom_px: i32, size: (i32, i32)) -> [TextureVertex; VERTICES_PER_LINE] {
+    // Convert pixels to the HUD's coordinates, which go from -1 to 1 vertically.
+    let scale = 2.0 / self.window_size.y as f32;
+    let aspect = self.window_size.x as f32 / self.window_size.y as f32;
+    let x0 = -aspect + MARGIN_PX as f32 * scale;
+    let y0 = -1.0 + bottom_px as f32 * scale;
+    let x1 = x0 + size.0 as f32 * scale;
+    let y1 = y0 + size.1 as f32 * scale;
+    view::text_line::quad(x0, y0, x1, y1)
+  }
+
+  fn rebuild(&mut self, gl: &mut GLContext) {
+    self.quads.buffer.byte_buffer.bind(gl);
+
+    let mut bottom_px = MARGIN_PX;
+    if let Some(ref line) = self.input_line {
+      let quad = self.quad(bottom_px, line.size);
+      self.quads.buffer.update(gl, INPUT_SLOT * VERTICES_PER_LINE, &quad);
+      bottom_px += line.size.1;
+    }
+
+    // Newest messages go at the bottom.
+    for (slot, line) in self.log.iter().rev().enumerate() {
+      let quad = self.quad(bottom_px, line.size);
+      self.quads.buffer.update(gl, slot * VERTICES_PER_LINE, &quad);
+      bottom_px += line.size.1;
+    }
+
+    self.dirty = false;
+  }
+
+  /// Draw the log and the message being typed.
+  /// N.B. This does not bind any shaders.
+  pub fn draw(&mut self, gl: &mut GLContext) {
+    if self.dirty {
+      self.rebuild(gl);
+    }
+
+    self.quads.bind(gl);
+    for (slot, line) in self.log.iter().rev().enumerate() {
+      draw_slot(slot, line);
+    }
+    if let Some(ref line) = self.input_line {
+      draw_slot(INPUT_SLOT, line);
+    }
+  }
+}
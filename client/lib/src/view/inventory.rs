@@ -0,0 +1,91 @@
+//! The inventory panel: how much of each material our player is carrying, drawn as text in the
+//! top right corner of the HUD.
+
+use cgmath::{Vector2};
+use yaglw::gl_context::GLContext;
+use yaglw::vertex_buffer::{GLArray};
+
+use common::inventory;
+use common::voxel;
+
+use vertex::TextureVertex;
+use view;
+use view::text_line::{Line, MARGIN_PX, VERTICES_PER_LINE, draw_slot, render_line};
+use view::ttf;
+
+#[allow(missing_docs)]
+pub struct T<'a> {
+  /// One line per material in `inventory::CARRIED`.
+  lines       : Vec<Line<'a>>,
+  quads       : GLArray<'a, TextureVertex>,
+  window_size : Vector2<i32>,
+  /// Whether `quads` needs to be rebuilt.
+  dirty       : bool,
+}
+
+#[allow(missing_docs)]
+pub fn new<'a, 'b>(
+  gl: &'b mut GLContext,
+  shader: &view::shaders::texture::T<'a>,
+  window_size: Vector2<i32>,
+) -> T<'a> where
+  'a: 'b,
+{
+  T {
+    lines       : Vec::new(),
+    quads       : view::text_line::new_quads(gl, shader, inventory::CARRIED.len()),
+    window_size : window_size,
+    dirty       : false,
+  }
+}
+
+impl<'a> T<'a> {
+  /// Show the contents of `inventory`.
+  pub fn set<'b>(&mut self, gl: &'b GLContext, font: &ttf::Font, inventory: &inventory::T) where 'a: 'b {
+    self.lines =
+      inventory::CARRIED.iter()
+      .map(|&material| {
+        let count = inventory.count(material) as f32 / voxel::UNITS_PER_VOXEL as f32;
+        render_line(gl, font, &format!("{:?}: {:.1}", material, count))
+      })
+      .collect();
+    self.dirty = true;
+  }
+
+  fn quad(&self, top_px: i32, size: (i32, i32)) -> [TextureVertex; VERTICES_PER_LINE] {
+    // Convert pixels to the HUD's coordinates, which go from -1 to 1 vertically.
+    let scale = 2.0 / self.window_size.y as f32;
+    let aspect = self.window_size.x as f32 / self.window_size.y as f32;
+    let x1 = aspect - MARGIN_PX as f32 * scale;
+    let y1 = 1.0 - top_px as f32 * scale;
+    let x0 = x1 - size.0 as f32 * scale;
+    let y0 = y1 - size.1 as f32 * scale;
+    view::text_line::quad(x0, y0, x1, y1)
+  }
+
+  fn rebuild(&mut self, gl: &mut GLContext) {
+    self.quads.buffer.byte_buffer.bind(gl);
+
+    let mut top_px = MARGIN_PX;
+    for (slot, line) in self.lines.iter().enumerate() {
+      let quad = self.quad(top_px, line.size);
+      self.quads.buffer.update(gl, slot * VERTICES_PER_LINE, &quad);
+      top_px += line.size.1;
+    }
+
+    self.dirty = false;
+  }
+
+  /// Draw the panel.
+  /// N.B. This does not bind any shaders.
+  pub fn draw(&mut self, gl: &mut GLContext) {
+    if self.dirty {
+      self.rebuild(gl);
+    }
+
+    self.quads.bind(gl);
+    for (slot, line) in self.lines.iter().enumerate() {
+      draw_slot(slot, line);
+    }
+  }
+}
@@ -1,16 +1,21 @@
 //! The state associated with perceiving the world state.
 
 mod camera;
+pub mod chat;
 pub mod chunked_terrain;
+pub mod fontloader;
 mod grass_buffers;
 pub mod entity;
+pub mod inventory;
 pub mod light;
 mod mob_buffers;
 mod player_buffers;
 mod render;
 pub mod shaders;
 pub mod terrain_buffers;
+pub mod text_line;
 pub mod thread;
+pub mod ttf;
 pub mod update;
 
 pub use self::render::render;
@@ -20,12 +25,14 @@ use gl;
 use gl::types::*;
 use image;
 use image::GenericImage;
+use sdl2::keyboard::Keycode;
 use std;
 use yaglw::gl_context::GLContext;
 use yaglw;
 use yaglw::vertex_buffer::{GLArray, GLBuffer, GLType, DrawMode, VertexAttribData};
 use yaglw::texture::{TextureUnit};
 
+use common::fnv_set;
 use common::id_allocator;
 use vertex::{ColoredVertex};
 
@@ -38,6 +45,8 @@ const VERTICES_PER_TRIANGLE: usize = 3;
 pub enum InputMode {
   Camera,
   Sun,
+  /// Typing a chat message.
+  Chat,
 }
 
 /// The state associated with perceiving the world state.
@@ -63,6 +72,12 @@ pub struct T<'a> {
   pub player_buffers: player_buffers::T<'a>,
   /// Hud triangles for non-text.
   pub hud_triangles: GLArray<'a, ColoredVertex>,
+  #[allow(missing_docs)]
+  pub fonts: fontloader::FontLoader,
+  /// The chat log and input line.
+  pub chat: chat::T<'a>,
+  /// What our player is carrying.
+  pub inventory: inventory::T<'a>,
 
   #[allow(missing_docs)]
   pub sun: light::Sun,
@@ -75,6 +90,8 @@ pub struct T<'a> {
 
   /// Whether to render HUD elements
   pub input_mode: InputMode,
+  /// Keys whose presses have been acted on, and whose releases should be too.
+  pub held_keys: fnv_set::T<Keycode>,
 
   /// Distance to near clip plane
   pub near_clip: f32,
@@ -167,6 +184,9 @@ pub fn new<'a>(
     )
   };
 
+  let chat = chat::new(&mut gl, &shaders.texture_shader, window_size);
+  let inventory = inventory::new(&mut gl, &shaders.texture_shader, window_size);
+
   let misc_texture_unit = texture_unit_alloc.allocate();
 
   unsafe {
@@ -221,6 +241,9 @@ pub fn new<'a>(
     mob_buffers: mob_buffers,
     player_buffers: player_buffers,
     hud_triangles: hud_triangles,
+    fonts: fontloader::FontLoader::new(),
+    chat: chat,
+    inventory: inventory,
 
     empty_gl_array: empty_gl_array,
     misc_texture_unit: misc_texture_unit,
@@ -246,6 +269,7 @@ pub fn new<'a>(
 
     show_hud: true,
     input_mode: InputMode::Camera,
+    held_keys: fnv_set::new(),
 
     near_clip: near_clip,
     far_clip: far_clip,
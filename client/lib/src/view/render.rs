@@ -82,6 +82,24 @@ fn draw_grass_billboards(
   rndr.grass_buffers.draw(&mut rndr.gl);
 }
 
+fn draw_text(
+  rndr: &mut view::T,
+) {
+  rndr.shaders.texture_shader.shader.use_shader(&mut rndr.gl);
+  let alpha_threshold_uniform =
+    rndr.shaders.texture_shader.shader.get_uniform_location("alpha_threshold");
+  unsafe {
+    gl::Disable(gl::DEPTH_TEST);
+    gl::Uniform1f(alpha_threshold_uniform, 0.01);
+    gl::ActiveTexture(rndr.misc_texture_unit.gl_id());
+  }
+  rndr.chat.draw(&mut rndr.gl);
+  rndr.inventory.draw(&mut rndr.gl);
+  unsafe {
+    gl::Enable(gl::DEPTH_TEST);
+  }
+}
+
 #[allow(missing_docs)]
 pub fn render(
   rndr: &mut view::T,
@@ -115,5 +133,7 @@ pub fn render(
     rndr.shaders.hud_color_shader.shader.use_shader(&mut rndr.gl);
     rndr.hud_triangles.bind(&mut rndr.gl);
     rndr.hud_triangles.draw(&mut rndr.gl);
+
+    draw_text(rndr);
   }
 }
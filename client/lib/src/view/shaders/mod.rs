@@ -65,7 +65,7 @@ pub fn new<'a, 'b>(gl: &'b mut GLContext, window_size: Vector2<i32>) -> T<'a> wh
   let terrain_shader       = self::terrain::new(gl);
   let mob_shader           = self::color::new(gl);
   let mut hud_color_shader = self::color::new(gl);
-  let texture_shader       = self::texture::new(gl);
+  let mut texture_shader   = self::texture::new(gl);
   let grass_billboard      = self::grass_billboard::new(gl);
   let sky                  = self::sky::new(gl);
 
@@ -82,6 +82,12 @@ pub fn new<'a, 'b>(gl: &'b mut GLContext, window_size: Vector2<i32>) -> T<'a> wh
     gl,
     &hud_camera,
   );
+  // The texture shader is only used for HUD text.
+  camera::set_camera(
+    &mut texture_shader.shader,
+    gl,
+    &hud_camera,
+  );
 
   match gl.get_error() {
     gl::NO_ERROR => {},
@@ -0,0 +1,87 @@
+//! Lines of text drawn on the HUD, each as one textured quad in a shared vertex array.
+
+use cgmath::{Point3, Vector2};
+use gl;
+use yaglw::gl_context::GLContext;
+use yaglw::texture::Texture2D;
+use yaglw::vertex_buffer::{GLArray, GLBuffer, GLType, DrawMode, VertexAttribData};
+
+use common::color::Color4;
+
+use vertex::TextureVertex;
+use view;
+use view::ttf;
+
+/// Each line of text is drawn as one textured quad.
+pub const VERTICES_PER_LINE: usize = 6;
+/// Distance from the edges of the screen, in pixels.
+pub const MARGIN_PX: i32 = 8;
+
+/// A line of text, rendered to a texture.
+pub struct Line<'a> {
+  texture  : Texture2D<'a>,
+  /// Size in pixels.
+  pub size : (i32, i32),
+}
+
+#[allow(missing_docs)]
+pub fn render_line<'a, 'b: 'a>(gl: &'a GLContext, font: &ttf::Font, text: &str) -> Line<'b> {
+  Line {
+    texture : font.render(gl, text, Color4::of_rgba(0xFF, 0xFF, 0xFF, 0xFF)),
+    size    : font.size_of(text),
+  }
+}
+
+/// A vertex array with room for `slots` lines, all empty to start with.
+pub fn new_quads<'a, 'b>(
+  gl: &'b mut GLContext,
+  shader: &view::shaders::texture::T<'a>,
+  slots: usize,
+) -> GLArray<'a, TextureVertex> where
+  'a: 'b,
+{
+  let buffer = GLBuffer::new(gl, slots * VERTICES_PER_LINE);
+  let mut quads =
+    GLArray::new(
+      gl,
+      &shader.shader,
+      &[
+        VertexAttribData { name: "position", size: 3, unit: GLType::Float, divisor: 0 },
+        VertexAttribData { name: "texture_position", size: 2, unit: GLType::Float, divisor: 0 },
+      ],
+      DrawMode::Triangles,
+      buffer,
+    );
+
+  let empty =
+    TextureVertex {
+      world_position   : Point3::new(0.0, 0.0, 0.0),
+      texture_position : Vector2::new(0.0, 0.0),
+    };
+  quads.buffer.byte_buffer.bind(gl);
+  quads.push(gl, &vec!(empty; slots * VERTICES_PER_LINE));
+  quads
+}
+
+/// The quad for a line of text with corners (x0, y0) and (x1, y1), in HUD coordinates.
+pub fn quad(x0: f32, y0: f32, x1: f32, y1: f32) -> [TextureVertex; VERTICES_PER_LINE] {
+  // Text textures are stored top row first.
+  let vtx = |x, y, u, v| {
+    TextureVertex {
+      world_position   : Point3::new(x, y, 0.0),
+      texture_position : Vector2::new(u, v),
+    }
+  };
+  [
+    vtx(x0, y0, 0.0, 1.0), vtx(x1, y0, 1.0, 1.0), vtx(x1, y1, 1.0, 0.0),
+    vtx(x0, y0, 0.0, 1.0), vtx(x1, y1, 1.0, 0.0), vtx(x0, y1, 0.0, 0.0),
+  ]
+}
+
+/// Draw `line` with the quad in slot `slot` of the bound vertex array.
+pub fn draw_slot(slot: usize, line: &Line) {
+  unsafe {
+    gl::BindTexture(gl::TEXTURE_2D, line.texture.handle.gl_id);
+    gl::DrawArrays(gl::TRIANGLES, (slot * VERTICES_PER_LINE) as i32, VERTICES_PER_LINE as i32);
+  }
+}
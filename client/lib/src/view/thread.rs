@@ -110,6 +110,11 @@ pub fn view_thread<Recv0, Recv1, UpdateServer>(
         }
         last_update = now;
 
+        if *client.session_lost.lock().unwrap() {
+          // Hand back to `run`, which will start a new session.
+          return ViewIteration::Quit
+        }
+
         event_pump.pump_events();
         let events: Vec<Event> = sdl_event.peek_events(1 << 6);
         sdl_event.flush_events(0, std::u32::MAX);
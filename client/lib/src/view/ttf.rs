@@ -99,6 +99,18 @@ impl Font {
     Font { p: p }
   }
 
+  /// The size, in pixels, that `txt` would be rendered at.
+  pub fn size_of(&self, txt: &str) -> (i32, i32) {
+    let c_str = CString::new(txt.as_bytes()).unwrap();
+    let ptr = c_str.as_ptr();
+    let mut w = 0;
+    let mut h = 0;
+    unsafe {
+      assert_eq!(ffi::TTF_SizeUTF8(self.p, ptr, &mut w, &mut h), 0);
+    }
+    (w as i32, h as i32)
+  }
+
   /// Color is rgba
   pub fn render<'a, 'b:'a>(
     &self,
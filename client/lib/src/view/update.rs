@@ -3,11 +3,13 @@
 use cgmath::Point3;
 use stopwatch;
 
+use hud;
 use terrain_mesh;
 use vertex::ColoredVertex;
 use view;
 
 use common::index;
+use common::inventory;
 
 use super::chunked_terrain;
 use super::entity;
@@ -22,11 +24,21 @@ pub enum T {
 
   /// Update a player mesh.
   UpdatePlayer(entity::id::Player, [ColoredVertex; VERTICES_PER_PLAYER]),
+  /// Remove a player mesh.
+  RemovePlayer(entity::id::Player),
   /// Update a mob mesh.
   UpdateMob(entity::id::Mob, [ColoredVertex; VERTICES_PER_MOB]),
+  /// Remove a mob mesh.
+  RemoveMob(entity::id::Mob),
 
   /// Update the sun.
   SetSun(light::Sun),
+  /// Add a line to the chat log.
+  LogChat(String),
+  /// Show our player's health on the HUD.
+  SetHealth(u32),
+  /// Show what our player is carrying on the HUD.
+  SetInventory(inventory::T),
 
   /// Add a terrain chunk to the view.
   LoadMesh (Box<chunked_terrain::T>),
@@ -49,9 +61,24 @@ pub fn apply_client_to_view(view: &mut view::T, up: T) {
     T::UpdateMob(id, triangles) => {
       view.mob_buffers.insert(&mut view.gl, id, &triangles);
     },
+    T::RemoveMob(id) => {
+      view.mob_buffers.remove(&mut view.gl, id);
+    },
     T::UpdatePlayer(id, triangles) => {
       view.player_buffers.insert(&mut view.gl, id, &triangles);
     },
+    T::RemovePlayer(id) => {
+      view.player_buffers.remove(&mut view.gl, id);
+    },
+    T::LogChat(text) => {
+      view.chat.push(&view.gl, &view.fonts.mono, &text);
+    },
+    T::SetHealth(health) => {
+      hud::set_health(view, health);
+    },
+    T::SetInventory(inventory) => {
+      view.inventory.set(&view.gl, &view.fonts.mono, &inventory);
+    },
     T::SetSun(sun) => {
       match view.input_mode {
         view::InputMode::Sun => {},
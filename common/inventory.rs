@@ -0,0 +1,109 @@
+//! The materials a player has dug up, and can build with.
+
+use voxel;
+
+/// The materials that can be carried, in the order clients show them.
+/// Trees grow out of the soil, and dig back up into it, so bark and leaves are carried as terrain.
+pub const CARRIED: [voxel::Material; 3] = [
+  voxel::Material::Terrain,
+  voxel::Material::Stone,
+  voxel::Material::Marble,
+];
+
+/// Where `material` is carried, or None if it can't be.
+fn slot(material: voxel::Material) -> Option<usize> {
+  let carried_as =
+    match material {
+      voxel::Material::Empty => return None,
+      voxel::Material::Bark | voxel::Material::Leaves => voxel::Material::Terrain,
+      material => material,
+    };
+  CARRIED.iter().position(|&m| m == carried_as)
+}
+
+/// How much of each material there is, in `voxel::UNITS_PER_VOXEL`ths of the smallest voxel.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub struct T {
+  counts: [u32; 3],
+}
+
+#[allow(missing_docs)]
+pub fn new() -> T {
+  Default::default()
+}
+
+impl T {
+  /// How much of `material` there is.
+  pub fn count(&self, material: voxel::Material) -> u32 {
+    slot(material).map(|i| self.counts[i]).unwrap_or(0)
+  }
+
+  /// Add `amount` of `material`. Materials that can't be carried are ignored.
+  pub fn add(&mut self, material: voxel::Material, amount: u32) {
+    match slot(material) {
+      None => {},
+      Some(i) => { self.counts[i] = self.counts[i].saturating_add(amount); },
+    }
+  }
+
+  /// Add everything in `gained`, then take away everything in `spent`.
+  /// Returns false, and changes nothing, if that would leave less than nothing of anything.
+  pub fn trade(&mut self, gained: &T, spent: &T) -> bool {
+    let mut counts = self.counts;
+    for i in 0 .. counts.len() {
+      let available = counts[i].saturating_add(gained.counts[i]);
+      if available < spent.counts[i] {
+        return false
+      }
+      counts[i] = available - spent.counts[i];
+    }
+    self.counts = counts;
+    true
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+
+  use voxel;
+
+  #[test]
+  fn building_spends_what_was_dug() {
+    let mut inventory = new();
+    let mut dug = new();
+    dug.add(voxel::Material::Stone, 10);
+    assert!(inventory.trade(&dug, &new()));
+
+    let mut built = new();
+    built.add(voxel::Material::Stone, 4);
+    assert!(inventory.trade(&new(), &built));
+    assert_eq!(inventory.count(voxel::Material::Stone), 6);
+  }
+
+  #[test]
+  fn unaffordable_trades_change_nothing() {
+    let mut inventory = new();
+    inventory.add(voxel::Material::Terrain, 3);
+    inventory.add(voxel::Material::Stone, 3);
+
+    let mut gained = new();
+    gained.add(voxel::Material::Terrain, 1);
+    let mut spent = new();
+    spent.add(voxel::Material::Terrain, 2);
+    spent.add(voxel::Material::Stone, 4);
+    assert!(!inventory.trade(&gained, &spent));
+    assert_eq!(inventory.count(voxel::Material::Terrain), 3);
+    assert_eq!(inventory.count(voxel::Material::Stone), 3);
+  }
+
+  #[test]
+  fn trees_are_carried_as_terrain() {
+    let mut inventory = new();
+    inventory.add(voxel::Material::Bark, 2);
+    inventory.add(voxel::Material::Leaves, 3);
+    inventory.add(voxel::Material::Empty, 5);
+    assert_eq!(inventory.count(voxel::Material::Terrain), 5);
+    assert_eq!(inventory.count(voxel::Material::Empty), 0);
+  }
+}
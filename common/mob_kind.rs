@@ -0,0 +1,22 @@
+//! The kinds of mob, as both the server and clients know them.
+
+use color::Color4;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[allow(missing_docs)]
+pub enum T {
+  Tag,
+  Grazer,
+  Lurker,
+}
+
+impl T {
+  /// What color clients draw this kind of mob.
+  pub fn color(&self) -> Color4<f32> {
+    match *self {
+      T::Tag    => Color4::of_rgba(1.0, 0.0, 0.0, 1.0),
+      T::Grazer => Color4::of_rgba(0.9, 0.8, 0.3, 1.0),
+      T::Lurker => Color4::of_rgba(0.4, 0.1, 0.5, 1.0),
+    }
+  }
+}
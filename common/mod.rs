@@ -8,11 +8,14 @@
 #![feature(test)]
 #![feature(unboxed_closures)]
 
+extern crate bincode;
 extern crate cgmath;
 extern crate collision;
+extern crate flate2;
 extern crate fnv;
 extern crate isosurface_extraction;
 extern crate log;
+#[cfg(feature = "nanomsg")]
 extern crate nanomsg;
 extern crate num;
 extern crate serde;
@@ -32,8 +35,13 @@ pub mod fnv_set;
 pub mod id_allocator;
 pub mod index;
 pub mod interval_timer;
+pub mod inventory;
+pub mod mob_kind;
+pub mod movement;
 pub mod protocol;
 pub mod range_abs;
+pub mod snapshot;
 pub mod socket;
 pub mod surroundings_loader;
 pub mod voxel;
+pub mod voxel_encoding;
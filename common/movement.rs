@@ -0,0 +1,649 @@
+//! Player movement rules, and the swept collision they're built on. The server uses these to simulate
+//! players and mobs, and clients use them to predict their own player's movement.
+
+use cgmath;
+use cgmath::{Matrix3, Point3, Vector2, Vector3, ElementWise, InnerSpace};
+use collision::{Aabb3};
+use std;
+use std::f32::consts::PI;
+
+use voxel;
+
+/// How many ticks a jump can keep accelerating for.
+pub const MAX_JUMP_FUEL: u32 = 4;
+/// The tallest obstacle that a player will automatically step up onto.
+pub const MAX_STEP_HEIGHT: f32 = 1.0;
+/// Extra upward acceleration while jumping.
+pub const JUMP_ACCEL: f32 = 0.3;
+/// How many times per second movement is simulated.
+pub const TICKS_PER_SECOND: u64 = 30;
+/// Walking acceleration along each axis that has a key held.
+pub const WALK_ACCEL: f32 = 0.1;
+/// The most a player can turn in a single tick, in radians, along each axis, however many `Rotate` inputs it sends.
+pub const MAX_ROTATION_PER_TICK: f32 = PI / 4.0;
+/// How many times a single move can be deflected along contact planes before it gives up.
+pub const MAX_SLIDES: usize = 4;
+/// Surfaces whose (unit) normal points at least this far up are ground that can be stood on, rather than
+/// slopes that are slid down. This allows slopes up to about 45 degrees.
+pub const MIN_GROUND_NORMAL_Y: f32 = 0.7;
+/// Bodies this close to touching are treated as touching, so rounding errors don't let them sink into each other.
+const CONTACT_EPSILON: f32 = 1e-4;
+
+fn clamp(x: f32, max: f32) -> f32 {
+  f32::max(-max, f32::min(max, x))
+}
+
+/// Whether a surface with this normal is flat enough to stand on.
+pub fn is_ground(normal: &Vector3<f32>) -> bool {
+  normal.y >= MIN_GROUND_NORMAL_Y
+}
+
+/// The movement controls a client is currently holding down.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[allow(missing_docs)]
+pub struct InputState {
+  pub forward : bool,
+  pub back    : bool,
+  pub left    : bool,
+  pub right   : bool,
+  pub jump    : bool,
+}
+
+impl InputState {
+  /// The walking acceleration these controls ask for. x/z units are relative to player facing.
+  pub fn walk_accel(&self) -> Vector3<f32> {
+    let axis = |negative: bool, positive: bool| {
+      match (negative, positive) {
+        (true, false) => -WALK_ACCEL,
+        (false, true) => WALK_ACCEL,
+        _ => 0.0,
+      }
+    };
+    Vector3::new(axis(self.left, self.right), 0.0, axis(self.forward, self.back))
+  }
+}
+
+/// A change to a player's movement, requested by a client.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub enum Input {
+  /// Replace the set of controls being held.
+  SetState(InputState),
+  /// Rotate the player by some amount (lateral, vertical).
+  Rotate(Vector2<f32>),
+}
+
+impl Input {
+  /// Pull this input back within the movement limits.
+  /// Returns None if it can't be salvaged (e.g. it has NaNs in it).
+  pub fn clamped(self) -> Option<Input> {
+    match self {
+      Input::SetState(_) => Some(self),
+      Input::Rotate(r) => {
+        if !(r.x.is_finite() && r.y.is_finite()) {
+          return None
+        }
+        Some(Input::Rotate(Vector2::new(
+          clamp(r.x, MAX_ROTATION_PER_TICK),
+          clamp(r.y, MAX_ROTATION_PER_TICK),
+        )))
+      },
+    }
+  }
+}
+
+/// Everything about a player's movement, other than where it is.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct State {
+  /// speed; units are world coordinates
+  pub speed: Vector3<f32>,
+  /// acceleration; units are world coordinates
+  pub accel: Vector3<f32>,
+  /// the controls being held, which determine the walking acceleration and jumping.
+  pub input: InputState,
+  /// this is depleted as we jump and replenished as we stand.
+  pub jump_fuel: u32,
+  /// are we currently trying to jump? (e.g. holding the key).
+  pub is_jumping: bool,
+  /// rotation around the y-axis, in radians
+  pub lateral_rotation: f32,
+  /// "pitch", in radians
+  pub vertical_rotation: f32,
+  /// How much further `Rotate` inputs can turn this tick, in radians (lateral, vertical).
+  pub rotation_budget: Vector2<f32>,
+}
+
+/// Something a moving body ran into.
+pub struct Collision<Obstacle> {
+  #[allow(missing_docs)]
+  pub obstacle: Obstacle,
+  /// The bounds of the obstacle.
+  pub bounds: Aabb3<f32>,
+  /// The normal of the obstacle face that was hit, pointing back at the body.
+  pub normal: Vector3<f32>,
+  /// Whether this is terrain, which can be stepped up onto.
+  pub is_terrain: bool,
+}
+
+/// What happened to a body over one tick of movement.
+pub struct Tick<Obstacle> {
+  /// Everything the body touched, in order.
+  pub obstacles: Vec<Obstacle>,
+  /// How fast the body was falling when it landed, or None if it didn't land this tick.
+  pub landed_at: Option<f32>,
+}
+
+/// The world, as far as moving a single body through it is concerned.
+pub trait Collider {
+  /// Identifies the things the body can run into.
+  type Obstacle;
+
+  /// The body's current bounds.
+  fn bounds(&self) -> Aabb3<f32>;
+
+  /// Move the body along `shift`, stopping where it first touches something. Returns what it touched.
+  /// `first_impact` does the work, given the obstacles near the path.
+  fn sweep(&mut self, shift: Vector3<f32>) -> Option<Collision<Self::Obstacle>>;
+}
+
+/// The smallest box containing `bounds` all the way along `shift`.
+pub fn swept_bounds(bounds: &Aabb3<f32>, shift: &Vector3<f32>) -> Aabb3<f32> {
+  let min = bounds.min + *shift;
+  let max = bounds.max + *shift;
+  Aabb3::new(
+    Point3::new(f32::min(bounds.min.x, min.x), f32::min(bounds.min.y, min.y), f32::min(bounds.min.z, min.z)),
+    Point3::new(f32::max(bounds.max.x, max.x), f32::max(bounds.max.y, max.y), f32::max(bounds.max.z, max.z)),
+  )
+}
+
+/// The solid part of an obstacle.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Shape {
+  /// The box the solid fits in.
+  pub bounds  : Aabb3<f32>,
+  /// If the solid is cut off by a surface running through `bounds`, a point on that surface and
+  /// the (unit) surface normal, which points out of the solid.
+  pub surface : Option<(Point3<f32>, Vector3<f32>)>,
+}
+
+impl Shape {
+  /// A solid box.
+  pub fn cuboid(bounds: Aabb3<f32>) -> Shape {
+    Shape {
+      bounds  : bounds,
+      surface : None,
+    }
+  }
+
+  /// The solid part of a voxel: nothing for empty space, the whole box for solid volumes, and
+  /// the part below the surface for voxels the terrain surface runs through.
+  pub fn of_voxel(bounds: &voxel::bounds::T, voxel: &voxel::T) -> Option<Shape> {
+    let (low, high) = bounds.corners();
+    let cuboid = Shape::cuboid(Aabb3::new(low, high));
+    match *voxel {
+      voxel::Volume(voxel::Material::Empty) => None,
+      voxel::Volume(_) => Some(cuboid),
+      voxel::Surface(ref surface) => {
+        let normal = surface.normal.to_float_normal();
+        if normal.magnitude2() == 0.0 {
+          return Some(cuboid)
+        }
+        Some(Shape {
+          surface : Some((surface.surface_vertex.to_world_vertex(bounds), normal.normalize())),
+          .. cuboid
+        })
+      },
+    }
+  }
+}
+
+/// How far along `shift` (from 0 to 1) `moving` gets before it touches `obstacle`, and the normal of the
+/// obstacle face it touches. Returns None if they never touch, or if they already overlap (so stuck bodies can get out).
+pub fn time_of_impact(
+  moving   : &Aabb3<f32>,
+  shift    : &Vector3<f32>,
+  obstacle : &Shape,
+) -> Option<(f32, Vector3<f32>)> {
+  let mut entry = std::f32::NEG_INFINITY;
+  let mut exit = std::f32::INFINITY;
+  let mut normal = Vector3::new(0.0, 0.0, 0.0);
+  for axis in 0 .. 3 {
+    let (low, high, d) = (moving.min[axis], moving.max[axis], shift[axis]);
+    let (obstacle_low, obstacle_high) = (obstacle.bounds.min[axis], obstacle.bounds.max[axis]);
+    if d == 0.0 {
+      if high <= obstacle_low + CONTACT_EPSILON || obstacle_high <= low + CONTACT_EPSILON {
+        return None
+      }
+      continue
+    }
+
+    // The distances to where the faces start and stop overlapping along this axis.
+    let (to_entry, to_exit) =
+      if d > 0.0 {
+        (obstacle_low - high, obstacle_high - low)
+      } else {
+        (low - obstacle_high, high - obstacle_low)
+      };
+    let (to_entry, to_exit) = (to_entry / d.abs(), to_exit / d.abs());
+    // Close enough counts as touching.
+    let to_entry = if to_entry < 0.0 && to_entry * d.abs() > -CONTACT_EPSILON { 0.0 } else { to_entry };
+    if to_entry > entry {
+      entry = to_entry;
+      normal = Vector3::new(0.0, 0.0, 0.0);
+      normal[axis] = -d.signum();
+    }
+    exit = f32::min(exit, to_exit);
+  }
+
+  if let Some((point, surface_normal)) = obstacle.surface {
+    // How far the corner of `moving` that reaches deepest into the solid is above the surface.
+    let deepest =
+      Point3::new(
+        if surface_normal.x > 0.0 { moving.min.x } else { moving.max.x },
+        if surface_normal.y > 0.0 { moving.min.y } else { moving.max.y },
+        if surface_normal.z > 0.0 { moving.min.z } else { moving.max.z },
+      );
+    let distance = (deepest - point).dot(surface_normal);
+    let approach = -shift.dot(surface_normal);
+    if approach == 0.0 {
+      if distance > -CONTACT_EPSILON {
+        return None
+      }
+    } else {
+      // Close enough counts as touching.
+      let distance = if distance < 0.0 && distance > -CONTACT_EPSILON { 0.0 } else { distance };
+      let to_surface = distance / approach;
+      if approach > 0.0 {
+        if to_surface > entry {
+          entry = to_surface;
+          normal = surface_normal;
+        }
+      } else {
+        exit = f32::min(exit, to_surface);
+      }
+    }
+  }
+
+  if entry < 0.0 || entry > 1.0 || entry >= exit {
+    None
+  } else {
+    Some((entry, normal))
+  }
+}
+
+/// The first of `obstacles` that `moving` touches on its way along `shift`, with the time and normal from `time_of_impact`,
+/// and the bounds of the obstacle. Ties go to the earliest obstacle.
+pub fn first_impact<Obstacle, Obstacles>(
+  moving    : &Aabb3<f32>,
+  shift     : &Vector3<f32>,
+  obstacles : Obstacles,
+) -> Option<(f32, Vector3<f32>, Aabb3<f32>, Obstacle)> where
+  Obstacles: IntoIterator<Item=(Shape, Obstacle)>,
+{
+  let mut first: Option<(f32, Vector3<f32>, Aabb3<f32>, Obstacle)> = None;
+  for (shape, obstacle) in obstacles {
+    match time_of_impact(moving, shift, &shape) {
+      None => {},
+      Some((t, normal)) => {
+        let is_first = first.as_ref().map(|&(first_t, _, _, _)| t < first_t).unwrap_or(true);
+        if is_first {
+          first = Some((t, normal, shape.bounds, obstacle));
+        }
+      },
+    }
+  }
+  first
+}
+
+/// Move a body along `shift`, sliding along whatever it touches, and stepping up onto terrain up to
+/// `max_step_height` high. Returns everything it touched, in order.
+pub fn slide<C: Collider>(
+  collider        : &mut C,
+  shift           : Vector3<f32>,
+  max_step_height : f32,
+) -> Vec<Collision<C::Obstacle>> {
+  let zero = Vector3::new(0.0, 0.0, 0.0);
+  let mut remaining = shift;
+  let mut collisions = Vec::new();
+  for _ in 0 .. MAX_SLIDES {
+    if remaining == zero {
+      break
+    }
+
+    let start = collider.bounds().min;
+    let collision =
+      match collider.sweep(remaining) {
+        None => break,
+        Some(collision) => collision,
+      };
+    remaining -= collider.bounds().min - start;
+
+    let mut collision = collision;
+    let is_wall = collision.is_terrain && collision.normal.y == 0.0;
+    if is_wall && step_up(collider, &collision.bounds, max_step_height) {
+      // We're standing on it now, and can carry on.
+      collision.normal = Vector3::new(0.0, 1.0, 0.0);
+    } else if is_ground(&collision.normal) {
+      // Keep walking the same way over the ground, following its slope, but don't slide down it.
+      let n = collision.normal;
+      remaining.y = -(n.x * remaining.x + n.z * remaining.z) / n.y;
+    } else {
+      // Keep whatever part of the move runs along the surface.
+      remaining -= collision.normal * remaining.dot(collision.normal);
+    }
+    collisions.push(collision);
+  }
+
+  collisions
+}
+
+/// Lift a body onto the top of `obstacle`, if that's low enough and there's room. Returns whether it did.
+fn step_up<C: Collider>(collider: &mut C, obstacle: &Aabb3<f32>, max_step_height: f32) -> bool {
+  let step_height = obstacle.max.y - collider.bounds().min.y;
+  if step_height <= 0.0 || step_height > max_step_height {
+    return false
+  }
+
+  let start = collider.bounds().min;
+  match collider.sweep(Vector3::new(0.0, step_height, 0.0)) {
+    None => true,
+    Some(_) => {
+      // No headroom; put it back.
+      let lifted = collider.bounds().min - start;
+      collider.sweep(-lifted);
+      false
+    },
+  }
+}
+
+#[allow(missing_docs)]
+pub fn new() -> State {
+  State {
+    speed             : Vector3::new(0.0, 0.0, 0.0),
+    accel             : Vector3::new(0.0, -0.1, 0.0),
+    input             : Default::default(),
+    jump_fuel         : 0,
+    is_jumping        : false,
+    lateral_rotation  : 0.0,
+    vertical_rotation : 0.0,
+    rotation_budget   : Vector2::new(MAX_ROTATION_PER_TICK, MAX_ROTATION_PER_TICK),
+  }
+}
+
+impl State {
+  /// Apply a client's input.
+  pub fn apply(&mut self, input: &Input) {
+    match *input {
+      Input::SetState(state) => self.set_input(state),
+      Input::Rotate(r) => {
+        let r = Vector2::new(clamp(r.x, self.rotation_budget.x), clamp(r.y, self.rotation_budget.y));
+        self.rotation_budget -= Vector2::new(r.x.abs(), r.y.abs());
+        self.rotate_lateral(r.x);
+        self.rotate_vertical(r.y);
+      },
+    }
+  }
+
+  /// Replace the held controls. Jumps start when the jump control is first pressed,
+  /// so the same state can be sent repeatedly without restarting a jump.
+  pub fn set_input(&mut self, input: InputState) {
+    if input.jump && !self.input.jump {
+      self.start_jump();
+    } else if !input.jump && self.input.jump {
+      self.stop_jump();
+    }
+    self.input = input;
+  }
+
+  /// Rotate the player around the y axis, by `r` radians. Positive is counterclockwise.
+  pub fn rotate_lateral(&mut self, r: f32) {
+    self.lateral_rotation = self.lateral_rotation + r;
+  }
+
+  /// Changes the player's pitch by `r` radians. Positive is up.
+  /// Angles that "flip around" (i.e. looking too far up or down)
+  /// are sliently rejected.
+  pub fn rotate_vertical(&mut self, r: f32) {
+    let new_rotation = self.vertical_rotation + r;
+
+    if new_rotation < -PI / 2.0
+    || new_rotation >  PI / 2.0 {
+      return
+    }
+
+    self.vertical_rotation = new_rotation;
+  }
+
+  /// [Try to] start a jump.
+  fn start_jump(&mut self) {
+    if !self.is_jumping {
+      self.is_jumping = true;
+      self.accel.y = self.accel.y + JUMP_ACCEL;
+    }
+  }
+
+  /// [Try to] stop a jump.
+  fn stop_jump(&mut self) {
+    if self.is_jumping {
+      self.is_jumping = false;
+      self.accel.y = self.accel.y - JUMP_ACCEL;
+    }
+  }
+
+  /// Simulate a single tick of movement. Returns the obstacles that were hit, and whether it landed.
+  pub fn tick<C: Collider>(&mut self, collider: &mut C) -> Tick<C::Obstacle> {
+    self.rotation_budget = Vector2::new(MAX_ROTATION_PER_TICK, MAX_ROTATION_PER_TICK);
+
+    if self.is_jumping {
+      if self.jump_fuel > 0 {
+        self.jump_fuel -= 1;
+      } else {
+        self.stop_jump();
+      }
+    }
+
+    let falling = self.speed.y < 0.0;
+    let fall_speed = -self.speed.y;
+    let mut landed = false;
+    let mut obstacles = Vec::new();
+    for collision in slide(collider, self.speed, MAX_STEP_HEIGHT) {
+      // Stop moving into whatever we hit.
+      let into = self.speed.dot(collision.normal);
+      if is_ground(&collision.normal) {
+        // Pushing back along a sloped normal would turn the fall into drift down the slope.
+        if into < 0.0 {
+          self.speed.y = f32::max(self.speed.y, 0.0);
+        }
+        landed = true;
+      } else if into < 0.0 {
+        self.speed -= collision.normal * into;
+      }
+      obstacles.push(collision.obstacle);
+    }
+    if falling {
+      self.jump_fuel = if landed { MAX_JUMP_FUEL } else { 0 };
+    }
+
+    let y_axis = Vector3::new(0.0, 1.0, 0.0);
+    let walk_v =
+        Matrix3::from_axis_angle(y_axis, cgmath::Rad(self.lateral_rotation))
+        * self.input.walk_accel();
+    self.speed += walk_v;
+    self.speed += self.accel;
+    // friction
+    self.speed.mul_assign_element_wise(Vector3::new(0.7, 0.99, 0.7 as f32));
+
+    Tick {
+      obstacles : obstacles,
+      landed_at : if falling && landed { Some(fall_speed) } else { None },
+    }
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+
+  #[test]
+  // Resending the same held controls shouldn't restart a jump that's run out of fuel.
+  fn repeated_jump_state_jumps_once() {
+    let mut state = new();
+    let held = InputState { jump: true, .. Default::default() };
+
+    state.set_input(held);
+    assert!(state.is_jumping);
+
+    state.stop_jump();
+    state.set_input(held);
+    assert!(!state.is_jumping);
+
+    state.set_input(Default::default());
+    state.set_input(held);
+    assert!(state.is_jumping);
+  }
+
+  #[test]
+  fn rotation_is_limited_per_tick() {
+    let mut state = new();
+    for _ in 0 .. 10 {
+      state.apply(&Input::Rotate(Vector2::new(MAX_ROTATION_PER_TICK, 0.0)));
+    }
+    assert_eq!(state.lateral_rotation, MAX_ROTATION_PER_TICK);
+
+    state.tick(&mut Boxes { body: aabb([0.0, 0.0, 0.0], [1.0, 1.0, 1.0]), obstacles: Vec::new() });
+    state.apply(&Input::Rotate(Vector2::new(-MAX_ROTATION_PER_TICK, 0.0)));
+    assert_eq!(state.lateral_rotation, 0.0);
+  }
+
+  #[test]
+  fn opposing_keys_cancel() {
+    let held = InputState { forward: true, back: true, right: true, .. Default::default() };
+    assert_eq!(held.walk_accel(), Vector3::new(WALK_ACCEL, 0.0, 0.0));
+  }
+
+  /// A body among some fixed terrain.
+  struct Boxes {
+    body      : Aabb3<f32>,
+    obstacles : Vec<Shape>,
+  }
+
+  impl Collider for Boxes {
+    type Obstacle = usize;
+
+    fn bounds(&self) -> Aabb3<f32> {
+      self.body
+    }
+
+    fn sweep(&mut self, shift: Vector3<f32>) -> Option<Collision<usize>> {
+      let obstacles = self.obstacles.iter().cloned().enumerate().map(|(i, shape)| (shape, i));
+      let hit = first_impact(&self.body, &shift, obstacles);
+      let t = hit.as_ref().map(|&(t, _, _, _)| t).unwrap_or(1.0);
+      self.body = Aabb3::new(self.body.min + shift * t, self.body.max + shift * t);
+      hit.map(|(_, normal, bounds, i)| {
+        Collision { obstacle: i, bounds: bounds, normal: normal, is_terrain: true }
+      })
+    }
+  }
+
+  fn aabb(min: [f32; 3], max: [f32; 3]) -> Aabb3<f32> {
+    Aabb3::new(Point3::from(min), Point3::from(max))
+  }
+
+  #[test]
+  fn fast_moves_dont_tunnel() {
+    let body = aabb([0.0, 0.0, 0.0], [1.0, 1.0, 1.0]);
+    let wall = Shape::cuboid(aabb([5.0, 0.0, 0.0], [5.1, 1.0, 1.0]));
+    let (t, normal) = time_of_impact(&body, &Vector3::new(10.0, 0.0, 0.0), &wall).unwrap();
+    assert_eq!(t, 0.4);
+    assert_eq!(normal, Vector3::new(-1.0, 0.0, 0.0));
+  }
+
+  #[test]
+  fn diagonal_moves_slide_along_the_floor() {
+    let mut boxes =
+      Boxes {
+        body      : aabb([0.0, 0.0, 0.0], [1.0, 2.0, 1.0]),
+        obstacles : vec!(Shape::cuboid(aabb([-10.0, -1.0, -10.0], [10.0, 0.0, 10.0]))),
+      };
+    let collisions = slide(&mut boxes, Vector3::new(1.0, -1.0, 1.0), 0.0);
+    assert_eq!(collisions.len(), 1);
+    assert_eq!(collisions[0].normal, Vector3::new(0.0, 1.0, 0.0));
+    assert_eq!(boxes.body.min, Point3::new(1.0, 0.0, 1.0));
+  }
+
+  #[test]
+  fn walks_up_low_steps() {
+    let mut boxes =
+      Boxes {
+        body      : aabb([0.0, 0.0, 0.0], [1.0, 2.0, 1.0]),
+        obstacles : vec!(
+          Shape::cuboid(aabb([-10.0, -1.0, -10.0], [10.0, 0.0, 10.0])),
+          Shape::cuboid(aabb([2.0, 0.0, -10.0], [10.0, 0.5, 10.0])),
+        ),
+      };
+    slide(&mut boxes, Vector3::new(2.0, 0.0, 0.0), MAX_STEP_HEIGHT);
+    assert_eq!(boxes.body.min, Point3::new(2.0, 0.5, 0.0));
+  }
+
+  #[test]
+  fn landing_reports_the_falling_speed() {
+    let mut boxes =
+      Boxes {
+        body      : aabb([0.0, 1.0, 0.0], [1.0, 3.0, 1.0]),
+        obstacles : vec!(Shape::cuboid(aabb([-10.0, -1.0, -10.0], [10.0, 0.0, 10.0]))),
+      };
+    let mut state = new();
+    state.speed = Vector3::new(0.0, -0.5, 0.0);
+    assert_eq!(state.tick(&mut boxes).landed_at, None);
+    state.speed = Vector3::new(0.0, -2.0, 0.0);
+    assert_eq!(state.tick(&mut boxes).landed_at, Some(2.0));
+    assert_eq!(boxes.body.min, Point3::new(0.0, 0.0, 0.0));
+  }
+
+  #[test]
+  fn lands_on_the_surface_inside_a_voxel() {
+    let ground =
+      Shape {
+        bounds  : aabb([0.0, 0.0, 0.0], [1.0, 1.0, 1.0]),
+        surface : Some((Point3::new(0.5, 0.5, 0.5), Vector3::new(0.0, 1.0, 0.0))),
+      };
+    let body = aabb([0.2, 2.0, 0.2], [0.8, 3.0, 0.8]);
+    let (t, normal) = time_of_impact(&body, &Vector3::new(0.0, -3.0, 0.0), &ground).unwrap();
+    assert_eq!(t, 0.5);
+    assert_eq!(normal, Vector3::new(0.0, 1.0, 0.0));
+  }
+
+  #[test]
+  fn sloped_surfaces_push_back_along_their_normal() {
+    let slope_normal = Vector3::new(1.0, 1.0, 0.0).normalize();
+    let slope =
+      Shape {
+        bounds  : aabb([-10.0, -10.0, -10.0], [10.0, 10.0, 10.0]),
+        surface : Some((Point3::new(0.0, 0.0, 0.0), slope_normal)),
+      };
+    let (_, normal) = time_of_impact(&aabb([0.0, 1.0, 0.0], [1.0, 2.0, 1.0]), &Vector3::new(0.0, -2.0, 0.0), &slope).unwrap();
+    assert_eq!(normal, slope_normal);
+  }
+
+  #[test]
+  // Gravity on a walkable slope shouldn't make a player creep downhill.
+  fn standing_on_a_gentle_slope_doesnt_slide() {
+    let slope_normal = Vector3::new(0.5, 1.0, 0.0).normalize();
+    let mut boxes =
+      Boxes {
+        body      : aabb([0.0, 1.0, 0.0], [1.0, 3.0, 1.0]),
+        obstacles : vec!(
+          Shape {
+            bounds  : aabb([-10.0, -10.0, -10.0], [10.0, 10.0, 10.0]),
+            surface : Some((Point3::new(0.0, 0.0, 0.0), slope_normal)),
+          },
+        ),
+      };
+    let mut state = new();
+    for _ in 0 .. 10 {
+      state.tick(&mut boxes);
+    }
+    assert_eq!(boxes.body.min.x, 0.0);
+    assert_eq!(boxes.body.min.z, 0.0);
+    assert_eq!(state.speed.x, 0.0);
+  }
+}
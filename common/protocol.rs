@@ -1,13 +1,37 @@
 //! Defines the messages passed between client and server.
 
-use cgmath::{Vector2, Vector3, Point3};
+use cgmath::{Vector2, Point3};
 use collision::{Aabb3};
 use std::default::Default;
 use std::ops::Add;
 
 use entity;
+use inventory;
+use movement;
+use snapshot;
 use voxel;
 
+/// The version of the client-server protocol. Bump this whenever the message formats change.
+pub const VERSION: u32 = 16;
+
+/// How long the server holds on to a client that has stopped answering, so it can `Reattach`.
+pub const REATTACH_GRACE_SECS: u64 = 60;
+
+/// The longest chat message the server will pass on, in bytes. Longer ones are truncated.
+pub const MAX_CHAT_MESSAGE_BYTES: usize = 256;
+
+/// Servers won't decode client messages that would take up more than this many bytes.
+pub const MAX_CLIENT_MESSAGE_BYTES: u64 = 1 << 20;
+
+/// The health a player starts with, and comes back with after dying.
+pub const MAX_HEALTH: u32 = 100;
+
+/// Names of optional protocol features, negotiated in `Init`.
+pub mod feature {
+  /// The client can decode `ServerToClient::CompressedVoxels`.
+  pub const COMPRESSED_VOXELS: &'static str = "compressed-voxels";
+}
+
 #[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Debug, Serialize, Deserialize)]
 /// Unique client ID.
 pub struct ClientId(u32);
@@ -27,23 +51,50 @@ impl Add<u32> for ClientId {
   }
 }
 
+#[derive(Copy, Clone, PartialEq, Eq, Debug, Serialize, Deserialize)]
+/// A secret handed out with a `ClientId`, which proves a reconnecting client is the one that leased it.
+pub struct SessionToken(pub u64);
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 /// Messages the client sends to the server.
+/// `Init` must stay the first variant, and keep its layout, across protocol versions.
 pub enum ClientToServer {
   /// Notify the server that the client exists, and provide a "return address".
-  Init(String),
-  /// Ping
+  Init {
+    /// The protocol `VERSION` the client was built with.
+    version  : u32,
+    /// The address the server should send to.
+    url      : String,
+    /// The optional protocol features the client supports.
+    features : Vec<String>,
+  },
+  /// Answer a server `Ping`, to show that the client is still alive.
   Ping(ClientId),
+  /// Pick up an existing session over a new connection, after the old one was lost.
+  Reattach {
+    #[allow(missing_docs)]
+    client_id : ClientId,
+    /// The token that came with the `ClientId`.
+    token     : SessionToken,
+    /// The address the server should send to from now on.
+    url       : String,
+  },
   /// Ask the server to create a new player.
   AddPlayer(ClientId),
-  /// Add a vector the player's acceleration.
-  Walk(entity::id::Player, Vector3<f32>),
+  /// Ask the server to remove one of this client's players.
+  RemovePlayer(ClientId, entity::id::Player),
+  /// Notify the server that the client is going away, along with all its players.
+  Leave(ClientId),
+  /// Move this client's spectator camera. The client gets entity updates around the camera,
+  /// as well as around its players. Clients that only spectate never need to `AddPlayer`.
+  MoveCamera(ClientId, Point3<f32>),
+  /// Set the full set of movement controls held for the player.
+  /// Each one replaces the last, so a lost or reordered one is corrected by the next.
+  SetInputState(ClientId, entity::id::Player, movement::InputState, InputSequence),
   /// Rotate the player by some amount.
-  RotatePlayer(entity::id::Player, Vector2<f32>),
-  /// [Try to] start a jump for the player.
-  StartJump(entity::id::Player),
-  /// [Try to] stop a jump for the player.
-  StopJump(entity::id::Player),
+  RotatePlayer(ClientId, entity::id::Player, Vector2<f32>, InputSequence),
+  /// Acknowledge a `WorldSnapshot`, so later ones can be sent relative to it.
+  AckSnapshot(ClientId, snapshot::Sequence),
   /// Ask the server to send a block of terrain.
   RequestVoxels {
     /// The time, in nanoseconds, when the voxels were requested.
@@ -52,13 +103,93 @@ pub enum ClientToServer {
     client_id       : ClientId,
     /// The bounds of the voxels to fetch.
     voxels          : Vec<voxel::bounds::T>,
+    /// Updates to the region these voxels cover are sent until this region is unsubscribed from.
+    /// Requesting under an id that's already subscribed replaces its region.
+    region          : RegionId,
   },
+  /// Stop sending updates about a region that was requested with `RequestVoxels`.
+  UnsubscribeVoxels {
+    /// The ID of the unsubscribing client.
+    client_id : ClientId,
+    /// The id the region was requested under.
+    region    : RegionId,
+  },
+  /// Say something, as one of this client's players.
+  Chat(ClientId, entity::id::Player, String),
   /// Brush-remove where the player's looking.
-  Add(entity::id::Player),
+  Add(ClientId, entity::id::Player),
   /// Brush-add at where the player's looking.
-  Remove(entity::id::Player),
+  Remove(ClientId, entity::id::Player),
 }
 
+/// Client-assigned number for each movement input, increasing from 1.
+/// The server acknowledges these so the client can reconcile its predictions.
+pub type InputSequence = u32;
+
+/// Client-assigned id for a region of voxels it has requested, and wants to hear about changes to.
+pub type RegionId = u64;
+
+impl ClientToServer {
+  /// Build the message for a movement input.
+  pub fn input(
+    client: ClientId,
+    player: entity::id::Player,
+    input: movement::Input,
+    sequence: InputSequence,
+  ) -> ClientToServer {
+    match input {
+      movement::Input::SetState(state) => ClientToServer::SetInputState(client, player, state, sequence),
+      movement::Input::Rotate(v) => ClientToServer::RotatePlayer(client, player, v, sequence),
+    }
+  }
+
+  /// The client that sent this message. Only `Init` comes from a client without an ID yet.
+  pub fn sender(&self) -> Option<ClientId> {
+    match *self {
+      ClientToServer::Init { .. } => None,
+      ClientToServer::Ping(client_id) => Some(client_id),
+      ClientToServer::Reattach { client_id, .. } => Some(client_id),
+      ClientToServer::AddPlayer(client_id) => Some(client_id),
+      ClientToServer::RemovePlayer(client_id, _) => Some(client_id),
+      ClientToServer::Leave(client_id) => Some(client_id),
+      ClientToServer::MoveCamera(client_id, _) => Some(client_id),
+      ClientToServer::SetInputState(client_id, _, _, _) => Some(client_id),
+      ClientToServer::RotatePlayer(client_id, _, _, _) => Some(client_id),
+      ClientToServer::AckSnapshot(client_id, _) => Some(client_id),
+      ClientToServer::RequestVoxels { client_id, .. } => Some(client_id),
+      ClientToServer::UnsubscribeVoxels { client_id, .. } => Some(client_id),
+      ClientToServer::Chat(client_id, _, _) => Some(client_id),
+      ClientToServer::Add(client_id, _) => Some(client_id),
+      ClientToServer::Remove(client_id, _) => Some(client_id),
+    }
+  }
+
+  /// The player this message acts on, if any. The sender has to own it.
+  pub fn player(&self) -> Option<entity::id::Player> {
+    match *self {
+      ClientToServer::RemovePlayer(_, player_id) => Some(player_id),
+      ClientToServer::SetInputState(_, player_id, _, _) => Some(player_id),
+      ClientToServer::RotatePlayer(_, player_id, _, _) => Some(player_id),
+      ClientToServer::Chat(_, player_id, _) => Some(player_id),
+      ClientToServer::Add(_, player_id) => Some(player_id),
+      ClientToServer::Remove(_, player_id) => Some(player_id),
+      ClientToServer::Init { .. } |
+      ClientToServer::Ping(_) |
+      ClientToServer::Reattach { .. } |
+      ClientToServer::AddPlayer(_) |
+      ClientToServer::Leave(_) |
+      ClientToServer::MoveCamera(_, _) |
+      ClientToServer::AckSnapshot(_, _) |
+      ClientToServer::RequestVoxels { .. } |
+      ClientToServer::UnsubscribeVoxels { .. } => None,
+    }
+  }
+}
+
+/// The server's clock, in nanoseconds, when an entity update was produced.
+/// Only meaningful relative to other `ServerTime`s from the same server.
+pub type ServerTime = u64;
+
 /// Why a block is being sent to a client.
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum VoxelReason {
@@ -79,21 +210,62 @@ pub enum Collision {
   PlayerMisc(entity::id::Player),
 }
 
+#[derive(Debug, Clone, Serialize, Deserialize)]
+/// Why the server refused a client's `Init`.
+pub enum InitRejection {
+  /// The client and server were built with different protocol versions.
+  VersionMismatch {
+    /// The server's protocol version.
+    server : u32,
+    /// The client's protocol version.
+    client : u32,
+  },
+  /// The client doesn't support some features the server requires.
+  MissingFeatures(Vec<String>),
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 /// Messages the server sends to the client.
+/// `InitRejected` must stay the first variant, and keep its layout, across protocol versions.
 pub enum ServerToClient {
-  /// Provide the client a unique id to tag its messages.
-  LeaseId(ClientId),
-  /// Ping
+  /// Refuse a client's `Init`. The server won't send anything else.
+  InitRejected(InitRejection),
+  /// Provide the client a unique id to tag its messages, and the token it needs to `Reattach`.
+  LeaseId(ClientId, SessionToken),
+  /// The client's `Reattach` worked; its players and voxel subscriptions are as it left them.
+  Reattached,
+  /// The client's `Reattach` failed, e.g. because its session had already expired.
+  ReattachRejected,
+  /// Check that the client is still alive. Clients should answer with a `Ping` of their own.
   Ping,
 
   /// Complete an AddPlayer request.
   PlayerAdded(entity::id::Player, Point3<f32>),
+  /// A player has been removed from the world.
+  PlayerRemoved(entity::id::Player),
+  /// One of the client's players has a new amount of health.
+  UpdateHealth(entity::id::Player, u32),
+  /// One of the client's players died, and has been moved back to the spawn point.
+  PlayerDied(entity::id::Player),
+  /// What one of the client's players is carrying, after it dug or built.
+  UpdateInventory(entity::id::Player, inventory::T),
 
-  /// Update a player's position.
-  UpdatePlayer(entity::id::Player, Aabb3<f32>),
-  /// Update the client's view of a mob with a given mesh.
-  UpdateMob(entity::id::Mob, Aabb3<f32>),
+  /// The entities within range of the client this tick, relative to a snapshot it has acknowledged.
+  WorldSnapshot(snapshot::WorldSnapshot),
+  /// Tell a client where the server has simulated one of its players to,
+  /// so it can correct its own prediction.
+  AckInput {
+    #[allow(missing_docs)]
+    player      : entity::id::Player,
+    /// The last input the server has applied.
+    sequence    : InputSequence,
+    /// How many ticks the server has simulated since applying that input.
+    ticks_since : u32,
+    /// The player's movement state after those ticks.
+    state       : movement::State,
+    /// The player's bounds after those ticks.
+    bounds      : Aabb3<f32>,
+  },
   /// The sun as a [0, 1) portion of its cycle.
   UpdateSun(f32),
 
@@ -104,6 +276,15 @@ pub enum ServerToClient {
     /// The reason the voxels are being sent.
     reason : VoxelReason,
   },
+  /// `Voxels`, packed with `voxel_encoding`. Only sent to clients that support `feature::COMPRESSED_VOXELS`.
+  CompressedVoxels {
+    /// The encoded voxels and their bounds.
+    voxels : Vec<u8>,
+    /// The reason the voxels are being sent.
+    reason : VoxelReason,
+  },
   /// A collision happened.
   Collision(Collision),
+  /// A player said something.
+  ChatMessage(entity::id::Player, String),
 }
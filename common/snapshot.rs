@@ -0,0 +1,237 @@
+//! Per-tick snapshots of the entities a client can see. Each snapshot only holds what changed
+//! since a snapshot the client has acknowledged, and positions and orientations are quantized.
+
+use cgmath::{Point3, Vector3};
+use collision::{Aabb3};
+use std::f32::consts::PI;
+use std::hash::Hash;
+
+use entity;
+use fnv_map;
+use mob_kind;
+use protocol;
+
+/// Fixed-point steps per world unit, in quantized positions.
+pub const POSITION_STEPS: f32 = 64.0;
+/// Fixed-point steps per full turn, in quantized lateral rotations.
+const LATERAL_STEPS: f32 = 65536.0;
+/// Fixed-point steps per quarter turn, in quantized vertical rotations.
+const VERTICAL_STEPS: f32 = 32767.0;
+
+/// Numbers each snapshot sent to a client, increasing from 0.
+pub type Sequence = u32;
+
+/// Quantized entity bounds.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Bounds {
+  center       : [i32; 3],
+  half_extents : [u16; 3],
+}
+
+impl Bounds {
+  #[allow(missing_docs)]
+  pub fn quantize(bounds: &Aabb3<f32>) -> Bounds {
+    let center = |i: usize| ((bounds.min[i] + bounds.max[i]) * 0.5 * POSITION_STEPS).round() as i32;
+    let half_extent = |i: usize| ((bounds.max[i] - bounds.min[i]) * 0.5 * POSITION_STEPS).round() as u16;
+    Bounds {
+      center       : [center(0), center(1), center(2)],
+      half_extents : [half_extent(0), half_extent(1), half_extent(2)],
+    }
+  }
+
+  #[allow(missing_docs)]
+  pub fn to_aabb(&self) -> Aabb3<f32> {
+    let center =
+      Point3::new(self.center[0] as f32, self.center[1] as f32, self.center[2] as f32)
+      / POSITION_STEPS;
+    let half_extents =
+      Vector3::new(self.half_extents[0] as f32, self.half_extents[1] as f32, self.half_extents[2] as f32)
+      / POSITION_STEPS;
+    Aabb3::new(center + -half_extents, center + half_extents)
+  }
+}
+
+/// Quantized player orientation.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Orientation {
+  lateral  : u16,
+  vertical : i16,
+}
+
+impl Orientation {
+  /// Quantize a rotation around the y axis and a pitch, both in radians.
+  pub fn quantize(lateral: f32, vertical: f32) -> Orientation {
+    let mut turns = (lateral / (2.0 * PI)).fract();
+    if turns < 0.0 {
+      turns += 1.0;
+    }
+    let vertical = vertical.max(-PI / 2.0).min(PI / 2.0);
+    Orientation {
+      lateral  : ((turns * LATERAL_STEPS).round() as u32 % LATERAL_STEPS as u32) as u16,
+      vertical : (vertical / (PI / 2.0) * VERTICAL_STEPS).round() as i16,
+    }
+  }
+
+  /// Rotation around the y axis, in radians, in [0, 2pi).
+  pub fn lateral(&self) -> f32 {
+    self.lateral as f32 / LATERAL_STEPS * 2.0 * PI
+  }
+
+  /// Pitch, in radians.
+  pub fn vertical(&self) -> f32 {
+    self.vertical as f32 / VERTICAL_STEPS * PI / 2.0
+  }
+}
+
+#[allow(missing_docs)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Player {
+  pub bounds      : Bounds,
+  pub orientation : Orientation,
+}
+
+#[allow(missing_docs)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Mob {
+  pub bounds : Bounds,
+  pub kind   : mob_kind::T,
+}
+
+/// Everything a client has been told about the entities it can see.
+#[derive(Debug, Clone, PartialEq)]
+pub struct View {
+  #[allow(missing_docs)]
+  pub players : fnv_map::T<entity::id::Player, Player>,
+  #[allow(missing_docs)]
+  pub mobs    : fnv_map::T<entity::id::Mob, Mob>,
+}
+
+#[allow(missing_docs)]
+pub fn empty() -> View {
+  View {
+    players : fnv_map::new(),
+    mobs    : fnv_map::new(),
+  }
+}
+
+/// A `View`, as changes from an earlier one.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WorldSnapshot {
+  #[allow(missing_docs)]
+  pub sequence        : Sequence,
+  /// The snapshot this one is relative to. If None, it's relative to an empty `View`.
+  pub baseline        : Option<Sequence>,
+  /// When the server took this snapshot.
+  pub time            : protocol::ServerTime,
+  /// Players that are new or changed since the baseline.
+  pub players         : Vec<(entity::id::Player, Player)>,
+  /// Mobs that are new or changed since the baseline.
+  pub mobs            : Vec<(entity::id::Mob, Mob)>,
+  /// Players in the baseline that are no longer visible.
+  pub removed_players : Vec<entity::id::Player>,
+  /// Mobs in the baseline that are no longer visible.
+  pub removed_mobs    : Vec<entity::id::Mob>,
+}
+
+fn diff<Id, State>(
+  current  : &fnv_map::T<Id, State>,
+  baseline : &fnv_map::T<Id, State>,
+) -> (Vec<(Id, State)>, Vec<Id>) where
+  Id    : Copy + Eq + Hash,
+  State : Copy + PartialEq,
+{
+  let changed =
+    current.iter()
+    .filter(|&(id, state)| baseline.get(id) != Some(state))
+    .map(|(&id, &state)| (id, state))
+    .collect();
+  let removed =
+    baseline.keys()
+    .filter(|id| !current.contains_key(id))
+    .cloned()
+    .collect();
+  (changed, removed)
+}
+
+fn patch<Id, State>(
+  baseline : &fnv_map::T<Id, State>,
+  changed  : &[(Id, State)],
+  removed  : &[Id],
+) -> fnv_map::T<Id, State> where
+  Id    : Copy + Eq + Hash,
+  State : Copy,
+{
+  let mut map = baseline.clone();
+  for id in removed {
+    map.remove(id);
+  }
+  for &(id, state) in changed {
+    map.insert(id, state);
+  }
+  map
+}
+
+impl View {
+  /// Describe this view relative to `baseline`, or in full if there's no baseline.
+  pub fn snapshot(
+    &self,
+    sequence : Sequence,
+    time     : protocol::ServerTime,
+    baseline : Option<(Sequence, &View)>,
+  ) -> WorldSnapshot {
+    let empty = empty();
+    let (baseline_sequence, baseline) =
+      match baseline {
+        None => (None, &empty),
+        Some((sequence, view)) => (Some(sequence), view),
+      };
+    let (players, removed_players) = diff(&self.players, &baseline.players);
+    let (mobs, removed_mobs) = diff(&self.mobs, &baseline.mobs);
+    WorldSnapshot {
+      sequence        : sequence,
+      baseline        : baseline_sequence,
+      time            : time,
+      players         : players,
+      mobs            : mobs,
+      removed_players : removed_players,
+      removed_mobs    : removed_mobs,
+    }
+  }
+
+  /// Rebuild the view that `snapshot` describes, given the view it's relative to.
+  pub fn apply(&self, snapshot: &WorldSnapshot) -> View {
+    View {
+      players : patch(&self.players, &snapshot.players, &snapshot.removed_players),
+      mobs    : patch(&self.mobs, &snapshot.mobs, &snapshot.removed_mobs),
+    }
+  }
+}
+
+#[test]
+fn delta_round_trip() {
+  let bounds = |x: f32| Bounds::quantize(&Aabb3::new(Point3::new(x, 0.0, 0.0), Point3::new(x + 1.0, 2.0, 1.0)));
+  let player = |x: f32| Player { bounds: bounds(x), orientation: Orientation::quantize(x, 0.5) };
+  let mob = |x: f32| Mob { bounds: bounds(x), kind: mob_kind::T::Tag };
+
+  let player_id = |i: u32| entity::id::Player::default() + i;
+  let mob_id = |i: u32| entity::id::Mob::default() + i;
+
+  let mut baseline = empty();
+  baseline.players.insert(player_id(0), player(0.0));
+  baseline.players.insert(player_id(1), player(1.0));
+  baseline.mobs.insert(mob_id(0), mob(0.0));
+
+  let mut current = baseline.clone();
+  current.players.remove(&player_id(0));
+  current.players.insert(player_id(1), player(1.5));
+  current.mobs.insert(mob_id(1), mob(3.0));
+
+  let snapshot = current.snapshot(1, 0, Some((0, &baseline)));
+  assert_eq!(snapshot.players.len(), 1);
+  assert_eq!(snapshot.mobs.len(), 1);
+  assert_eq!(snapshot.removed_players, vec!(player_id(0)));
+  assert_eq!(baseline.apply(&snapshot), current);
+
+  let full = current.snapshot(1, 0, None);
+  assert_eq!(empty().apply(&full), current);
+}
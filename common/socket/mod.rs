@@ -0,0 +1,238 @@
+//! One-way socket wrapper data structures, over a choice of transport backends.
+//!
+//! URLs beginning with `tcp://` use plain TCP, with a single two-way connection per client.
+//! Any other URL (e.g. `ipc://`) is handed to nanomsg.
+
+#[cfg(feature = "nanomsg")]
+mod nanomsg;
+mod tcp;
+
+use std;
+use std::sync::Arc;
+use std::time::Duration;
+
+/// The sending half of a transport backend.
+pub trait Sender: std::marker::Send {
+  /// Block until we can send a message.
+  fn write(&mut self, msg: &[u8]) -> std::io::Result<()>;
+}
+
+/// The receiving half of a transport backend.
+pub trait Receiver: std::marker::Send {
+  /// Block until a message can be fetched. Returns `None` if the connection is gone.
+  fn read(&mut self) -> Option<(Peer, Vec<u8>)>;
+  /// Try to fetch a message without blocking.
+  fn try_read(&mut self) -> Result<(Peer, Vec<u8>)>;
+}
+
+#[allow(missing_docs)]
+pub enum Result<T> {
+  Success(T),
+  Empty,
+  Terminating,
+}
+
+#[derive(Clone)]
+#[cfg_attr(not(feature = "nanomsg"), allow(dead_code))]
+enum PeerConnection {
+  /// Reach the peer by connecting to the address it gives us.
+  Address,
+  /// Reply over the connection the message arrived on.
+  Tcp(Arc<std::net::TcpStream>),
+  /// Nobody's listening; replies go nowhere.
+  Nowhere,
+}
+
+/// The sender of a received message.
+#[derive(Clone)]
+pub struct Peer(PeerConnection);
+
+impl Peer {
+  /// Whether this and `other` sent their messages over the same connection,
+  /// or None if the transport can't tell senders apart.
+  pub fn same_connection(&self, other: &Peer) -> Option<bool> {
+    match (&self.0, &other.0) {
+      (&PeerConnection::Tcp(ref a), &PeerConnection::Tcp(ref b)) => Some(Arc::ptr_eq(a, b)),
+      (&PeerConnection::Tcp(_), _) | (_, &PeerConnection::Tcp(_)) => Some(false),
+      _ => None,
+    }
+  }
+
+  /// A peer that silently drops everything sent to it, e.g. for replaying recorded messages.
+  pub fn nowhere() -> Peer {
+    Peer(PeerConnection::Nowhere)
+  }
+
+  /// Open a socket back to this peer. `url` is the return address the peer provided;
+  /// transports that reply over the same connection ignore it.
+  /// Dropping the socket closes the connection.
+  pub fn connect_back(&self, url: &str, timeout: Option<Duration>) -> std::io::Result<SendSocket> {
+    match self.reply_socket(timeout) {
+      Some(socket) => Ok(socket),
+      None => SendSocket::new(url, timeout),
+    }
+  }
+
+  /// A socket that replies over the connection this peer's message arrived on, without connecting anywhere new,
+  /// or None if this transport can't do that. Dropping the socket closes the connection.
+  pub fn reply_socket(&self, timeout: Option<Duration>) -> Option<SendSocket> {
+    match self.0 {
+      PeerConnection::Address => None,
+      PeerConnection::Tcp(ref stream) => {
+        Some(SendSocket {
+          sender: Box::new(tcp::Sender::new(stream.clone(), timeout)),
+        })
+      },
+      PeerConnection::Nowhere => {
+        Some(SendSocket {
+          sender: Box::new(NowhereSender),
+        })
+      },
+    }
+  }
+}
+
+struct NowhereSender;
+
+impl Sender for NowhereSender {
+  fn write(&mut self, _: &[u8]) -> std::io::Result<()> {
+    Ok(())
+  }
+}
+
+fn tcp_address(url: &str) -> Option<&str> {
+  let prefix = "tcp://";
+  if url.starts_with(prefix) {
+    Some(&url[prefix.len() ..])
+  } else {
+    None
+  }
+}
+
+/// A send-only socket.
+pub struct SendSocket {
+  sender: Box<Sender>,
+}
+
+impl SendSocket {
+  /// Connect to `url`. Fails if it can't be reached.
+  pub fn new(url: &str, timeout: Option<Duration>) -> std::io::Result<SendSocket> {
+    let sender: Box<Sender> =
+      match tcp_address(url) {
+        Some(address) => Box::new(try!(tcp::Sender::connect(address, timeout))),
+        None => nanomsg_sender(url, timeout),
+      };
+    Ok(SendSocket {
+      sender: sender,
+    })
+  }
+
+  /// Block until we can send this socket a message.
+  pub fn write(&mut self, msg: &[u8]) -> std::io::Result<()> {
+    self.sender.write(msg)
+  }
+
+  /// Terminate this connection.
+  pub fn close(self) {
+    // The `drop` takes care of everything.
+  }
+}
+
+/// A receive-only socket.
+pub struct ReceiveSocket {
+  receiver: Box<Receiver>,
+}
+
+impl ReceiveSocket {
+  #[allow(missing_docs)]
+  pub fn new(url: &str, timeout: Option<Duration>) -> ReceiveSocket {
+    let receiver: Box<Receiver> =
+      match tcp_address(url) {
+        Some(address) => Box::new(tcp::listen(address, timeout)),
+        None => nanomsg_receiver(url, timeout),
+      };
+    ReceiveSocket {
+      receiver: receiver,
+    }
+  }
+
+  /// Block until a message can be fetched from this socket.
+  pub fn read(&mut self) -> Option<Vec<u8>> {
+    self.receiver.read().map(|(_, msg)| msg)
+  }
+
+  /// Try to read a message from this socket.
+  pub fn try_read(&mut self) -> Result<Vec<u8>> {
+    match self.receiver.try_read() {
+      Result::Success((_, msg)) => Result::Success(msg),
+      Result::Empty => Result::Empty,
+      Result::Terminating => Result::Terminating,
+    }
+  }
+
+  /// Try to read a message from this socket, along with a handle to its sender.
+  pub fn try_read_from(&mut self) -> Result<(Peer, Vec<u8>)> {
+    self.receiver.try_read()
+  }
+
+  /// Terminate this connection.
+  pub fn close(self) {
+    // The `drop` takes care of everything.
+  }
+}
+
+/// Open a two-way connection to a server. Transports that can't reply over the same
+/// connection will bind `listen_url` to receive the server's messages.
+/// Fails if the server can't be reached.
+pub fn connect(
+  server_url: &str,
+  listen_url: &str,
+  timeout: Option<Duration>,
+) -> std::io::Result<(SendSocket, ReceiveSocket)> {
+  match tcp_address(server_url) {
+    Some(address) => {
+      let (sender, receiver) = try!(tcp::connect(address, timeout));
+      Ok((
+        SendSocket { sender: Box::new(sender) },
+        ReceiveSocket { receiver: Box::new(receiver) },
+      ))
+    },
+    None => {
+      Ok((
+        try!(SendSocket::new(server_url, timeout)),
+        ReceiveSocket::new(listen_url, timeout),
+      ))
+    },
+  }
+}
+
+#[cfg(feature = "nanomsg")]
+fn nanomsg_sender(url: &str, timeout: Option<Duration>) -> Box<Sender> {
+  Box::new(nanomsg::Sender::new(url, timeout))
+}
+
+#[cfg(not(feature = "nanomsg"))]
+fn nanomsg_sender(url: &str, _: Option<Duration>) -> Box<Sender> {
+  panic!("Can't connect to {}: built without nanomsg support", url)
+}
+
+#[cfg(feature = "nanomsg")]
+fn nanomsg_receiver(url: &str, timeout: Option<Duration>) -> Box<Receiver> {
+  Box::new(nanomsg::Receiver::new(url, timeout))
+}
+
+#[cfg(not(feature = "nanomsg"))]
+fn nanomsg_receiver(url: &str, _: Option<Duration>) -> Box<Receiver> {
+  panic!("Can't listen on {}: built without nanomsg support", url)
+}
+
+/// Shut down all the sockets in the process, e.g. to unblock threads that are waiting on them.
+#[cfg(feature = "nanomsg")]
+pub fn terminate() {
+  nanomsg::terminate();
+}
+
+/// Shut down all the sockets in the process, e.g. to unblock threads that are waiting on them.
+#[cfg(not(feature = "nanomsg"))]
+pub fn terminate() {
+}
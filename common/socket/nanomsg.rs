@@ -1,4 +1,5 @@
-//! One-way socket wrapper data structures.
+//! nanomsg push/pull transport. Each direction is a separate connection, so servers reach clients
+//! by connecting to the return address that they provide.
 
 use nanomsg::{Endpoint, Socket, Protocol, Error};
 use std;
@@ -6,88 +7,75 @@ use std::convert::AsRef;
 use std::io::{Read, Write};
 use std::time::Duration;
 
-/// A send-only socket.
-pub struct SendSocket {
-  socket: Socket,
-  endpoint: Endpoint,
-}
+use super::{Peer, PeerConnection, Result};
 
 fn as_millis(duration: Duration) -> isize {
   (duration.as_secs() * 1_000) as isize + (duration.subsec_nanos() / 1_000_000) as isize
 }
 
-impl SendSocket {
-  #[allow(missing_docs)]
-  pub fn new(url: &str, timeout: Option<Duration>) -> SendSocket {
+pub struct Sender {
+  socket: Socket,
+  endpoint: Endpoint,
+}
+
+impl Sender {
+  pub fn new(url: &str, timeout: Option<Duration>) -> Sender {
     let mut socket = Socket::new(Protocol::Push).unwrap();
     timeout.map(|timeout| socket.set_receive_timeout(as_millis(timeout)).unwrap());
     let endpoint = socket.connect(url).unwrap();
 
-    SendSocket {
+    Sender {
       socket: socket,
       endpoint: endpoint,
     }
   }
+}
 
-  /// Block until we can send this socket a message.
-  pub fn write(&mut self, msg: &[u8]) -> std::io::Result<()> {
+impl super::Sender for Sender {
+  fn write(&mut self, msg: &[u8]) -> std::io::Result<()> {
     self.socket.write(msg).map(|_| ())
   }
-
-  /// Terminate this connection.
-  pub fn close(self) {
-    // The `drop` takes care of everything.
-  }
 }
 
-impl Drop for SendSocket {
+impl Drop for Sender {
   fn drop(&mut self) {
     self.endpoint.shutdown().unwrap_or(());
   }
 }
 
-#[allow(missing_docs)]
-pub enum Result<T> {
-  Success(T),
-  Empty,
-  Terminating,
-}
-
-/// A receive-only socket.
-pub struct ReceiveSocket {
+pub struct Receiver {
   socket: Socket,
   endpoint: Endpoint,
 }
 
-impl ReceiveSocket {
-  #[allow(missing_docs)]
-  pub fn new(url: &str, timeout: Option<Duration>) -> ReceiveSocket {
+impl Receiver {
+  pub fn new(url: &str, timeout: Option<Duration>) -> Receiver {
     let mut socket = Socket::new(Protocol::Pull).unwrap();
     timeout.map(|timeout| socket.set_receive_timeout(as_millis(timeout)).unwrap());
     let endpoint = socket.bind(url.as_ref()).unwrap();
 
-    ReceiveSocket {
+    Receiver {
       socket: socket,
       endpoint: endpoint,
     }
   }
+}
 
-  /// Block until a message can be fetched from this socket.
-  pub fn read(&mut self) -> Option<Vec<u8>> {
+impl super::Receiver for Receiver {
+  fn read(&mut self) -> Option<(Peer, Vec<u8>)> {
     let mut msg = Vec::new();
     if self.socket.read_to_end(&mut msg).is_ok() {
-      Some(msg)
+      Some((Peer(PeerConnection::Address), msg))
     } else {
       None
     }
   }
 
-  /// Try to read a message from this socket.
-  pub fn try_read(&mut self) -> Result<Vec<u8>> {
+  fn try_read(&mut self) -> Result<(Peer, Vec<u8>)> {
     let mut msg = Vec::new();
     let result = self.socket.nb_read_to_end(&mut msg);
     match result {
-      Ok(_) => Result::Success(msg),
+      Ok(_) => Result::Success((Peer(PeerConnection::Address), msg)),
       Err(Error::TryAgain) => Result::Empty,
       Err(Error::Terminating) => Result::Terminating,
       Err(_) => {
@@ -96,15 +84,14 @@ impl ReceiveSocket {
       }
     }
   }
-
-  /// Terminate this connection.
-  pub fn close(self) {
-    // The `drop` takes care of everything.
-  }
 }
 
-impl Drop for ReceiveSocket {
+impl Drop for Receiver {
   fn drop(&mut self) {
     self.endpoint.shutdown().unwrap_or(());
   }
 }
+
+pub fn terminate() {
+  Socket::terminate();
+}
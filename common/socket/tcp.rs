@@ -0,0 +1,172 @@
+//! Plain TCP transport. Each client has a single two-way connection to the server,
+//! carrying length-prefixed messages in both directions.
+
+use std;
+use std::io::{Read, Write};
+use std::net::{Shutdown, TcpListener, TcpStream};
+use std::sync::Arc;
+use std::sync::mpsc;
+use std::time::Duration;
+
+use super::{Peer, PeerConnection, Result};
+
+/// Longer messages are assumed to be garbage, rather than allocated.
+const MAX_MESSAGE_BYTES: usize = 1 << 28;
+
+/// Write a single length-prefixed message.
+pub fn write_message<W: Write>(mut w: W, msg: &[u8]) -> std::io::Result<()> {
+  let len = msg.len() as u32;
+  let mut frame = Vec::with_capacity(4 + msg.len());
+  frame.extend_from_slice(&[len as u8, (len >> 8) as u8, (len >> 16) as u8, (len >> 24) as u8]);
+  frame.extend_from_slice(msg);
+  w.write_all(&frame)
+}
+
+/// Read a single length-prefixed message.
+pub fn read_message<R: Read>(mut r: R) -> std::io::Result<Vec<u8>> {
+  let mut header = [0; 4];
+  try!(r.read_exact(&mut header));
+  let len =
+    (header[0] as usize) |
+    (header[1] as usize) << 8 |
+    (header[2] as usize) << 16 |
+    (header[3] as usize) << 24;
+  if len > MAX_MESSAGE_BYTES {
+    return Err(std::io::Error::new(std::io::ErrorKind::InvalidData, "message too long"))
+  }
+
+  let mut msg = vec!(0; len);
+  try!(r.read_exact(&mut msg));
+  Ok(msg)
+}
+
+fn open(address: &str) -> std::io::Result<Arc<TcpStream>> {
+  let stream = try!(TcpStream::connect(address));
+  try!(stream.set_nodelay(true));
+  Ok(Arc::new(stream))
+}
+
+/// Writes to a connection, and closes it when dropped, so its reader thread finishes too.
+pub struct Sender {
+  stream: Arc<TcpStream>,
+}
+
+impl Sender {
+  /// Writes that block for longer than `timeout` fail, rather than holding up the sender.
+  pub fn new(stream: Arc<TcpStream>, timeout: Option<Duration>) -> Sender {
+    stream.set_write_timeout(timeout).unwrap_or(());
+    Sender {
+      stream: stream,
+    }
+  }
+
+  pub fn connect(address: &str, timeout: Option<Duration>) -> std::io::Result<Sender> {
+    let stream = try!(open(address));
+    Ok(Sender::new(stream, timeout))
+  }
+}
+
+impl super::Sender for Sender {
+  fn write(&mut self, msg: &[u8]) -> std::io::Result<()> {
+    write_message(&*self.stream, msg)
+  }
+}
+
+impl Drop for Sender {
+  fn drop(&mut self) {
+    self.stream.shutdown(Shutdown::Both).unwrap_or(());
+  }
+}
+
+type Message = (Arc<TcpStream>, Vec<u8>);
+
+/// Forward messages from a connection into a channel, until either one closes.
+fn spawn_reader(stream: Arc<TcpStream>, messages: mpsc::Sender<Message>) {
+  std::thread::spawn(move || {
+    loop {
+      let msg =
+        match read_message(&*stream) {
+          Err(_) => break,
+          Ok(msg) => msg,
+        };
+      if messages.send((stream.clone(), msg)).is_err() {
+        break
+      }
+    }
+  });
+}
+
+pub struct Receiver {
+  messages: mpsc::Receiver<Message>,
+  timeout: Option<Duration>,
+}
+
+/// Accept connections on `address`, and receive messages from all of them.
+pub fn listen(address: &str, timeout: Option<Duration>) -> Receiver {
+  let listener = TcpListener::bind(address).unwrap();
+  let (send, recv) = mpsc::channel();
+
+  std::thread::spawn(move || {
+    for stream in listener.incoming() {
+      let stream =
+        match stream {
+          Err(_) => continue,
+          Ok(stream) => stream,
+        };
+      stream.set_nodelay(true).unwrap_or(());
+      spawn_reader(Arc::new(stream), send.clone());
+    }
+  });
+
+  Receiver {
+    messages: recv,
+    timeout: timeout,
+  }
+}
+
+/// Open a single connection to `address`, and use it in both directions.
+pub fn connect(address: &str, timeout: Option<Duration>) -> std::io::Result<(Sender, Receiver)> {
+  let stream = try!(open(address));
+  let (send, recv) = mpsc::channel();
+  spawn_reader(stream.clone(), send);
+
+  let receiver =
+    Receiver {
+      messages: recv,
+      timeout: timeout,
+    };
+  Ok((Sender::new(stream, timeout), receiver))
+}
+
+impl super::Receiver for Receiver {
+  fn read(&mut self) -> Option<(Peer, Vec<u8>)> {
+    let msg =
+      match self.timeout {
+        None => self.messages.recv().ok(),
+        Some(timeout) => self.messages.recv_timeout(timeout).ok(),
+      };
+    msg.map(|(stream, msg)| (Peer(PeerConnection::Tcp(stream)), msg))
+  }
+
+  fn try_read(&mut self) -> Result<(Peer, Vec<u8>)> {
+    match self.messages.try_recv() {
+      Ok((stream, msg)) => Result::Success((Peer(PeerConnection::Tcp(stream)), msg)),
+      Err(mpsc::TryRecvError::Empty) => Result::Empty,
+      Err(mpsc::TryRecvError::Disconnected) => Result::Terminating,
+    }
+  }
+}
+
+#[test]
+fn message_round_trip() {
+  let mut buffer = Vec::new();
+  write_message(&mut buffer, b"hello").unwrap();
+  write_message(&mut buffer, b"").unwrap();
+  write_message(&mut buffer, &[0xFF; 300]).unwrap();
+
+  let mut buffer = std::io::Cursor::new(buffer);
+  assert_eq!(read_message(&mut buffer).unwrap(), b"hello".to_vec());
+  assert_eq!(read_message(&mut buffer).unwrap(), Vec::new());
+  assert_eq!(read_message(&mut buffer).unwrap(), vec!(0xFF; 300));
+  assert!(read_message(&mut buffer).is_err());
+}
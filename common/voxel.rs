@@ -1,5 +1,6 @@
 //! Voxel implementation for terrain
 
+use cgmath::{Point3, InnerSpace};
 use isosurface_extraction;
 use voxel_data;
 
@@ -23,6 +24,39 @@ pub enum Material {
   Marble = 5,
 }
 
+/// How finely voxel contents are measured: a solid voxel of the smallest size holds this many units
+/// of its material, one per octant.
+pub const UNITS_PER_VOXEL: u32 = 8;
+
+/// What a voxel of the smallest size is made of, and how many units of it there are.
+/// Surface voxels are only solid below their surface, which is made of their corner material.
+pub fn contents(bounds: &bounds::T, voxel: &T) -> (Material, u32) {
+  match *voxel {
+    Volume(Material::Empty) => (Material::Empty, 0),
+    Volume(material) => (material, UNITS_PER_VOXEL),
+    Surface(ref surface) => {
+      let normal = surface.normal.to_float_normal();
+      if normal.magnitude2() == 0.0 {
+        return (surface.corner, UNITS_PER_VOXEL)
+      }
+      let vertex = surface.surface_vertex.to_world_vertex(bounds);
+      let (low, high) = bounds.corners();
+      let along = |low: f32, high: f32, f: f32| low + (high - low) * f;
+      // Count the octants whose centers are under the surface.
+      let mut solid = 0;
+      for &x in &[0.25, 0.75] {
+      for &y in &[0.25, 0.75] {
+      for &z in &[0.25, 0.75] {
+        let center = Point3::new(along(low.x, high.x, x), along(low.y, high.y, y), along(low.z, high.z, z));
+        if (center - vertex).dot(normal) < 0.0 {
+          solid += 1;
+        }
+      }}}
+      (surface.corner, solid)
+    },
+  }
+}
+
 #[allow(missing_docs)]
 pub mod tree {
   use voxel_data;
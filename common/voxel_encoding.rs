@@ -0,0 +1,143 @@
+//! Compact encoding for lists of voxels sent over the network.
+//!
+//! Distinct voxel values are stored once in a palette, consecutive repeats of the same value
+//! are run-length coded, and the whole thing is deflated.
+
+use bincode;
+use flate2;
+use std;
+use std::io::{Read, Write};
+
+use fnv_map;
+use voxel;
+
+/// The most bytes a message may inflate to before we give up on it.
+pub const MAX_DECODED_BYTES: u64 = 1 << 26;
+
+#[derive(Serialize, Deserialize)]
+struct Encoded {
+  bounds  : Vec<voxel::bounds::T>,
+  palette : Vec<voxel::T>,
+  /// (palette index, run length) pairs.
+  runs    : Vec<(u32, u32)>,
+}
+
+/// Encode a list of voxels.
+pub fn encode(voxels: &[(voxel::bounds::T, voxel::T)]) -> Vec<u8> {
+  let mut encoded =
+    Encoded {
+      bounds  : Vec::with_capacity(voxels.len()),
+      palette : Vec::new(),
+      runs    : Vec::new(),
+    };
+
+  // Voxels aren't hashable, so index the palette by their serialized form.
+  let mut palette_index = fnv_map::new();
+  for &(bounds, voxel) in voxels {
+    encoded.bounds.push(bounds);
+
+    let key = bincode::serialize(&voxel, bincode::Infinite).unwrap();
+    let palette = &mut encoded.palette;
+    let i =
+      *palette_index.entry(key).or_insert_with(|| {
+        palette.push(voxel);
+        palette.len() as u32 - 1
+      });
+
+    let extends_run = encoded.runs.last().map(|&(last, _)| last == i) == Some(true);
+    if extends_run {
+      encoded.runs.last_mut().unwrap().1 += 1;
+    } else {
+      encoded.runs.push((i, 1));
+    }
+  }
+
+  compress(&encoded)
+}
+
+fn compress(encoded: &Encoded) -> Vec<u8> {
+  let encoded = bincode::serialize(encoded, bincode::Infinite).unwrap();
+  let mut compressor = flate2::write::ZlibEncoder::new(Vec::new(), flate2::Compression::default());
+  compressor.write_all(&encoded).unwrap();
+  compressor.finish().unwrap()
+}
+
+/// Decode a list of voxels produced by `encode`.
+pub fn decode(bytes: &[u8]) -> std::io::Result<Vec<(voxel::bounds::T, voxel::T)>> {
+  decode_with_limit(bytes, MAX_DECODED_BYTES)
+}
+
+fn decode_with_limit(bytes: &[u8], limit: u64) -> std::io::Result<Vec<(voxel::bounds::T, voxel::T)>> {
+  let mut encoded = Vec::new();
+  // Read one byte past the limit, so we can tell a message that fits exactly from one that doesn't.
+  try!(flate2::read::ZlibDecoder::new(bytes).take(limit + 1).read_to_end(&mut encoded));
+  if encoded.len() as u64 > limit {
+    return Err(std::io::Error::new(std::io::ErrorKind::InvalidData, "message inflates past the limit"))
+  }
+  let encoded: Encoded =
+    try!(
+      bincode::deserialize(&encoded)
+      .map_err(|err| std::io::Error::new(std::io::ErrorKind::InvalidData, err))
+    );
+
+  let mut voxels = Vec::with_capacity(encoded.bounds.len());
+  let mut bounds = encoded.bounds.into_iter();
+  for (i, len) in encoded.runs {
+    let voxel =
+      match encoded.palette.get(i as usize) {
+        None => return Err(std::io::Error::new(std::io::ErrorKind::InvalidData, "bad palette index")),
+        Some(&voxel) => voxel,
+      };
+    for _ in 0 .. len {
+      match bounds.next() {
+        None => return Err(std::io::Error::new(std::io::ErrorKind::InvalidData, "too many voxels")),
+        Some(bounds) => voxels.push((bounds, voxel)),
+      }
+    }
+  }
+  if bounds.next().is_some() {
+    return Err(std::io::Error::new(std::io::ErrorKind::InvalidData, "too few voxels"))
+  }
+
+  Ok(voxels)
+}
+
+#[test]
+fn round_trip() {
+  let voxels: Vec<_> =
+    (0 .. 100)
+    .map(|i| {
+      let material = if i < 30 || i % 7 == 0 { voxel::Material::Terrain } else { voxel::Material::Empty };
+      (voxel::bounds::new(i, 0, -i, 0), voxel::Volume(material))
+    })
+    .collect();
+
+  let decoded = decode(&encode(&voxels)).unwrap();
+  assert_eq!(format!("{:?}", decoded), format!("{:?}", voxels));
+}
+
+#[test]
+fn rejects_oversized_messages() {
+  let voxels: Vec<_> =
+    (0 .. 1000)
+    .map(|i| (voxel::bounds::new(i, 0, 0, 0), voxel::Volume(voxel::Material::Terrain)))
+    .collect();
+  let encoded = encode(&voxels);
+
+  let err = decode_with_limit(&encoded, 64).unwrap_err();
+  assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+  assert_eq!(decode_with_limit(&encoded, MAX_DECODED_BYTES).unwrap().len(), voxels.len());
+}
+
+#[test]
+fn rejects_leftover_bounds() {
+  let encoded =
+    compress(&Encoded {
+      bounds  : vec!(voxel::bounds::new(0, 0, 0, 0), voxel::bounds::new(1, 0, 0, 0)),
+      palette : vec!(voxel::Volume(voxel::Material::Terrain)),
+      runs    : vec!((0, 1)),
+    });
+
+  let err = decode(&encoded).unwrap_err();
+  assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+}
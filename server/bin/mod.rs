@@ -4,11 +4,11 @@
 #![deny(warnings)]
 
 extern crate env_logger;
-extern crate nanomsg;
 #[macro_use]
 extern crate log;
 extern crate thread_scoped;
 
+extern crate common;
 extern crate server_lib;
 
 use std::borrow::Borrow;
@@ -18,12 +18,39 @@ use std::sync::Mutex;
 fn main() {
   env_logger::init().unwrap();
 
-  let mut args = env::args();
-  args.next().unwrap();
+  let mut args: Vec<String> = env::args().skip(1).collect();
+
+  // `--replay FILE` replays a recorded session instead of listening for clients.
+  match take_flag(&mut args, "--replay") {
+    None => {},
+    Some(path) => {
+      assert!(args.is_empty());
+      match server_lib::replay(std::path::Path::new(&path)) {
+        Ok(hash) => println!("World hash: {:x}", hash),
+        Err(err) => println!("Error replaying {}: {:?}", path, err),
+      }
+      return
+    },
+  }
+
+  // `--record FILE` records every incoming message, for `--replay`.
+  let record_path = take_flag(&mut args, "--record");
+
+  let mut args = args.into_iter();
   let listen_url = args.next().unwrap_or_else(|| String::from("ipc:///tmp/server.ipc"));
+  let client_timeout_secs =
+    args.next()
+    .map(|s| s.parse().unwrap())
+    .unwrap_or(server_lib::server::DEFAULT_CLIENT_TIMEOUT_SECS);
+  let entity_update_radius =
+    args.next()
+    .map(|s| s.parse().unwrap())
+    .unwrap_or(server_lib::server::DEFAULT_ENTITY_UPDATE_RADIUS);
   assert!(args.next().is_none());
 
   info!("Listening on {}.", listen_url);
+  info!("Disconnecting clients after {}s of silence.", client_timeout_secs);
+  info!("Updating clients about entities within {} of their players.", entity_update_radius);
 
   let quit_signal = Mutex::new(false);
 
@@ -34,11 +61,27 @@ fn main() {
         wait_for_quit();
         *quit_signal.lock().unwrap() = true;
         // Close all sockets.
-        nanomsg::Socket::terminate();
+        common::socket::terminate();
       })
     };
 
-  server_lib::run(listen_url.borrow(), &quit_signal);
+  server_lib::run(
+    listen_url.borrow(),
+    std::time::Duration::from_secs(client_timeout_secs),
+    entity_update_radius,
+    record_path.as_ref().map(|path| std::path::Path::new(path)),
+    &quit_signal,
+  );
+}
+
+/// Remove `flag` and the value after it from `args`, wherever they are, and return the value.
+fn take_flag(args: &mut Vec<String>, flag: &str) -> Option<String> {
+  let i = args.iter().position(|arg| arg == flag);
+  i.map(|i| {
+    args.remove(i);
+    assert!(i < args.len(), "{} needs a file", flag);
+    args.remove(i)
+  })
 }
 
 fn wait_for_quit() {
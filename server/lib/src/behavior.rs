@@ -0,0 +1,345 @@
+//! Behavior trees for mob AI. A mob ticks its tree once per world update; nodes read and write the mob's
+//! blackboard to remember things between ticks.
+
+use cgmath::{Point3};
+use collision::{Aabb3};
+use std::sync::Arc;
+
+use common::fnv_map;
+
+use mob;
+use server;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Status {
+  Success,
+  Failure,
+  /// Still working on it; tick again next update.
+  Running,
+}
+
+pub type Condition = Box<Fn(&server::T, &mob::Mob) -> bool + Send + Sync>;
+pub type Action = Box<Fn(&server::T, &mut mob::Mob) -> Status + Send + Sync>;
+
+pub enum Node {
+  /// Tick children in order until one doesn't succeed.
+  Sequence(Vec<Node>),
+  /// Tick children in order until one doesn't fail.
+  Selector(Vec<Node>),
+  /// Succeed if the check passes, fail otherwise.
+  Condition(Condition),
+  Action(Action),
+}
+
+pub type Tree = Arc<Node>;
+
+pub fn sequence(children: Vec<Node>) -> Node {
+  Node::Sequence(children)
+}
+
+pub fn selector(children: Vec<Node>) -> Node {
+  Node::Selector(children)
+}
+
+pub fn condition<F>(f: F) -> Node where
+  F: Fn(&server::T, &mob::Mob) -> bool + Send + Sync + 'static,
+{
+  Node::Condition(Box::new(f))
+}
+
+pub fn action<F>(f: F) -> Node where
+  F: Fn(&server::T, &mut mob::Mob) -> Status + Send + Sync + 'static,
+{
+  Node::Action(Box::new(f))
+}
+
+/// An action that keeps on running without doing anything.
+pub fn wait() -> Node {
+  action(|_, _| Status::Running)
+}
+
+impl Node {
+  pub fn tick(&self, server: &server::T, mob: &mut mob::Mob) -> Status {
+    match *self {
+      Node::Sequence(ref children) => {
+        sequence_status(children.iter().map(|child| child.tick(server, mob)))
+      },
+      Node::Selector(ref children) => {
+        selector_status(children.iter().map(|child| child.tick(server, mob)))
+      },
+      Node::Condition(ref condition) => condition_status(condition(server, mob)),
+      Node::Action(ref action) => action(server, mob),
+    }
+  }
+}
+
+/// The status of a sequence whose children report `statuses`, in order. Children after the first one that
+/// doesn't succeed aren't ticked.
+fn sequence_status<I: Iterator<Item=Status>>(statuses: I) -> Status {
+  for status in statuses {
+    match status {
+      Status::Success => {},
+      status => return status,
+    }
+  }
+  Status::Success
+}
+
+/// The status of a selector whose children report `statuses`, in order. Children after the first one that
+/// doesn't fail aren't ticked.
+fn selector_status<I: Iterator<Item=Status>>(statuses: I) -> Status {
+  for status in statuses {
+    match status {
+      Status::Failure => {},
+      status => return status,
+    }
+  }
+  Status::Failure
+}
+
+fn condition_status(passed: bool) -> Status {
+  if passed {
+    Status::Success
+  } else {
+    Status::Failure
+  }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Value {
+  Position(Point3<f32>),
+  Bounds(Aabb3<f32>),
+  Symbol(&'static str),
+}
+
+/// Per-mob memory shared between the nodes of its tree.
+pub struct Blackboard {
+  values: fnv_map::T<&'static str, Value>,
+}
+
+pub fn new_blackboard() -> Blackboard {
+  Blackboard {
+    values: fnv_map::new(),
+  }
+}
+
+impl Blackboard {
+  pub fn set(&mut self, key: &'static str, value: Value) {
+    self.values.insert(key, value);
+  }
+
+  pub fn remove(&mut self, key: &'static str) {
+    self.values.remove(key);
+  }
+
+  pub fn position(&self, key: &'static str) -> Option<Point3<f32>> {
+    match self.values.get(key) {
+      Some(&Value::Position(p)) => Some(p),
+      _ => None,
+    }
+  }
+
+  pub fn bounds(&self, key: &'static str) -> Option<Aabb3<f32>> {
+    match self.values.get(key) {
+      Some(&Value::Bounds(b)) => Some(b),
+      _ => None,
+    }
+  }
+
+  pub fn symbol(&self, key: &'static str) -> Option<&'static str> {
+    match self.values.get(key) {
+      Some(&Value::Symbol(s)) => Some(s),
+      _ => None,
+    }
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use cgmath::{Point3, Vector3};
+  use collision::{Aabb3};
+  use std::cell::Cell;
+  use std::collections::VecDeque;
+  use std::sync::Arc;
+
+  use common::protocol;
+  use common::surroundings_loader;
+  use common::voxel;
+
+  use clock;
+  use entity;
+  use mob;
+  use mob_kinds;
+  use perception;
+  use player;
+  use server;
+
+  use super::*;
+  use super::Status::*;
+
+  fn new_server() -> server::T {
+    server::new(clock::fixed(0), server::SessionTokens::Replayed(VecDeque::new()))
+  }
+
+  fn new_mob(server: &server::T, position: Point3<f32>) -> mob::Mob {
+    mob::Mob {
+      position            : position,
+      speed               : Vector3::new(0.0, 0.0, 0.0),
+      kind                : &mob_kinds::GRAZER,
+      behavior            : Arc::new(wait()),
+      blackboard          : new_blackboard(),
+      entity_id           : server.mob_allocator.lock().unwrap().allocate(),
+      physics_id          : server.misc_allocator.lock().unwrap().allocate(),
+      owner_id            : server.owner_allocator.lock().unwrap().allocate(),
+      surroundings_loader : surroundings_loader::new(8, Vec::new()),
+    }
+  }
+
+  /// Add a player whose body has bounds `bounds`.
+  fn add_player(server: &server::T, bounds: Aabb3<f32>) -> entity::id::Player {
+    let entity_id = server.player_allocator.lock().unwrap().allocate();
+    let physics_id = server.misc_allocator.lock().unwrap().allocate();
+    let player = player::new(entity_id, physics_id, protocol::ClientId::default(), &server.owner_allocator);
+    server.physics.lock().unwrap().insert_misc(physics_id, &bounds);
+    server.players.lock().unwrap().insert(entity_id, player);
+    entity_id
+  }
+
+  /// An action that notes it was ticked under `key`, then reports `status`.
+  fn mark(key: &'static str, status: Status) -> Node {
+    action(move |_, mob| {
+      mob.blackboard.set(key, Value::Symbol(key));
+      status
+    })
+  }
+
+  fn ticked(mob: &mob::Mob, key: &'static str) -> bool {
+    mob.blackboard.symbol(key).is_some()
+  }
+
+  /// Feed `statuses` to `combine`, and return its result along with how many children it ticked.
+  fn run<F>(combine: F, statuses: &[Status]) -> (Status, usize) where
+    F: FnOnce(&mut Iterator<Item=Status>) -> Status,
+  {
+    let ticked = Cell::new(0);
+    let status = {
+      let mut statuses = statuses.iter().map(|&status| { ticked.set(ticked.get() + 1); status });
+      let statuses: &mut Iterator<Item=Status> = &mut statuses;
+      combine(statuses)
+    };
+    (status, ticked.get())
+  }
+
+  #[test]
+  fn sequence_succeeds_only_if_every_child_does() {
+    assert_eq!(run(|s| sequence_status(s), &[]), (Success, 0));
+    assert_eq!(run(|s| sequence_status(s), &[Success, Success]), (Success, 2));
+    assert_eq!(run(|s| sequence_status(s), &[Success, Failure, Success]), (Failure, 2));
+    assert_eq!(run(|s| sequence_status(s), &[Running, Failure]), (Running, 1));
+  }
+
+  #[test]
+  fn selector_fails_only_if_every_child_does() {
+    assert_eq!(run(|s| selector_status(s), &[]), (Failure, 0));
+    assert_eq!(run(|s| selector_status(s), &[Failure, Failure]), (Failure, 2));
+    assert_eq!(run(|s| selector_status(s), &[Failure, Success, Failure]), (Success, 2));
+    assert_eq!(run(|s| selector_status(s), &[Running, Success]), (Running, 1));
+  }
+
+  #[test]
+  fn condition_succeeds_when_it_passes() {
+    assert_eq!(condition_status(true), Success);
+    assert_eq!(condition_status(false), Failure);
+  }
+
+  #[test]
+  fn sequences_stop_ticking_at_the_first_child_that_fails() {
+    let server = new_server();
+    let mut mob = new_mob(&server, Point3::new(0.0, 0.0, 0.0));
+    let tree = sequence(vec!(mark("a", Success), condition(|_, _| false), mark("b", Success)));
+
+    assert_eq!(tree.tick(&server, &mut mob), Failure);
+    assert!(ticked(&mob, "a"));
+    assert!(!ticked(&mob, "b"));
+  }
+
+  #[test]
+  fn selectors_stop_ticking_at_the_first_child_that_succeeds() {
+    let server = new_server();
+    let mut mob = new_mob(&server, Point3::new(0.0, 0.0, 0.0));
+    let tree = selector(vec!(mark("a", Failure), condition(|_, _| true), mark("b", Success)));
+
+    assert_eq!(tree.tick(&server, &mut mob), Success);
+    assert!(ticked(&mob, "a"));
+    assert!(!ticked(&mob, "b"));
+  }
+
+  #[test]
+  fn running_children_hold_up_their_parents() {
+    let server = new_server();
+    let mut mob = new_mob(&server, Point3::new(0.0, 0.0, 0.0));
+    let tree =
+      selector(vec!(
+        sequence(vec!(mark("a", Success), wait(), mark("b", Success))),
+        mark("fallback", Success),
+      ));
+
+    assert_eq!(tree.tick(&server, &mut mob), Running);
+    assert!(ticked(&mob, "a"));
+    assert!(!ticked(&mob, "b"));
+    assert!(!ticked(&mob, "fallback"));
+  }
+
+  #[test]
+  fn blackboards_remember_values_by_key_and_kind() {
+    let mut blackboard = new_blackboard();
+    let position = Point3::new(1.0, 2.0, 3.0);
+    blackboard.set("here", Value::Position(position));
+    blackboard.set("mode", Value::Symbol("ready"));
+
+    assert_eq!(blackboard.position("here"), Some(position));
+    assert_eq!(blackboard.symbol("mode"), Some("ready"));
+    // Asking for the wrong kind of value, or a missing key, gets nothing.
+    assert_eq!(blackboard.bounds("here"), None);
+    assert_eq!(blackboard.symbol("there"), None);
+
+    blackboard.set("mode", Value::Symbol("chasing"));
+    assert_eq!(blackboard.symbol("mode"), Some("chasing"));
+
+    blackboard.remove("here");
+    assert_eq!(blackboard.position("here"), None);
+    assert_eq!(blackboard.symbol("mode"), Some("chasing"));
+  }
+
+  #[test]
+  fn nearest_player_picks_the_closest_one() {
+    let server = new_server();
+    let from = Point3::new(0.0, 0.0, 0.0);
+    assert!(perception::nearest_player(&server, &from).is_none());
+
+    let far = Aabb3::new(Point3::new(10.0, 0.0, 0.0), Point3::new(11.0, 2.0, 1.0));
+    let near = Aabb3::new(Point3::new(-4.0, 0.0, 0.0), Point3::new(-3.0, 2.0, 1.0));
+    add_player(&server, far);
+    let near_id = add_player(&server, near);
+
+    let (id, bounds) = perception::nearest_player(&server, &from).unwrap();
+    assert_eq!(id, near_id);
+    assert_eq!(bounds, near);
+  }
+
+  #[test]
+  fn line_of_sight_is_blocked_by_terrain() {
+    let server = new_server();
+    let from = Point3::new(0.5, 0.5, 0.5);
+    let to = Point3::new(8.5, 0.5, 0.5);
+    assert!(perception::line_of_sight(&server, &from, &to));
+
+    {
+      let mut voxels = server.terrain_loader.terrain.voxels.lock().unwrap();
+      voxels.get_mut_or_create(&voxel::bounds::new(4, 0, 0, 0)).data = Some(voxel::Volume(voxel::Material::Stone));
+    }
+    assert!(!perception::line_of_sight(&server, &from, &to));
+    // Terrain beyond the target doesn't get in the way.
+    assert!(perception::line_of_sight(&server, &from, &Point3::new(3.5, 0.5, 0.5)));
+  }
+}
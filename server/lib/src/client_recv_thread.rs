@@ -1,3 +1,4 @@
+use bincode;
 use cgmath::{Point3, Vector3, EuclideanSpace};
 use collision::{Aabb3};
 use rand;
@@ -8,16 +9,20 @@ use std::ops::DerefMut;
 use std::time::Duration;
 use stopwatch;
 
+use common::fnv_set;
+use common::movement;
 use common::protocol;
-use common::socket::SendSocket;
+use common::socket::Peer;
 use common::voxel;
 
 use entity;
 use player;
 use server;
 use server::Client;
+use snapshots;
 use terrain;
 use voxel_data;
+use voxel_subscriptions;
 use update_gaia;
 use update_gaia::LoadDestination;
 
@@ -32,8 +37,11 @@ fn cast(
   let ray;
   {
     let players = server.players.lock().unwrap();
-    let player = players.get(&player_id).unwrap();
-    ray = player.forward_ray();
+    ray =
+      match players.get(&player_id) {
+        None => return None,
+        Some(player) => player.forward_ray(),
+      };
   }
 
   server.terrain_loader.terrain.voxels.lock().unwrap().cast_ray(
@@ -47,95 +55,391 @@ fn cast(
   )
 }
 
+fn apply_input(
+  server: &server::T,
+  client_id: protocol::ClientId,
+  player_id: entity::id::Player,
+  sequence: protocol::InputSequence,
+  input: movement::Input,
+) {
+  let input =
+    match input.clamped() {
+      None => {
+        debug!("Dropping unusable input {:?} from {:?}", input, client_id);
+        server.drop_message(client_id);
+        return
+      },
+      Some(input) => input,
+    };
+
+  let applied =
+    server.players.lock().unwrap()
+    .get_mut(&player_id)
+    .map(|player| player.apply_input(sequence, &input))
+    .unwrap_or(true);
+  if !applied {
+    debug!("Dropping stale input {} from {:?}", sequence, client_id);
+    server.drop_message(client_id);
+  }
+}
+
+/// Whether this player is allowed to brush-edit the terrain right now. If so, start its cooldown.
+fn take_brush_turn(server: &server::T, player_id: entity::id::Player) -> bool {
+  let tick = *server.tick.lock().unwrap();
+  match server.players.lock().unwrap().get_mut(&player_id) {
+    None => false,
+    Some(player) => {
+      let ready =
+        player.last_brush_tick
+        .map(|last| tick.saturating_sub(last) >= server::MIN_BRUSH_INTERVAL_TICKS)
+        .unwrap_or(true);
+      if ready {
+        player.last_brush_tick = Some(tick);
+      }
+      ready
+    },
+  }
+}
+
+/// Strip control characters and surrounding whitespace from a chat message, and cut it down to
+/// `protocol::MAX_CHAT_MESSAGE_BYTES`. Returns None if there's nothing left to say.
+fn clean_chat(text: &str) -> Option<String> {
+  let mut cleaned = String::new();
+  for c in text.trim().chars().filter(|c| !c.is_control()) {
+    if cleaned.len() + c.len_utf8() > protocol::MAX_CHAT_MESSAGE_BYTES {
+      break
+    }
+    cleaned.push(c);
+  }
+
+  if cleaned.is_empty() {
+    None
+  } else {
+    Some(cleaned)
+  }
+}
+
+fn check_init(version: u32, features: &[String]) -> Result<(), protocol::InitRejection> {
+  if version != protocol::VERSION {
+    return Err(
+      protocol::InitRejection::VersionMismatch {
+        server : protocol::VERSION,
+        client : version,
+      }
+    )
+  }
+
+  let missing: Vec<String> =
+    server::REQUIRED_FEATURES.iter()
+    .filter(|&&required| !features.iter().any(|f| f == required))
+    .map(|&required| required.to_owned())
+    .collect();
+  if !missing.is_empty() {
+    return Err(protocol::InitRejection::MissingFeatures(missing))
+  }
+
+  Ok(())
+}
+
+/// Why a client message can't be applied.
+#[derive(Debug)]
+enum Invalid {
+  /// The sending client isn't connected.
+  UnknownClient(protocol::ClientId),
+  /// The message claims to be from a client, but didn't arrive over that client's connection.
+  WrongConnection(protocol::ClientId),
+  /// The message is for a player that doesn't exist (e.g. one that was removed while the message was in flight).
+  UnknownPlayer(protocol::ClientId, entity::id::Player),
+  /// The message is for a player that belongs to someone else.
+  NotOwner(protocol::ClientId, entity::id::Player),
+  /// A `Reattach` to a session that doesn't exist, or with the wrong token.
+  BadToken(protocol::ClientId),
+}
+
+/// Compare session tokens without stopping at the first difference, so how long a rejection takes
+/// doesn't tell a guesser how close they got.
+fn tokens_match(a: protocol::SessionToken, b: protocol::SessionToken) -> bool {
+  let difference = a.0 ^ b.0;
+  let mut folded = 0;
+  for i in 0 .. 8 {
+    folded |= (difference >> (8 * i)) as u8;
+  }
+  folded == 0
+}
+
+/// Check that everything a message refers to exists, and that the sender is allowed to touch it.
+fn validate(server: &server::T, peer: &Peer, update: &protocol::ClientToServer) -> Result<(), Invalid> {
+  if let protocol::ClientToServer::Reattach { client_id, token, .. } = *update {
+    // This arrives over a new connection, so the token vouches for it instead.
+    let is_owner =
+      server.clients.lock().unwrap().get(&client_id)
+      .map(|client| tokens_match(client.session_token, token));
+    return
+      if is_owner == Some(true) {
+        Ok(())
+      } else {
+        Err(Invalid::BadToken(client_id))
+      }
+  }
+
+  let client_id =
+    match update.sender() {
+      None => return Ok(()),
+      Some(client_id) => client_id,
+    };
+
+  match server.clients.lock().unwrap().get(&client_id) {
+    None => return Err(Invalid::UnknownClient(client_id)),
+    Some(client) => {
+      if client.peer.same_connection(peer) == Some(false) {
+        return Err(Invalid::WrongConnection(client_id))
+      }
+    },
+  }
+
+  let player_id =
+    match update.player() {
+      None => return Ok(()),
+      Some(player_id) => player_id,
+    };
+
+  match server.players.lock().unwrap().get(&player_id) {
+    None => Err(Invalid::UnknownPlayer(client_id, player_id)),
+    Some(player) => {
+      if player.owner == client_id {
+        Ok(())
+      } else {
+        Err(Invalid::NotOwner(client_id, player_id))
+      }
+    },
+  }
+}
+
 pub fn apply_client_update<UpdateGaia>(
   server: &server::T,
   update_gaia: &mut UpdateGaia,
+  peer: &Peer,
   update: protocol::ClientToServer,
 ) where
   UpdateGaia: FnMut(update_gaia::Message),
 {
   stopwatch::time("apply_client_update", move || {
+    match validate(server, peer, &update) {
+      Ok(()) => {},
+      Err(Invalid::UnknownClient(client_id)) => {
+        debug!("Dropping message from unknown client {:?}", client_id);
+        server.drop_from(peer);
+        return
+      },
+      Err(Invalid::WrongConnection(client_id)) => {
+        warn!("Dropping message claiming to be from {:?}, which arrived over another connection", client_id);
+        server.drop_from(peer);
+        return
+      },
+      Err(Invalid::UnknownPlayer(client_id, player_id)) => {
+        debug!("Dropping message from {:?} for unknown player {:?}", client_id, player_id);
+        server.drop_message(client_id);
+        return
+      },
+      Err(Invalid::NotOwner(client_id, player_id)) => {
+        warn!("Dropping message from {:?} for player {:?}, which it doesn't own", client_id, player_id);
+        server.drop_message(client_id);
+        return
+      },
+      Err(Invalid::BadToken(client_id)) => {
+        warn!("Rejecting reattach to {:?}", client_id);
+        *server.unattributed_drops.lock().unwrap() += 1;
+        // Only answer over the connection this came in on: an unauthenticated peer shouldn't get us
+        // connecting anywhere. Over other transports, the client gives up when nobody answers.
+        peer.reply_socket(Some(Duration::from_secs(30))).map(|mut socket| {
+          let msg = bincode::serialize(&protocol::ServerToClient::ReattachRejected, bincode::Infinite).unwrap();
+          match socket.write(msg.as_ref()) {
+            Ok(()) => {},
+            Err(err) => warn!("Error sending to client: {:?}", err),
+          }
+        });
+        return
+      },
+    }
+
     match update {
-      protocol::ClientToServer::Init(client_url) => {
-        info!("Sending to {}.", client_url);
+      protocol::ClientToServer::Init { version, url, features } => {
+        info!("Sending to {}.", url);
 
+        let socket =
+          match peer.connect_back(url.as_ref(), Some(Duration::from_secs(30))) {
+            Err(err) => {
+              warn!("Couldn't connect back to {}: {:?}", url, err);
+              return
+            },
+            Ok(socket) => socket,
+          };
         let mut client =
           Client {
-            socket: SendSocket::new(client_url.as_ref(), Some(Duration::from_secs(30))),
+            socket: socket,
+            peer: peer.clone(),
+            players: fnv_set::new(),
+            camera: None,
+            last_heard_ns: server.clock.now(),
+            compress_voxels: features.iter().any(|f| f == protocol::feature::COMPRESSED_VOXELS),
+            bytes_sent: 0,
+            bytes_received: 0,
+            dropped_messages: 0,
+            snapshots: snapshots::new(),
+            voxel_subscriptions: voxel_subscriptions::new(),
+            session_token: server.new_session_token(),
+            detached_since: None,
+            missed_voxels: Vec::new(),
+            missed_too_much: false,
           };
 
-        let client_id = server.client_allocator.lock().unwrap().allocate();
-        client.send(protocol::ServerToClient::LeaseId(client_id));
+        match check_init(version, &features) {
+          Err(rejection) => {
+            warn!("Rejecting client at {}: {:?}", url, rejection);
+            client.send(protocol::ServerToClient::InitRejected(rejection));
+          },
+          Ok(()) => {
+            let client_id = server.client_allocator.lock().unwrap().allocate();
+            let token = client.session_token;
+            client.send(protocol::ServerToClient::LeaseId(client_id, token));
 
-        server.clients.lock().unwrap().insert(client_id, client);
+            server.clients.lock().unwrap().insert(client_id, client);
+          },
+        }
       },
       protocol::ClientToServer::Ping(client_id) => {
         server.clients.lock().unwrap()
           .get_mut(&client_id)
-          .unwrap()
-          .send(protocol::ServerToClient::Ping);
+          .map(|client| client.last_heard_ns = server.clock.now());
+      },
+      protocol::ClientToServer::Reattach { client_id, url, .. } => {
+        let socket =
+          match peer.connect_back(url.as_ref(), Some(Duration::from_secs(30))) {
+            Err(err) => {
+              warn!("Couldn't connect back to {}: {:?}", url, err);
+              return
+            },
+            Ok(socket) => socket,
+          };
+        // Replacing the client's socket closes its old connection.
+        let players: Vec<entity::id::Player> =
+          match server.clients.lock().unwrap().get_mut(&client_id) {
+            None => return,
+            Some(client) => {
+              info!("Client {:?} reattached from {}", client_id, url);
+              client.reattach(socket, peer.clone(), server.clock.now());
+              client.players.iter().cloned().collect()
+            },
+          };
+        // Inventory updates may have been lost along with the old connection.
+        let inventories: Vec<_> = {
+          let all_players = server.players.lock().unwrap();
+          players.iter()
+            .filter_map(|id| all_players.get(id).map(|player| (*id, player.inventory)))
+            .collect()
+        };
+        server.clients.lock().unwrap()
+          .get_mut(&client_id)
+          .map(|client| {
+            for (id, inventory) in inventories {
+              client.send(protocol::ServerToClient::UpdateInventory(id, inventory));
+            }
+          });
       },
       protocol::ClientToServer::AddPlayer(client_id) => {
         let mut player =
           player::new(
             server.player_allocator.lock().unwrap().allocate(),
             server.misc_allocator.lock().unwrap().allocate(),
+            client_id,
             &server.owner_allocator,
           );
 
-        // TODO: shift upward until outside terrain
-        let min = Point3::new(0.0, 64.0, 4.0);
-        let max = min + (&Vector3::new(1.0, 2.0, 1.0));
-        let bounds = Aabb3::new(min, max);
+        let bounds = player::spawn_bounds();
         server.physics.lock().unwrap().insert_misc(player.physics_id, &bounds);
 
         player.position = center(&bounds);
-        player.rotate_lateral(PI / 2.0);
+        player.movement.rotate_lateral(PI / 2.0);
 
         let id = player.entity_id;
         let pos = player.position;
+        let inventory = player.inventory;
 
         server.players.lock().unwrap().insert(id, player);
 
-        let mut clients = server.clients.lock().unwrap();
-        let client = clients.get_mut(&client_id).unwrap();
-        client.send(
-          protocol::ServerToClient::PlayerAdded(id, pos)
-        );
-      },
-      protocol::ClientToServer::StartJump(player_id) => {
-        let mut players = server.players.lock().unwrap();
-        let player = players.get_mut(&player_id).unwrap();
-        if !player.is_jumping {
-          player.is_jumping = true;
-          // this 0.3 is duplicated in a few places
-          player.accel.y = player.accel.y + 0.3;
+        let added =
+          match server.clients.lock().unwrap().get_mut(&client_id) {
+            None => false,
+            Some(client) => {
+              client.players.insert(id);
+              client.send(
+                protocol::ServerToClient::PlayerAdded(id, pos)
+              );
+              client.send(
+                protocol::ServerToClient::UpdateInventory(id, inventory)
+              );
+              true
+            },
+          };
+        if !added {
+          // The client left while we were adding its player.
+          server.remove_player(id);
         }
       },
-      protocol::ClientToServer::StopJump(player_id) => {
-        let mut players = server.players.lock().unwrap();
-        let player = players.get_mut(&player_id).unwrap();
-        if player.is_jumping {
-          player.is_jumping = false;
-          // this 0.3 is duplicated in a few places
-          player.accel.y = player.accel.y - 0.3;
+      protocol::ClientToServer::RemovePlayer(_, player_id) => {
+        server.remove_player(player_id);
+      },
+      protocol::ClientToServer::Leave(client_id) => {
+        server.remove_client(client_id);
+      },
+      protocol::ClientToServer::MoveCamera(client_id, position) => {
+        if !(position.x.is_finite() && position.y.is_finite() && position.z.is_finite()) {
+          debug!("Dropping unusable camera position {:?} from {:?}", position, client_id);
+          server.drop_message(client_id);
+          return
         }
+        server.clients.lock().unwrap()
+          .get_mut(&client_id)
+          .map(|client| client.camera = Some(position));
+      },
+      protocol::ClientToServer::Chat(_, player_id, text) => {
+        clean_chat(&text).map(|text| {
+          info!("Chat from {:?}: {}", player_id, text);
+          for (_, client) in server.clients.lock().unwrap().iter_mut() {
+            client.send(protocol::ServerToClient::ChatMessage(player_id, text.clone()));
+          }
+        });
       },
-      protocol::ClientToServer::Walk(player_id, v) => {
-        let mut players = server.players.lock().unwrap();
-        let mut player = players.get_mut(&player_id).unwrap();
-        player.walk(v);
+      protocol::ClientToServer::AckSnapshot(client_id, sequence) => {
+        server.clients.lock().unwrap()
+          .get_mut(&client_id)
+          .map(|client| client.snapshots.ack(sequence));
       },
-      protocol::ClientToServer::RotatePlayer(player_id, v) => {
-        let mut players = server.players.lock().unwrap();
-        let mut player = players.get_mut(&player_id).unwrap();
-        player.rotate_lateral(v.x);
-        player.rotate_vertical(v.y);
+      protocol::ClientToServer::SetInputState(client_id, player_id, state, sequence) => {
+        apply_input(server, client_id, player_id, sequence, movement::Input::SetState(state));
       },
-      protocol::ClientToServer::RequestVoxels { time_requested_ns, client_id, voxels } => {
+      protocol::ClientToServer::RotatePlayer(client_id, player_id, v, sequence) => {
+        apply_input(server, client_id, player_id, sequence, movement::Input::Rotate(v));
+      },
+      protocol::ClientToServer::RequestVoxels { time_requested_ns, client_id, voxels, region: region_id } => {
+        voxel_subscriptions::region_of(&voxels).map(|region| {
+          server.clients.lock().unwrap()
+            .get_mut(&client_id)
+            .map(|client| client.voxel_subscriptions.subscribe(region_id, &region));
+        });
         update_gaia(update_gaia::Message::Load(time_requested_ns, voxels, LoadDestination::Client(client_id)));
       },
-      protocol::ClientToServer::Add(player_id) => {
+      protocol::ClientToServer::UnsubscribeVoxels { client_id, region } => {
+        server.clients.lock().unwrap()
+          .get_mut(&client_id)
+          .map(|client| client.voxel_subscriptions.unsubscribe(region));
+      },
+      protocol::ClientToServer::Add(client_id, player_id) => {
+        if !take_brush_turn(server, player_id) {
+          server.drop_message(client_id);
+          return
+        }
+
         let bounds = cast(server, player_id);
 
         bounds.map(|bounds| {
@@ -194,10 +498,15 @@ pub fn apply_client_update<UpdateGaia>(
               min_lg_size: 0,
             };
 
-          update_gaia(update_gaia::Message::Brush(brush));
+          update_gaia(update_gaia::Message::Brush(player_id, brush));
         });
       },
-      protocol::ClientToServer::Remove(player_id) => {
+      protocol::ClientToServer::Remove(client_id, player_id) => {
+        if !take_brush_turn(server, player_id) {
+          server.drop_message(client_id);
+          return
+        }
+
         let bounds = cast(server, player_id);
 
         bounds.map(|bounds| {
@@ -232,7 +541,7 @@ pub fn apply_client_update<UpdateGaia>(
               min_lg_size: 0,
             };
           let brush: voxel_data::brush::T<Box<voxel_data::mosaic::T<voxel::Material> + Send>> = brush;
-          update_gaia(update_gaia::Message::Brush(brush));
+          update_gaia(update_gaia::Message::Brush(player_id, brush));
         });
       },
     };
@@ -0,0 +1,40 @@
+//! The server's notion of the current time. Replays use a fixed clock, so they come out the same every time.
+
+use std::sync::Mutex;
+use time;
+
+#[allow(missing_docs)]
+pub enum T {
+  /// The system clock.
+  Real,
+  /// A clock that only moves when it's told to.
+  Fixed(Mutex<u64>),
+}
+
+#[allow(missing_docs)]
+pub fn real() -> T {
+  T::Real
+}
+
+#[allow(missing_docs)]
+pub fn fixed(now_ns: u64) -> T {
+  T::Fixed(Mutex::new(now_ns))
+}
+
+impl T {
+  /// The current time, in ns.
+  pub fn now(&self) -> u64 {
+    match *self {
+      T::Real => time::precise_time_ns(),
+      T::Fixed(ref now) => *now.lock().unwrap(),
+    }
+  }
+
+  /// Move a fixed clock forward. Real clocks move by themselves, and ignore this.
+  pub fn advance(&self, ns: u64) {
+    match *self {
+      T::Real => {},
+      T::Fixed(ref now) => *now.lock().unwrap() += ns,
+    }
+  }
+}
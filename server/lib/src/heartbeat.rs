@@ -0,0 +1,45 @@
+//! Ping clients, detach the ones that stop answering, and disconnect the ones that don't come back.
+
+use stopwatch;
+
+use common::protocol;
+
+use server;
+
+/// Send pings if they're due, detach clients that have timed out, and disconnect clients whose grace period is up.
+pub fn heartbeat(server: &server::T) {
+  stopwatch::time("heartbeat", || {
+    let now = server.clock.now();
+
+    if server.ping_timer.lock().unwrap().update(now) > 0 {
+      for (_, client) in server.clients.lock().unwrap().iter_mut() {
+        client.send(protocol::ServerToClient::Ping);
+      }
+    }
+
+    let mut expired = Vec::new();
+    for (&client_id, client) in server.clients.lock().unwrap().iter_mut() {
+      match client.detached_since {
+        None => {
+          if now.saturating_sub(client.last_heard_ns) > server.client_timeout_ns {
+            warn!("Client {:?} timed out; holding its session for it to reattach", client_id);
+            client.detach(now);
+          }
+        },
+        Some(detached_since) => {
+          if client.missed_too_much {
+            warn!("Client {:?} missed too many terrain updates to catch up", client_id);
+            expired.push(client_id);
+          } else if now.saturating_sub(detached_since) > server.reattach_grace_ns {
+            warn!("Client {:?} didn't reattach in time", client_id);
+            expired.push(client_id);
+          }
+        },
+      }
+    }
+
+    for client_id in expired {
+      server.remove_client(client_id);
+    }
+  })
+}
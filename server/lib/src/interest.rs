@@ -0,0 +1,106 @@
+//! Decide which entity updates each client gets to hear about.
+
+use cgmath::{Point3, EuclideanSpace, InnerSpace};
+use collision::{Aabb3};
+use stopwatch;
+
+use common::fnv_map;
+use common::mob_kind;
+use common::protocol;
+use common::snapshot;
+
+use entity;
+use server;
+
+/// A player's bounds and (lateral, vertical) rotation this tick.
+pub type PlayerUpdate = (entity::id::Player, Aabb3<f32>, (f32, f32));
+/// A mob's bounds this tick, and what kind it is.
+pub type MobUpdate = (entity::id::Mob, Aabb3<f32>, mob_kind::T);
+
+fn center(bounds: &Aabb3<f32>) -> Point3<f32> {
+  (bounds.min + bounds.max.to_vec()) * 0.5
+}
+
+/// Send each client a snapshot of the entities within `server.entity_update_radius` of any of its players
+/// or its spectator camera, along with the collisions involving players it can see.
+pub fn send_entity_updates(
+  server     : &server::T,
+  players    : &[PlayerUpdate],
+  mobs       : &[MobUpdate],
+  collisions : &[protocol::Collision],
+) {
+  stopwatch::time("send_entity_updates", || {
+    let mut player_positions = fnv_map::new();
+    for &(id, bounds, _) in players {
+      player_positions.insert(id, center(&bounds));
+    }
+
+    let players: Vec<(entity::id::Player, Point3<f32>, snapshot::Player)> =
+      players.iter()
+      .map(|&(id, bounds, (lateral, vertical))| {
+        let state =
+          snapshot::Player {
+            bounds      : snapshot::Bounds::quantize(&bounds),
+            orientation : snapshot::Orientation::quantize(lateral, vertical),
+          };
+        (id, center(&bounds), state)
+      })
+      .collect();
+    let mobs: Vec<(entity::id::Mob, Point3<f32>, snapshot::Mob)> =
+      mobs.iter()
+      .map(|&(id, bounds, kind)| {
+        let state =
+          snapshot::Mob {
+            bounds : snapshot::Bounds::quantize(&bounds),
+            kind   : kind,
+          };
+        (id, center(&bounds), state)
+      })
+      .collect();
+
+    let radius2 = server.entity_update_radius * server.entity_update_radius;
+    let time_ns = server.clock.now();
+
+    for (_, client) in server.clients.lock().unwrap().iter_mut() {
+      let centers: Vec<Point3<f32>> =
+        client.players.iter()
+        .filter_map(|id| player_positions.get(id))
+        .cloned()
+        .chain(client.camera)
+        .collect();
+      let in_range = |p: &Point3<f32>| {
+        centers.iter().any(|c| (*p - *c).magnitude2() <= radius2)
+      };
+
+      let mut view = snapshot::empty();
+      for &(id, ref p, state) in &players {
+        if in_range(p) {
+          view.players.insert(id, state);
+        }
+      }
+      for &(id, ref p, state) in &mobs {
+        if in_range(p) {
+          view.mobs.insert(id, state);
+        }
+      }
+
+      let mut out = Vec::new();
+      for collision in collisions {
+        let player_id =
+          match *collision {
+            protocol::Collision::PlayerTerrain(id) => id,
+            protocol::Collision::PlayerMisc(id) => id,
+          };
+        if view.players.contains_key(&player_id) {
+          out.push(protocol::ServerToClient::Collision(collision.clone()));
+        }
+      }
+
+      let snapshot = client.snapshots.next(view, time_ns);
+      client.send(protocol::ServerToClient::WorldSnapshot(snapshot));
+      for msg in out {
+        client.send(msg);
+      }
+    }
+  })
+}
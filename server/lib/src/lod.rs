@@ -4,6 +4,7 @@ use std::cmp::Ordering;
 use std::ops::Add;
 
 use common::fnv_map;
+use common::fnv_set;
 use common::voxel;
 
 pub use self::T::*;
@@ -37,6 +38,8 @@ impl Ord for T {
 /// Data structure to keep track of a position's owners, requested LODs, and current T.
 pub struct Map {
   loaded: fnv_map::T<voxel::bounds::T, BlockLoadState>,
+  /// Full loads that owners have asked for, but that haven't arrived yet.
+  requested: fnv_set::T<(voxel::bounds::T, OwnerId)>,
 }
 
 impl Map {
@@ -44,9 +47,20 @@ impl Map {
   pub fn new() -> Map {
     Map {
       loaded: fnv_map::new(),
+      requested: fnv_set::new(),
     }
   }
 
+  /// Note that `owner` is waiting on a full load of `position`.
+  pub fn request(&mut self, position: voxel::bounds::T, owner: OwnerId) {
+    self.requested.insert((position, owner));
+  }
+
+  /// Stop waiting on `owner`'s full load of `position`. Returns whether it was still wanted.
+  pub fn cancel_request(&mut self, position: voxel::bounds::T, owner: OwnerId) -> bool {
+    self.requested.remove(&(position, owner))
+  }
+
   /// Find out what T is up at a `position`.
   pub fn get<'a>(
     &'a self,
@@ -60,6 +74,21 @@ impl Map {
     })
   }
 
+  /// Find all the positions where `owner` holds a handle, or is waiting on a full load.
+  pub fn owned_by(&self, owner: OwnerId) -> Vec<voxel::bounds::T> {
+    let mut positions: fnv_set::T<voxel::bounds::T> =
+      self.loaded.iter()
+      .filter(|&(_, bls)| bls.owner_lods.iter().any(|&(o, _)| o == owner))
+      .map(|(position, _)| *position)
+      .collect();
+    positions.extend(
+      self.requested.iter()
+      .filter(|&&(_, o)| o == owner)
+      .map(|&(position, _)| position)
+    );
+    positions.into_iter().collect()
+  }
+
   // TODO: Can probably get rid of the LODChange returns; we only assert with em.
 
   /// Acquire/update an owner's handle in `position`.
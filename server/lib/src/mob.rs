@@ -1,20 +1,71 @@
 use cgmath::{Point3, Vector3};
 
+use common::mob_kind;
 use common::surroundings_loader;
 
+use behavior;
 use entity;
 use lod;
 use server;
+use terrain;
 
-pub type Behavior = fn(&server::T, &mut Mob);
+/// When a kind of mob comes out.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TimeOfDay {
+  Day,
+  Night,
+  Always,
+}
+
+/// Where and when a kind of mob spawns.
+pub struct Spawn {
+  /// The biomes it lives in.
+  pub biomes         : &'static [terrain::biome::Kind],
+  /// The lowest and highest cells it can stand in.
+  pub heights        : (i32, i32),
+  pub time_of_day    : TimeOfDay,
+  /// The most mobs of this kind in the world at once.
+  pub max_population : usize,
+}
+
+/// A kind of mob, and how it looks and behaves.
+pub struct Kind {
+  pub id             : mob_kind::T,
+  pub name           : &'static str,
+  /// Width, height and depth.
+  pub size           : [f32; 3],
+  /// How far it walks per tick.
+  pub speed          : f32,
+  /// How much it hurts players it touches.
+  pub contact_damage : u32,
+  /// Build the behavior tree for a new mob of this kind.
+  pub behavior       : fn() -> behavior::Node,
+  pub spawn          : Spawn,
+}
 
 pub struct Mob {
   pub position            : Point3<f32>,
   pub speed               : Vector3<f32>,
-  pub behavior            : Behavior,
+  pub kind                : &'static Kind,
+  pub behavior            : behavior::Tree,
+  pub blackboard          : behavior::Blackboard,
 
   pub entity_id           : entity::id::Mob,
   pub physics_id          : entity::id::Misc,
   pub owner_id            : lod::OwnerId,
   pub surroundings_loader : surroundings_loader::T,
 }
+
+impl Mob {
+  /// The point at the bottom middle of the mob, where it stands.
+  pub fn feet(&self) -> Point3<f32> {
+    Point3::new(self.position.x, self.position.y - self.kind.size[1] / 2.0, self.position.z)
+  }
+
+  /// Release this mob's hold on the world: its terrain loads, its physics body and its path.
+  pub fn destroy(&self, server: &server::T) {
+    server.terrain_loader.unload_owner(&server.physics, self.owner_id);
+    server.physics.lock().unwrap().remove_misc(self.physics_id);
+    server.paths.lock().unwrap().remove(self.entity_id);
+  }
+}
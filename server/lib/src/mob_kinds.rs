@@ -0,0 +1,219 @@
+//! The kinds of mobs in the world, and how each behaves. Add new kinds here, and to `ALL`.
+
+use cgmath::{Point3, Vector3, EuclideanSpace, InnerSpace};
+use collision::{Aabb3};
+use rand::Rng;
+
+use common::mob_kind;
+
+use behavior;
+use behavior::{Status, Value, sequence, selector, condition, action};
+use mob;
+use pathfinding;
+use perception;
+use server;
+use terrain::biome;
+
+/// Plays tag: once a player touches it, it waits for them to run off, then chases them down.
+pub static TAG: mob::Kind =
+  mob::Kind {
+    id             : mob_kind::T::Tag,
+    name           : "tag",
+    size           : [1.0, 2.0, 1.0],
+    speed          : 0.25,
+    contact_damage : 5,
+    behavior       : tag,
+    spawn          :
+      mob::Spawn {
+        biomes         : &[biome::Kind::Hills],
+        heights        : (-512, 512),
+        time_of_day    : mob::TimeOfDay::Always,
+        max_population : 1,
+      },
+  };
+
+/// Wanders the grassy hills during the day.
+pub static GRAZER: mob::Kind =
+  mob::Kind {
+    id             : mob_kind::T::Grazer,
+    name           : "grazer",
+    size           : [0.8, 1.0, 0.8],
+    speed          : 0.1,
+    contact_damage : 0,
+    behavior       : graze,
+    spawn          :
+      mob::Spawn {
+        biomes         : &[biome::Kind::Hills],
+        heights        : (0, 512),
+        time_of_day    : mob::TimeOfDay::Day,
+        max_population : 12,
+      },
+  };
+
+/// Comes out of the low ground at night, and hunts any player it can see.
+pub static LURKER: mob::Kind =
+  mob::Kind {
+    id             : mob_kind::T::Lurker,
+    name           : "lurker",
+    size           : [0.9, 1.8, 0.9],
+    speed          : 0.2,
+    contact_damage : 20,
+    behavior       : lurk,
+    spawn          :
+      mob::Spawn {
+        biomes         : &[biome::Kind::Hills, biome::Kind::Mountains],
+        heights        : (-512, 48),
+        time_of_day    : mob::TimeOfDay::Night,
+        max_population : 6,
+      },
+  };
+
+/// Every kind of mob that can spawn.
+pub static ALL: &'static [&'static mob::Kind] = &[&TAG, &GRAZER, &LURKER];
+
+const MODE: &'static str = "mode";
+const PLAYER: &'static str = "player";
+const DESTINATION: &'static str = "destination";
+
+const READY: &'static str = "ready";
+const TAGGED: &'static str = "tagged";
+const CHASING: &'static str = "chasing";
+const RESETTING: &'static str = "resetting";
+
+/// How far a grazer wanders in one go, along each axis.
+const WANDER_DISTANCE: f32 = 8.0;
+
+fn center(bounds: &Aabb3<f32>) -> Point3<f32> {
+  (bounds.min + bounds.max.to_vec()) * 0.5
+}
+
+fn feet(bounds: &Aabb3<f32>) -> Point3<f32> {
+  let center = center(bounds);
+  Point3::new(center.x, bounds.min.y, center.z)
+}
+
+fn in_mode(mode: &'static str) -> behavior::Node {
+  condition(move |_, mob| mob.blackboard.symbol(MODE).unwrap_or(READY) == mode)
+}
+
+fn enter(mode: &'static str) -> behavior::Node {
+  action(move |_, mob| {
+    mob.blackboard.set(MODE, Value::Symbol(mode));
+    Status::Success
+  })
+}
+
+/// How far the nearest player is from the mob, as seen on the last tick, if there was one.
+fn to_player(mob: &mob::Mob) -> Option<Vector3<f32>> {
+  mob.blackboard.bounds(PLAYER).map(|bounds| center(&bounds) - mob.position)
+}
+
+fn player_within(distance: f32) -> behavior::Node {
+  condition(move |_, mob| to_player(mob).map_or(false, |v| v.magnitude() < distance))
+}
+
+fn player_beyond(distance: f32) -> behavior::Node {
+  condition(move |_, mob| to_player(mob).map_or(false, |v| v.magnitude() > distance))
+}
+
+/// Remember where the nearest player is. Fails if there's nobody around.
+fn look_for_player() -> behavior::Node {
+  action(|server: &server::T, mob: &mut mob::Mob| {
+    match perception::nearest_player(server, &mob.position) {
+      None => {
+        mob.blackboard.remove(PLAYER);
+        Status::Failure
+      },
+      Some((_, bounds)) => {
+        mob.blackboard.set(PLAYER, Value::Bounds(bounds));
+        Status::Success
+      },
+    }
+  })
+}
+
+fn player_in_sight() -> behavior::Node {
+  condition(|server: &server::T, mob: &mob::Mob| {
+    match mob.blackboard.bounds(PLAYER) {
+      None => false,
+      Some(player) => perception::line_of_sight(server, &mob.position, &center(&player)),
+    }
+  })
+}
+
+fn chase() -> behavior::Node {
+  action(|server: &server::T, mob: &mut mob::Mob| {
+    match mob.blackboard.bounds(PLAYER) {
+      None => Status::Failure,
+      Some(player) => pathfinding::follow(server, mob, &feet(&player)),
+    }
+  })
+}
+
+fn stop() -> behavior::Node {
+  action(|_, mob: &mut mob::Mob| {
+    mob.speed = Vector3::new(0.0, 0.0, 0.0);
+    Status::Success
+  })
+}
+
+fn tag() -> behavior::Node {
+  selector(vec!(
+    sequence(vec!(
+      look_for_player(),
+      selector(vec!(
+        sequence(vec!(in_mode(READY), player_within(2.0), enter(TAGGED))),
+        // Only give chase once the player can be seen; a player who runs off behind a hill stays tagged
+        // until they come back into view.
+        sequence(vec!(in_mode(TAGGED), player_beyond(8.0), player_in_sight(), enter(CHASING))),
+        sequence(vec!(
+          in_mode(CHASING),
+          selector(vec!(
+            sequence(vec!(player_within(2.0), stop(), enter(RESETTING))),
+            chase(),
+          )),
+        )),
+        sequence(vec!(in_mode(RESETTING), condition(|_, mob| to_player(mob).map_or(false, |v| v.magnitude() >= 2.0)), enter(READY))),
+        // Nothing to do yet.
+        behavior::wait(),
+      )),
+    )),
+    // Nobody to play with.
+    enter(READY),
+  ))
+}
+
+fn graze() -> behavior::Node {
+  action(|server: &server::T, mob: &mut mob::Mob| {
+    let destination =
+      match mob.blackboard.position(DESTINATION) {
+        Some(destination) => destination,
+        None => {
+          let (dx, dz) = {
+            let mut rng = server.rng.lock().unwrap();
+            (rng.gen_range(-WANDER_DISTANCE, WANDER_DISTANCE), rng.gen_range(-WANDER_DISTANCE, WANDER_DISTANCE))
+          };
+          let destination = mob.feet() + Vector3::new(dx, 0.0, dz);
+          mob.blackboard.set(DESTINATION, Value::Position(destination));
+          destination
+        },
+      };
+    match pathfinding::follow(server, mob, &destination) {
+      Status::Running => Status::Running,
+      _ => {
+        // Got there, or can't; pick somewhere else next time.
+        mob.blackboard.remove(DESTINATION);
+        mob.speed.x = 0.0;
+        mob.speed.z = 0.0;
+        Status::Success
+      },
+    }
+  })
+}
+
+fn lurk() -> behavior::Node {
+  selector(vec!(
+    sequence(vec!(look_for_player(), player_within(16.0), player_in_sight(), chase())),
+    sequence(vec!(stop(), behavior::wait())),
+  ))
+}
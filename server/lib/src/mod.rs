@@ -12,7 +12,6 @@ extern crate collision;
 extern crate common;
 #[macro_use]
 extern crate log;
-extern crate nanomsg;
 extern crate num;
 extern crate rand;
 extern crate stopwatch;
@@ -22,20 +21,31 @@ extern crate thread_scoped;
 extern crate time;
 extern crate voxel_data;
 
+mod behavior;
 mod client_recv_thread;
+pub mod clock;
 mod entity;
+mod heartbeat;
 mod in_progress_terrain;
-mod init_mobs;
+mod interest;
 mod lod;
 mod mob;
+mod mob_kinds;
 mod octree;
+mod pathfinding;
+mod perception;
 mod physics;
 mod player;
+mod recording;
 mod run;
+mod snapshots;
+mod spawner;
 pub mod server;
 mod sun;
 mod terrain_loader;
 pub mod update_gaia;
 mod update_world;
+mod voxel_subscriptions;
 
+pub use recording::replay;
 pub use run::run;
@@ -246,6 +246,32 @@ impl<V: Debug + Copy + Eq + PartialOrd> Octree<V> {
     }
   }
 
+  // Find all the objects overlapping the bounds provided, other than `self_v`.
+  // Objects that were split across cells may show up once per piece.
+  pub fn intersecting(&self, bounds: &Aabb3<f32>, self_v: Option<V>) -> Vec<(Aabb3<f32>, V)> {
+    let mut r = Vec::new();
+    self.intersecting_into(bounds, self_v, &mut r);
+    r
+  }
+
+  fn intersecting_into(&self, bounds: &Aabb3<f32>, self_v: Option<V>, r: &mut Vec<(Aabb3<f32>, V)>) {
+    match self.contents {
+      OctreeContents::Leaf(ref vs) => {
+        r.extend(
+          vs.iter()
+          .filter(|&&(ref bs, ref v)| Some(*v) != self_v && aabb_overlap(bounds, bs))
+          .cloned()
+        );
+      },
+      OctreeContents::Branch(ref b) => {
+        let mid = middle(&self.bounds, self.dimension);
+        let (low_bounds, high_bounds) = split(mid, self.dimension, bounds);
+        low_bounds.map(|bs| b.low_tree.intersecting_into(&bs, self_v, r));
+        high_bounds.map(|bs| b.high_tree.intersecting_into(&bs, self_v, r));
+      },
+    }
+  }
+
   // like insert, but before recursing downward, we recurse up the parents
   // until the bounds provided are inside the tree.
   fn insert_from(&mut self, bounds: &Aabb3<f32>, v: V) {
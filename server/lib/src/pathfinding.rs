@@ -0,0 +1,420 @@
+//! Find routes for mobs through walkable voxels, and keep them up to date as the terrain changes.
+//! A mob occupies a column of two cells, and stands in a cell when the cell below it is solid
+//! (or the cell itself holds walkable ground). Only terrain that's already loaded is searched;
+//! anything else is treated as impassable.
+
+use cgmath::{Point3, Vector3, InnerSpace};
+use collision::{Aabb3};
+use std;
+use std::cmp::Ordering;
+use std::collections::{BinaryHeap, VecDeque};
+
+use common::fnv_map;
+use common::movement;
+use common::voxel;
+
+use behavior;
+use entity;
+use mob;
+use server;
+
+/// The most cells a single search will expand before settling for the closest cell it found.
+const MAX_EXPANDED: usize = 2048;
+/// The furthest a mob will drop down in a single move.
+const MAX_DROP: i32 = 3;
+/// Re-route a path once the goal has moved this many cells from where the path leads.
+const REPLAN_DISTANCE: i32 = 2;
+/// How many ticks a mob waits before searching again for a goal it couldn't find a way toward.
+/// This doubles with each failure in a row, up to `MAX_RETRY_TICKS`.
+const MIN_RETRY_TICKS: u64 = 4;
+/// The longest a mob waits before searching again for a goal it couldn't find a way toward.
+const MAX_RETRY_TICKS: u64 = 8 * movement::TICKS_PER_SECOND;
+
+/// The cell that a body with its feet at `feet` is standing in.
+pub fn cell_of(feet: &Point3<f32>) -> Point3<i32> {
+  // Nudge up a little, so resting exactly on a boundary counts as above it.
+  Point3::new(
+    feet.x.floor() as i32,
+    (feet.y + 0.01).floor() as i32,
+    feet.z.floor() as i32,
+  )
+}
+
+/// The terrain as the pathfinder sees it.
+struct Cells<'a> {
+  voxels : &'a voxel::tree::T,
+}
+
+impl<'a> Cells<'a> {
+  /// The voxel in this cell, or None if it hasn't been loaded.
+  fn get(&self, p: &Point3<i32>) -> Option<voxel::T> {
+    self.voxels.get(&voxel::bounds::new(p.x, p.y, p.z, 0)).cloned()
+  }
+
+  /// Whether a body can be in this cell.
+  fn is_open(&self, p: &Point3<i32>) -> bool {
+    match self.get(p) {
+      None => false,
+      Some(voxel::Volume(voxel::Material::Empty)) => true,
+      Some(voxel::Volume(_)) => false,
+      // Ground that can be stood on can be walked over; steeper slopes are walls.
+      Some(voxel::Surface(surface)) => {
+        let normal = surface.normal.to_float_normal();
+        normal.y >= movement::MIN_GROUND_NORMAL_Y * normal.magnitude()
+      },
+    }
+  }
+
+  /// Whether a body can stand in this cell.
+  fn is_standable(&self, p: &Point3<i32>) -> bool {
+    self.ground(p).is_some()
+  }
+
+  /// What a body standing in this cell stands on, if it can stand there.
+  fn ground(&self, p: &Point3<i32>) -> Option<voxel::Material> {
+    let up = Vector3::new(0, 1, 0);
+    if !self.is_open(p) || !self.is_open(&(*p + up)) {
+      return None
+    }
+    match self.get(p) {
+      Some(voxel::Surface(surface)) => Some(surface.corner),
+      _ => {
+        match self.get(&(*p + -up)) {
+          None => None,
+          Some(voxel::Volume(voxel::Material::Empty)) => None,
+          Some(voxel::Volume(material)) => Some(material),
+          Some(voxel::Surface(surface)) => Some(surface.corner),
+        }
+      },
+    }
+  }
+
+  /// The cells a body standing in `p` can move to, and what each move costs.
+  fn neighbors(&self, p: &Point3<i32>) -> Vec<(Point3<i32>, u32)> {
+    let up = Vector3::new(0, 1, 0);
+    let max_step = movement::MAX_STEP_HEIGHT.floor() as i32;
+    let mut neighbors = Vec::new();
+    for &(dx, dz) in &[(1, 0), (-1, 0), (0, 1), (0, -1)] {
+      let side = *p + Vector3::new(dx, 0, dz);
+      let climbs = (1 .. max_step + 1).map(|dy| (dy, (2 .. dy + 2).map(|j| *p + up * j).collect::<Vec<_>>()));
+      let drops = (1 .. MAX_DROP + 1).map(|dy| (-dy, (2 - dy .. 2).map(|j| side + up * j).collect::<Vec<_>>()));
+      let moves = Some((0, Vec::new())).into_iter().chain(climbs).chain(drops);
+      for (dy, clearance) in moves {
+        let to = side + up * dy;
+        if !clearance.iter().all(|c| self.is_open(c)) {
+          continue
+        }
+        if self.is_standable(&to) {
+          neighbors.push((to, 1 + dy.abs() as u32));
+          break
+        }
+      }
+    }
+    neighbors
+  }
+}
+
+/// What a body standing in `cell` would stand on, or None if it can't stand there (or it isn't loaded).
+pub fn ground(voxels: &voxel::tree::T, cell: &Point3<i32>) -> Option<voxel::Material> {
+  Cells { voxels: voxels }.ground(cell)
+}
+
+/// A lower bound on the cost of getting from `from` to `to`.
+fn heuristic(from: &Point3<i32>, to: &Point3<i32>) -> u32 {
+  ((to.x - from.x).abs() + (to.z - from.z).abs()) as u32
+}
+
+/// How many cells apart `a` and `b` are, counting height too.
+fn distance(a: &Point3<i32>, b: &Point3<i32>) -> i32 {
+  heuristic(a, b) as i32 + (a.y - b.y).abs()
+}
+
+#[derive(PartialEq, Eq)]
+struct Open {
+  estimate : u32,
+  cost     : u32,
+  cell     : Point3<i32>,
+}
+
+impl Ord for Open {
+  fn cmp(&self, other: &Open) -> Ordering {
+    // `BinaryHeap` pops the largest, and we want the cheapest. Among equally good estimates, prefer
+    // whichever has come further, and break any remaining ties by cell, so this agrees with `Eq`.
+    other.estimate.cmp(&self.estimate)
+      .then_with(|| self.cost.cmp(&other.cost))
+      .then_with(|| (self.cell.x, self.cell.y, self.cell.z).cmp(&(other.cell.x, other.cell.y, other.cell.z)))
+  }
+}
+
+impl PartialOrd for Open {
+  fn partial_cmp(&self, other: &Open) -> Option<Ordering> {
+    Some(self.cmp(other))
+  }
+}
+
+/// A* search from `from` to `to`. If `to` can't be reached (or is too far to search for),
+/// this finds a way to the closest cell it could reach instead.
+/// Returns the cells to walk through, not including `from`, or None if there's nowhere better to go.
+pub fn find(voxels: &voxel::tree::T, from: &Point3<i32>, to: &Point3<i32>) -> Option<VecDeque<Point3<i32>>> {
+  let cells = Cells { voxels: voxels };
+  let mut came_from = fnv_map::new();
+  let mut costs = fnv_map::new();
+  let mut open = BinaryHeap::new();
+  costs.insert(*from, 0);
+  open.push(Open { estimate: heuristic(from, to), cost: 0, cell: *from });
+
+  let mut closest = (heuristic(from, to), *from);
+  let mut expanded = 0;
+  while let Some(Open { cost, cell, .. }) = open.pop() {
+    if cost > costs[&cell] {
+      // We've already found a cheaper way here.
+      continue
+    }
+    let estimate = heuristic(&cell, to);
+    if estimate < closest.0 || cell == *to {
+      closest = (estimate, cell);
+    }
+    if cell == *to {
+      break
+    }
+    expanded += 1;
+    if expanded > MAX_EXPANDED {
+      break
+    }
+
+    for (next, step_cost) in cells.neighbors(&cell) {
+      let next_cost = cost + step_cost;
+      let is_better = costs.get(&next).map(|&c| next_cost < c).unwrap_or(true);
+      if is_better {
+        costs.insert(next, next_cost);
+        came_from.insert(next, cell);
+        open.push(Open { estimate: next_cost + heuristic(&next, to), cost: next_cost, cell: next });
+      }
+    }
+  }
+
+  let (_, end) = closest;
+  if end == *from {
+    return None
+  }
+  let mut path = VecDeque::new();
+  let mut cell = end;
+  while cell != *from {
+    path.push_front(cell);
+    cell = came_from[&cell];
+  }
+  Some(path)
+}
+
+/// A mob's planned route.
+pub struct Path {
+  /// The cells left to walk through, nearest first.
+  pub waypoints : VecDeque<Point3<i32>>,
+  /// Where the path was planned to.
+  pub goal      : Point3<i32>,
+  /// If the terrain changed under the path, `waypoints` only holds the part before the change,
+  /// and the rest needs to be planned again.
+  pub is_stale  : bool,
+}
+
+/// A goal that a mob couldn't find a way toward.
+struct Failure {
+  goal       : Point3<i32>,
+  /// Don't search for `goal` again before this tick.
+  retry_tick : u64,
+  /// How long we waited this time; the next failure waits twice as long.
+  wait_ticks : u64,
+}
+
+/// The paths mobs are following, so they don't have to search every tick, and the goals they
+/// recently failed to find paths to, so they don't keep searching for those either.
+pub struct Cache {
+  paths    : fnv_map::T<entity::id::Mob, Path>,
+  failures : fnv_map::T<entity::id::Mob, Failure>,
+}
+
+pub fn new() -> Cache {
+  Cache {
+    paths    : fnv_map::new(),
+    failures : fnv_map::new(),
+  }
+}
+
+fn contains(region: &Aabb3<i32>, p: &Point3<i32>) -> bool {
+  region.min.x <= p.x && p.x < region.max.x &&
+  region.min.y <= p.y && p.y < region.max.y &&
+  region.min.z <= p.z && p.z < region.max.z
+}
+
+impl Cache {
+  pub fn take(&mut self, mob: entity::id::Mob) -> Option<Path> {
+    self.paths.remove(&mob)
+  }
+
+  pub fn insert(&mut self, mob: entity::id::Mob, path: Path) {
+    self.failures.remove(&mob);
+    self.paths.insert(mob, path);
+  }
+
+  /// Forget everything about a mob.
+  pub fn remove(&mut self, mob: entity::id::Mob) {
+    self.paths.remove(&mob);
+    self.failures.remove(&mob);
+  }
+
+  /// Whether a mob failed to find a way to (about) `goal` too recently to try again at `tick`.
+  fn is_backing_off(&self, mob: entity::id::Mob, goal: &Point3<i32>, tick: u64) -> bool {
+    match self.failures.get(&mob) {
+      None => false,
+      Some(failure) => tick < failure.retry_tick && distance(&failure.goal, goal) <= REPLAN_DISTANCE,
+    }
+  }
+
+  /// Remember that a mob couldn't find a way to `goal` at `tick`.
+  fn failed(&mut self, mob: entity::id::Mob, goal: &Point3<i32>, tick: u64) {
+    let wait_ticks =
+      match self.failures.get(&mob) {
+        Some(failure) if distance(&failure.goal, goal) <= REPLAN_DISTANCE =>
+          std::cmp::min(2 * failure.wait_ticks, MAX_RETRY_TICKS),
+        _ => MIN_RETRY_TICKS,
+      };
+    self.failures.insert(mob, Failure { goal: *goal, retry_tick: tick + wait_ticks, wait_ticks: wait_ticks });
+  }
+
+  /// Cut every path short where it runs through `region`, so the rest gets replanned.
+  pub fn terrain_changed(&mut self, region: &Aabb3<i32>) {
+    // Standing in a cell depends on the cell below it and the one above it too.
+    let region =
+      Aabb3::new(
+        region.min + Vector3::new(0, -1, 0),
+        region.max + Vector3::new(0, 2, 0),
+      );
+    for (_, path) in self.paths.iter_mut() {
+      match path.waypoints.iter().position(|p| contains(&region, p)) {
+        None => {},
+        Some(i) => {
+          path.waypoints.truncate(i);
+          path.is_stale = true;
+        },
+      }
+    }
+    // The change might have opened up a way.
+    self.failures.clear();
+  }
+}
+
+/// Bring a mob's path up to date for getting from `here` to `goal`, planning whatever's missing.
+fn plan(voxels: &voxel::tree::T, path: Option<Path>, here: &Point3<i32>, goal: &Point3<i32>) -> Option<Path> {
+  let replan_from_scratch = || {
+    find(voxels, here, goal).map(|waypoints| {
+      Path {
+        waypoints : waypoints,
+        goal      : *goal,
+        is_stale  : false,
+      }
+    })
+  };
+
+  let mut path =
+    match path {
+      None => return replan_from_scratch(),
+      Some(path) => path,
+    };
+  if path.waypoints.is_empty() {
+    return replan_from_scratch()
+  }
+
+  if distance(&path.goal, goal) > REPLAN_DISTANCE {
+    // Keep following the path as far as it gets toward the new goal, and re-route from there.
+    let (closest, closest_distance) =
+      path.waypoints.iter().enumerate()
+      .map(|(i, p)| (i, distance(p, goal)))
+      .min_by_key(|&(_, d)| d)
+      .unwrap();
+    if distance(here, goal) <= closest_distance {
+      // None of the path helps.
+      return replan_from_scratch()
+    }
+    path.waypoints.truncate(closest + 1);
+    path.goal = *goal;
+    path.is_stale = true;
+  }
+
+  if path.is_stale {
+    // Keep the part that's still good, and plan onward from the end of it.
+    let end = *path.waypoints.back().unwrap();
+    match find(voxels, &end, &path.goal) {
+      None => {},
+      Some(rest) => path.waypoints.extend(rest),
+    }
+    path.is_stale = false;
+  }
+  Some(path)
+}
+
+/// Walk a mob along a path toward `goal` (where its feet should end up), planning and replanning as needed.
+/// Runs until the mob gets there, and fails if there's no way to get any closer.
+pub fn follow(server: &server::T, mob: &mut mob::Mob, goal: &Point3<f32>) -> behavior::Status {
+  let here = cell_of(&mob.feet());
+  let goal = cell_of(goal);
+  if here == goal {
+    return behavior::Status::Success
+  }
+
+  let tick = *server.tick.lock().unwrap();
+  let path = {
+    let mut paths = server.paths.lock().unwrap();
+    if paths.is_backing_off(mob.entity_id, &goal, tick) {
+      return behavior::Status::Failure
+    }
+    paths.take(mob.entity_id)
+  };
+  let path = {
+    let voxels = server.terrain_loader.terrain.voxels.lock().unwrap();
+    plan(&*voxels, path, &here, &goal)
+  };
+  let mut path =
+    match path {
+      None => {
+        server.paths.lock().unwrap().failed(mob.entity_id, &goal, tick);
+        return behavior::Status::Failure
+      },
+      Some(path) => path,
+    };
+
+  // Skip past anything we've already reached.
+  match path.waypoints.iter().position(|p| *p == here) {
+    None => {},
+    Some(i) => { path.waypoints.drain(.. i + 1); },
+  }
+
+  let status =
+    match path.waypoints.front() {
+      None => {
+        // We got as close as we could.
+        mob.speed.x = 0.0;
+        mob.speed.z = 0.0;
+        behavior::Status::Success
+      },
+      Some(next) => {
+        let to_next =
+          Vector3::new(
+            next.x as f32 + 0.5 - mob.position.x,
+            0.0,
+            next.z as f32 + 0.5 - mob.position.z,
+          );
+        let walk =
+          if to_next.magnitude2() == 0.0 {
+            to_next
+          } else {
+            to_next.normalize() * f32::min(mob.kind.speed, to_next.magnitude())
+          };
+        mob.speed.x = walk.x;
+        mob.speed.z = walk.z;
+        behavior::Status::Running
+      },
+    };
+
+  server.paths.lock().unwrap().insert(mob.entity_id, path);
+  status
+}
@@ -0,0 +1,67 @@
+//! What mobs can tell about the world around them.
+
+use cgmath::{Point3, EuclideanSpace, InnerSpace};
+use collision::{Aabb3, Ray3};
+
+use common::voxel;
+
+use entity;
+use server;
+
+fn center(bounds: &Aabb3<f32>) -> Point3<f32> {
+  (bounds.min + bounds.max.to_vec()) * 0.5
+}
+
+/// Every player in the world, and their bounds.
+pub fn players(server: &server::T) -> Vec<(entity::id::Player, Aabb3<f32>)> {
+  let players: Vec<(entity::id::Player, entity::id::Misc)> =
+    server.players.lock().unwrap().iter()
+    .map(|(&id, player)| (id, player.physics_id))
+    .collect();
+
+  let physics = server.physics.lock().unwrap();
+  players.into_iter()
+    .filter_map(|(id, physics_id)| physics.get_bounds(physics_id).map(|bounds| (id, *bounds)))
+    .collect()
+}
+
+/// The closest player to `from`, and their bounds.
+pub fn nearest_player(server: &server::T, from: &Point3<f32>) -> Option<(entity::id::Player, Aabb3<f32>)> {
+  let mut nearest = None;
+  let mut nearest_distance = 0.0;
+  for (id, bounds) in players(server) {
+    let distance = (center(&bounds) - *from).magnitude2();
+    if nearest.is_none() || distance < nearest_distance {
+      nearest = Some((id, bounds));
+      nearest_distance = distance;
+    }
+  }
+  nearest
+}
+
+/// Whether there's no terrain between `from` and `to`.
+pub fn line_of_sight(server: &server::T, from: &Point3<f32>, to: &Point3<f32>) -> bool {
+  let distance = (*to - *from).magnitude();
+  if distance == 0.0 {
+    return true
+  }
+
+  let ray = Ray3::new(*from, (*to - *from) / distance);
+  let hit =
+    server.terrain_loader.terrain.voxels.lock().unwrap().cast_ray(
+      &ray,
+      &mut |bounds, voxel| {
+        match voxel {
+          &voxel::Volume(voxel::Material::Empty) => None,
+          _ => Some(bounds),
+        }
+      }
+    );
+  match hit {
+    None => true,
+    Some(bounds) => {
+      let (low, high) = bounds.corners();
+      (center(&Aabb3::new(low, high)) - *from).magnitude() >= distance
+    },
+  }
+}
@@ -2,35 +2,68 @@ use cgmath::{Vector3};
 use collision::{Aabb3};
 
 use common::fnv_map;
+use common::fnv_set;
+use common::movement;
 
 use entity;
 use octree::Octree;
 
 pub struct T {
   pub terrain_octree : Octree<entity::id::Terrain>,
-  terrain_bounds : fnv_map::T<entity::id::Terrain, Aabb3<f32>>,
+  terrain_shapes : fnv_map::T<entity::id::Terrain, movement::Shape>,
   pub misc_octree    : Octree<entity::id::Misc>,
   misc_bounds    : fnv_map::T<entity::id::Misc, Aabb3<f32>>,
+  /// The misc entities that are standing in for terrain that hasn't loaded yet.
+  placeholders   : fnv_set::T<entity::id::Misc>,
 }
 
 pub enum Collision {
   Misc(entity::id::Misc),
   Terrain(entity::id::Terrain),
+  /// A solid block standing in for terrain that hasn't loaded yet. It's treated like terrain.
+  Placeholder(entity::id::Misc),
+}
+
+/// A misc entity's physics body, as seen by the shared movement code.
+pub struct Body<'a> {
+  pub physics : &'a mut T,
+  pub id      : entity::id::Misc,
+}
+
+impl<'a> movement::Collider for Body<'a> {
+  type Obstacle = Collision;
+
+  fn bounds(&self) -> Aabb3<f32> {
+    *self.physics.get_bounds(self.id).unwrap()
+  }
+
+  fn sweep(&mut self, shift: Vector3<f32>) -> Option<movement::Collision<Collision>> {
+    self.physics.sweep_misc(self.id, shift).map(|(bounds, collision, normal)| {
+      let is_terrain =
+        match collision {
+          Collision::Terrain(_) => true,
+          Collision::Placeholder(_) => true,
+          Collision::Misc(_) => false,
+        };
+      movement::Collision { obstacle: collision, bounds: bounds, normal: normal, is_terrain: is_terrain }
+    })
+  }
 }
 
 impl T {
   pub fn new(world_bounds: Aabb3<f32>) -> T {
     T {
       terrain_octree : Octree::new(&world_bounds),
-      terrain_bounds : fnv_map::new(),
+      terrain_shapes : fnv_map::new(),
       misc_octree    : Octree::new(&world_bounds),
       misc_bounds    : fnv_map::new(),
+      placeholders   : fnv_set::new(),
     }
   }
 
-  pub fn insert_terrain(&mut self, id: entity::id::Terrain, bounds: &Aabb3<f32>) {
-    self.terrain_octree.insert(bounds, id);
-    self.terrain_bounds.insert(id, *bounds);
+  pub fn insert_terrain(&mut self, id: entity::id::Terrain, shape: &movement::Shape) {
+    self.terrain_octree.insert(&shape.bounds, id);
+    self.terrain_shapes.insert(id, *shape);
   }
 
   pub fn insert_misc(&mut self, id: entity::id::Misc, bounds: &Aabb3<f32>) {
@@ -38,20 +71,27 @@ impl T {
     self.misc_bounds.insert(id, *bounds);
   }
 
+  /// Insert a solid block in place of terrain that hasn't loaded yet. Remove it with `remove_misc`.
+  pub fn insert_placeholder(&mut self, id: entity::id::Misc, bounds: &Aabb3<f32>) {
+    self.insert_misc(id, bounds);
+    self.placeholders.insert(id);
+  }
+
   pub fn remove_terrain(&mut self, id: entity::id::Terrain) {
-    match self.terrain_bounds.get(&id) {
+    match self.terrain_shapes.remove(&id) {
       None => {},
-      Some(bounds) => {
-        self.terrain_octree.remove(bounds, id);
+      Some(shape) => {
+        self.terrain_octree.remove(&shape.bounds, id);
       },
     }
   }
 
   pub fn remove_misc(&mut self, id: entity::id::Misc) {
-    match self.misc_bounds.get(&id) {
+    self.placeholders.remove(&id);
+    match self.misc_bounds.remove(&id) {
       None => {},
       Some(bounds) => {
-        self.misc_octree.remove(bounds, id);
+        self.misc_octree.remove(&bounds, id);
       },
     }
   }
@@ -60,29 +100,39 @@ impl T {
     self.misc_bounds.get(&id)
   }
 
-  pub fn translate_misc(&mut self, id: entity::id::Misc, amount: Vector3<f32>) -> Option<(Aabb3<f32>, Collision)> {
-    let bounds = self.misc_bounds.get_mut(&id).unwrap();
-    let new_bounds =
-      Aabb3::new(
-        bounds.min + amount,
-        bounds.max + amount,
-      );
-    match self.terrain_octree.intersect(&new_bounds, None) {
-      Some((bounds, terrain_id)) => {
-        Some((bounds, Collision::Terrain(terrain_id)))
-      },
-      None => {
-        match self.misc_octree.intersect(&new_bounds, Some(id)) {
-          Some((bounds, misc_id)) => {
-            Some ((bounds, Collision::Misc(misc_id)))
-          },
-          None => {
-            self.misc_octree.reinsert(id, bounds, &new_bounds);
-            *bounds = new_bounds;
-            None
-          },
-        }
-      },
+  /// Move a misc entity along `amount`, stopping where it first touches something.
+  /// Returns what it touched: its bounds, what it is, and the normal of the surface that was touched.
+  /// Terrain is solid only below its surface; misc entities, placeholders included, are solid boxes.
+  pub fn sweep_misc(&mut self, id: entity::id::Misc, amount: Vector3<f32>) -> Option<(Aabb3<f32>, Collision, Vector3<f32>)> {
+    let bounds = *self.misc_bounds.get(&id).unwrap();
+    let swept = movement::swept_bounds(&bounds, &amount);
+    let hit = {
+      let terrain_shapes = &self.terrain_shapes;
+      let terrain =
+        self.terrain_octree.intersecting(&swept, None).into_iter()
+        .map(|(_, terrain_id)| (*terrain_shapes.get(&terrain_id).unwrap(), Collision::Terrain(terrain_id)));
+      let placeholders = &self.placeholders;
+      let misc =
+        self.misc_octree.intersecting(&swept, Some(id)).into_iter()
+        .map(|(bounds, misc_id)| {
+          let collision =
+            if placeholders.contains(&misc_id) {
+              Collision::Placeholder(misc_id)
+            } else {
+              Collision::Misc(misc_id)
+            };
+          (movement::Shape::cuboid(bounds), collision)
+        });
+      movement::first_impact(&bounds, &amount, terrain.chain(misc))
+    };
+
+    let t = hit.as_ref().map(|&(t, _, _, _)| t).unwrap_or(1.0);
+    if t > 0.0 {
+      let new_bounds = Aabb3::new(bounds.min + amount * t, bounds.max + amount * t);
+      self.misc_octree.reinsert(id, &bounds, &new_bounds);
+      self.misc_bounds.insert(id, new_bounds);
     }
+
+    hit.map(|(_, normal, bounds, collision)| (bounds, collision, normal))
   }
 }
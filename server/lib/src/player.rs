@@ -1,12 +1,13 @@
 use cgmath;
-use cgmath::{Point3, Matrix3, Vector3, ElementWise};
+use cgmath::{Point3, Matrix3, Vector3, EuclideanSpace};
 use collision::{Aabb3, Ray3};
-use std::f32::consts::PI;
-use std::ops::DerefMut;
 use std::sync::Mutex;
 use stopwatch;
 
 use common::id_allocator;
+use common::inventory;
+use common::movement;
+use common::protocol;
 use common::surroundings_loader;
 use common::voxel;
 
@@ -17,34 +18,59 @@ use server;
 use update_gaia;
 use update_world::load_placeholders;
 
-const MAX_JUMP_FUEL: u32 = 4;
-const MAX_STEP_HEIGHT: f32 = 1.0;
+/// Landing any slower than this doesn't hurt.
+const SAFE_FALL_SPEED: f32 = 1.0;
+/// How much damage each unit of landing speed beyond `SAFE_FALL_SPEED` does.
+const FALL_DAMAGE_PER_SPEED: f32 = 50.0;
+/// How long after being hurt by touching a mob before it can hurt again.
+const CONTACT_COOLDOWN_TICKS: u64 = movement::TICKS_PER_SECOND;
+
+/// Where new players, and players who have died, appear.
+pub fn spawn_bounds() -> Aabb3<f32> {
+  // TODO: shift upward until outside terrain
+  let min = Point3::new(0.0, 64.0, 4.0);
+  let max = min + Vector3::new(1.0, 2.0, 1.0);
+  Aabb3::new(min, max)
+}
+
+fn center(bounds: &Aabb3<f32>) -> Point3<f32> {
+  (bounds.min + bounds.max.to_vec()) * 0.5
+}
+
+/// The damage done by landing at `speed`.
+fn fall_damage(speed: f32) -> u32 {
+  (f32::max(0.0, speed - SAFE_FALL_SPEED) * FALL_DAMAGE_PER_SPEED).round() as u32
+}
 
 #[derive(Debug, Clone)]
 pub enum Collision {
   Terrain(entity::id::Terrain),
+  /// Terrain that hasn't loaded yet.
+  Placeholder(entity::id::Misc),
   Misc(entity::id::Misc),
 }
 
 pub struct T {
   pub position: Point3<f32>,
-  // speed; units are world coordinates
-  pub speed: Vector3<f32>,
-  // acceleration; units are world coordinates
-  pub accel: Vector3<f32>,
-  // acceleration; x/z units are relative to player facing
-  pub walk_accel: Vector3<f32>,
-  // this is depleted as we jump and replenished as we stand.
-  pub jump_fuel: u32,
-  // are we currently trying to jump? (e.g. holding the key).
-  pub is_jumping: bool,
+  pub movement: movement::State,
   pub entity_id: entity::id::Player,
   pub physics_id: entity::id::Misc,
-
-  // rotation around the y-axis, in radians
-  pub lateral_rotation: f32,
-  // "pitch", in radians
-  pub vertical_rotation: f32,
+  /// The client that added this player, and is the only one allowed to control it.
+  pub owner: protocol::ClientId,
+
+  /// The sequence number of the last input applied from this player's client.
+  pub last_input: protocol::InputSequence,
+  /// How many ticks have been simulated since `last_input` was applied.
+  pub ticks_since_input: u32,
+  /// The tick on which this player last brush-edited the terrain.
+  pub last_brush_tick: Option<u64>,
+
+  /// Zero means dead.
+  pub health: u32,
+  /// The tick on which this player was last hurt by touching a mob.
+  pub last_contact_tick: Option<u64>,
+  /// The materials this player has dug up, to build with.
+  pub inventory: inventory::T,
 
   surroundings_loader: surroundings_loader::T,
   surroundings_owner: lod::OwnerId,
@@ -56,21 +82,23 @@ pub struct T {
 pub fn new(
   entity_id: entity::id::Player,
   physics_id: entity::id::Misc,
+  owner: protocol::ClientId,
   owner_allocator: &Mutex<id_allocator::T<lod::OwnerId>>,
 ) -> T {
   let surroundings_owner = owner_allocator.lock().unwrap().allocate();
   let solid_owner = owner_allocator.lock().unwrap().allocate();
   T {
     position            : Point3::new(0.0, 0.0, 0.0),
-    speed               : Vector3::new(0.0, 0.0, 0.0),
-    accel               : Vector3::new(0.0, -0.1, 0.0),
-    walk_accel          : Vector3::new(0.0, 0.0, 0.0),
-    jump_fuel           : 0,
-    is_jumping          : false,
+    movement            : movement::new(),
     entity_id           : entity_id,
     physics_id          : physics_id,
-    lateral_rotation    : 0.0,
-    vertical_rotation   : 0.0,
+    owner               : owner,
+    last_input          : 0,
+    ticks_since_input   : 0,
+    last_brush_tick     : None,
+    health              : protocol::MAX_HEALTH,
+    last_contact_tick   : None,
+    inventory           : inventory::new(),
 
     surroundings_loader : surroundings_loader::new(8, Vec::new()),
     solid_boundary      : surroundings_loader::new(8, Vec::new()),
@@ -80,74 +108,6 @@ pub fn new(
 }
 
 impl T {
-  /// Translates the player by a vector.
-  /// If the player collides with something with a small height jump, the player will shift upward.
-  /// Returns the actual amount moved by.
-  fn translate(
-    &mut self,
-    physics: &Mutex<physics::T>,
-    requested_shift: Vector3<f32>,
-  ) -> (Aabb3<f32>, Vec<Collision>)
-  {
-    let mut physics = physics.lock().unwrap();
-    let physics = physics.deref_mut();
-    let init_bounds = *physics.get_bounds(self.physics_id).unwrap();
-    let requested_bounds =
-      Aabb3::new(
-        init_bounds.min + requested_shift,
-        init_bounds.max + requested_shift,
-      );
-
-    let mut shift = requested_shift;
-    let mut collisions = Vec::new();
-    let mut collided = false;
-    loop {
-      match physics.translate_misc(self.physics_id, shift) {
-        None => {
-          break
-        },
-        Some((_, physics::Collision::Misc(id))) => {
-          collided = true;
-          collisions.push(Collision::Misc(id));
-          break
-        },
-        Some((collision_bounds, physics::Collision::Terrain(id))) => {
-          collisions.push(Collision::Terrain(id));
-          collided = true;
-
-          // Step to the top of whatever we hit.
-          let step_height = collision_bounds.max.y - requested_bounds.min.y;
-          assert!(step_height > 0.0);
-
-          if step_height > MAX_STEP_HEIGHT {
-            // Step is too big; we just ran into something.
-            break
-          }
-
-          shift += Vector3::new(0.0, step_height, 0.0);
-        },
-      }
-    }
-
-    let shifted = *physics.get_bounds(self.physics_id).unwrap();
-    self.position += shifted.min - init_bounds.min;
-
-    if collided {
-      if requested_shift.y < 0.0 {
-        self.jump_fuel = MAX_JUMP_FUEL;
-      }
-
-      self.speed.y -= requested_shift.y;
-    } else {
-      if requested_shift.y < 0.0 {
-        self.jump_fuel = 0;
-      }
-    }
-
-    (shifted, collisions)
-  }
-
-
   pub fn update<RequestBlock>(
     &mut self,
     server: &server::T,
@@ -200,76 +160,87 @@ impl T {
       }
     });
 
-    if self.is_jumping {
-      if self.jump_fuel > 0 {
-        self.jump_fuel -= 1;
-      } else {
-        // this code is duplicated in a few places
-        self.is_jumping = false;
-        self.accel.y = self.accel.y - 0.3;
-      }
-    }
-
-    let delta_p = self.speed;
-    let mut new_bounds = *server.physics.lock().unwrap().get_bounds(self.physics_id).unwrap();
-    let mut collisions = Vec::new();
-    if delta_p.x != 0.0 {
-      let (b, c) = self.translate(&server.physics, Vector3::new(delta_p.x, 0.0, 0.0));
-      new_bounds = b;
-      collisions.extend_from_slice(c.as_slice());
-    }
-    if delta_p.y != 0.0 {
-      let (b, c) = self.translate(&server.physics, Vector3::new(0.0, delta_p.y, 0.0));
-      new_bounds = b;
-      collisions.extend_from_slice(c.as_slice());
-    }
-    if delta_p.z != 0.0 {
-      let (b, c) = self.translate(&server.physics, Vector3::new(0.0, 0.0, delta_p.z));
-      new_bounds = b;
-      collisions.extend_from_slice(c.as_slice());
+    let mut physics = server.physics.lock().unwrap();
+    let mut body = physics::Body { physics: &mut *physics, id: self.physics_id };
+    let init_bounds = movement::Collider::bounds(&body);
+    let tick = self.movement.tick(&mut body);
+    let collisions =
+      tick.obstacles.into_iter()
+      .map(|collision| {
+        match collision {
+          physics::Collision::Terrain(id) => Collision::Terrain(id),
+          physics::Collision::Placeholder(id) => Collision::Placeholder(id),
+          physics::Collision::Misc(id) => Collision::Misc(id),
+        }
+      })
+      .collect();
+    let new_bounds = movement::Collider::bounds(&body);
+    self.position += new_bounds.min - init_bounds.min;
+    self.ticks_since_input = self.ticks_since_input.saturating_add(1);
+
+    match tick.landed_at {
+      None => {},
+      Some(speed) => self.hurt(fall_damage(speed)),
     }
 
-    let y_axis = Vector3::new(0.0, 1.0, 0.0);
-    let walk_v =
-        Matrix3::from_axis_angle(y_axis, cgmath::Rad(self.lateral_rotation))
-        * self.walk_accel;
-    self.speed += walk_v;
-    self.speed += self.accel;
-    // friction
-    self.speed.mul_assign_element_wise(Vector3::new(0.7, 0.99, 0.7 as f32));
-
     (new_bounds, collisions)
   }
 
-  /// Changes the player's acceleration by the given `da`.
-  pub fn walk(&mut self, da: Vector3<f32>) {
-    self.walk_accel += &da * 0.1;
+  pub fn hurt(&mut self, damage: u32) {
+    self.health = self.health.saturating_sub(damage);
   }
 
-  /// Rotate the player around the y axis, by `r` radians. Positive is counterclockwise.
-  pub fn rotate_lateral(&mut self, r: f32) {
-    self.lateral_rotation = self.lateral_rotation + r;
+  /// Take damage from touching a mob on tick `tick`, unless one has hurt this player too recently.
+  pub fn hurt_by_contact(&mut self, damage: u32, tick: u64) {
+    if damage == 0 {
+      return
+    }
+    match self.last_contact_tick {
+      Some(last) if tick < last + CONTACT_COOLDOWN_TICKS => return,
+      _ => {},
+    }
+    self.last_contact_tick = Some(tick);
+    self.hurt(damage);
   }
 
-  /// Changes the player's pitch by `r` radians. Positive is up.
-  /// Angles that "flip around" (i.e. looking too far up or down)
-  /// are sliently rejected.
-  pub fn rotate_vertical(&mut self, r: f32) {
-    let new_rotation = self.vertical_rotation + r;
+  /// Bring this player back at the spawn point, at full health.
+  pub fn respawn(&mut self, server: &server::T) {
+    let bounds = spawn_bounds();
+    {
+      let mut physics = server.physics.lock().unwrap();
+      physics.remove_misc(self.physics_id);
+      physics.insert_misc(self.physics_id, &bounds);
+    }
+    self.position = center(&bounds);
+    self.movement.speed = Vector3::new(0.0, 0.0, 0.0);
+    self.health = protocol::MAX_HEALTH;
+    self.last_contact_tick = None;
+  }
 
-    if new_rotation < -PI / 2.0
-    || new_rotation >  PI / 2.0 {
-      return
+  /// Apply an input from this player's client. Inputs that arrive out of order, after a later one has
+  /// already been applied, are ignored. Returns whether the input was applied.
+  pub fn apply_input(&mut self, sequence: protocol::InputSequence, input: &movement::Input) -> bool {
+    if sequence <= self.last_input {
+      return false
     }
+    self.movement.apply(input);
+    self.last_input = sequence;
+    self.ticks_since_input = 0;
+    true
+  }
 
-    self.vertical_rotation = new_rotation;
+  /// Release this player's hold on the world: its terrain loads and its physics body.
+  pub fn destroy(&self, server: &server::T) {
+    server.terrain_loader.unload_owner(&server.physics, self.surroundings_owner);
+    server.terrain_loader.unload_owner(&server.physics, self.solid_owner);
+    server.physics.lock().unwrap().remove_misc(self.physics_id);
   }
 
   // axes
 
   /// Return the "right" axis (i.e. the x-axis rotated to match you).
   pub fn right(&self) -> Vector3<f32> {
-    Matrix3::from_axis_angle(Vector3::new(0.0, 1.0, 0.0), cgmath::Rad(self.lateral_rotation))
+    Matrix3::from_axis_angle(Vector3::new(0.0, 1.0, 0.0), cgmath::Rad(self.movement.lateral_rotation))
       * Vector3::new(1.0, 0.0, 0.0)
   }
 
@@ -277,8 +248,8 @@ impl T {
   pub fn forward(&self) -> Vector3<f32> {
     let y_axis = Vector3::new(0.0, 1.0, 0.0);
     let transform =
-      Matrix3::from_axis_angle(self.right(), cgmath::Rad(self.vertical_rotation))
-        * Matrix3::from_axis_angle(y_axis, cgmath::Rad(self.lateral_rotation));
+      Matrix3::from_axis_angle(self.right(), cgmath::Rad(self.movement.vertical_rotation))
+        * Matrix3::from_axis_angle(y_axis, cgmath::Rad(self.movement.lateral_rotation));
     let forward_orig = Vector3::new(0.0, 0.0, -1.0);
 
     transform * forward_orig
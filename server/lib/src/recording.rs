@@ -0,0 +1,182 @@
+//! Record the messages a server receives, and replay them to reproduce the server's state.
+//!
+//! Along with each message, a recording notes every time the server applied a queued terrain (gaia)
+//! update, and how many world updates had run by then. A replay runs everything on one thread, against a
+//! fixed clock, and applies its queued terrain updates at the same points, so it follows the live session's
+//! schedule. Replays of the same recording always end up in the same state, which can be compared with
+//! `world_hash`. Updates queued by the world tick and by a message that arrived during that same tick can
+//! still be queued in a different order than they were live.
+
+use bincode;
+use std;
+use std::collections::VecDeque;
+use std::collections::hash_map::DefaultHasher;
+use std::hash::Hasher;
+use std::io::{Read, Write};
+
+use common::protocol;
+use common::socket::Peer;
+
+use client_recv_thread::apply_client_update;
+use clock;
+use run;
+use server;
+use update_gaia::update_gaia;
+use update_world::update_world;
+
+// Each recorded event starts with one of these tags, and the tick it happened on.
+
+/// A message arrived. The message follows.
+const MESSAGE: u8 = 0;
+/// The oldest queued gaia update was applied.
+const GAIA_UPDATE: u8 = 1;
+/// A session token was handed out. The token follows.
+const SESSION_TOKEN: u8 = 2;
+
+/// Writes each incoming message, each time a gaia update is applied, and each session token handed out,
+/// to a file, tagged with the tick it happened on. Replays hand out the recorded tokens, since the live
+/// server's tokens are unpredictable.
+pub struct Recorder {
+  file: std::io::BufWriter<std::fs::File>,
+}
+
+#[allow(missing_docs)]
+pub fn create(path: &std::path::Path) -> std::io::Result<Recorder> {
+  let file = try!(std::fs::File::create(path));
+  Ok(Recorder {
+    file: std::io::BufWriter::new(file),
+  })
+}
+
+fn invalid_data(err: bincode::Error) -> std::io::Error {
+  std::io::Error::new(std::io::ErrorKind::InvalidData, format!("{:?}", err))
+}
+
+impl Recorder {
+  /// Record a message that arrived after `tick` world updates.
+  pub fn record(&mut self, tick: u64, msg: &protocol::ClientToServer) {
+    let r =
+      bincode::serialize_into(&mut self.file, &(MESSAGE, tick, msg), bincode::Infinite)
+      .map_err(|err| format!("{:?}", err));
+    self.flush(r);
+  }
+
+  /// Record that the oldest queued gaia update was applied after `tick` world updates.
+  pub fn record_gaia_update(&mut self, tick: u64) {
+    let r =
+      bincode::serialize_into(&mut self.file, &(GAIA_UPDATE, tick), bincode::Infinite)
+      .map_err(|err| format!("{:?}", err));
+    self.flush(r);
+  }
+
+  /// Record that `token` was handed out after `tick` world updates.
+  pub fn record_session_token(&mut self, tick: u64, token: protocol::SessionToken) {
+    let r =
+      bincode::serialize_into(&mut self.file, &(SESSION_TOKEN, tick, token), bincode::Infinite)
+      .map_err(|err| format!("{:?}", err));
+    self.flush(r);
+  }
+
+  // Flush every event, so the recording survives a crash.
+  fn flush(&mut self, written: Result<(), String>) {
+    let r = written.and_then(|()| self.file.flush().map_err(|err| format!("{:?}", err)));
+    match r {
+      Ok(()) => {},
+      Err(err) => warn!("Error recording: {}", err),
+    }
+  }
+}
+
+/// A hash of the terrain and the positions of all the entities.
+pub fn world_hash(server: &server::T) -> u64 {
+  let mut players: Vec<_> =
+    server.players.lock().unwrap().iter()
+    .map(|(&id, player)| (id, player.position))
+    .collect();
+  players.sort_by_key(|&(id, _)| id);
+
+  let mut mobs: Vec<_> =
+    server.mobs.lock().unwrap().iter()
+    .map(|(&id, mob)| (id, mob.position))
+    .collect();
+  mobs.sort_by_key(|&(id, _)| id);
+
+  let mut hasher = DefaultHasher::new();
+  hasher.write(&bincode::serialize(&*server.terrain_loader.terrain.voxels.lock().unwrap(), bincode::Infinite).unwrap());
+  hasher.write(&bincode::serialize(&players, bincode::Infinite).unwrap());
+  hasher.write(&bincode::serialize(&mobs, bincode::Infinite).unwrap());
+  hasher.finish()
+}
+
+enum Event {
+  Message(protocol::ClientToServer),
+  GaiaUpdate,
+  SessionToken(protocol::SessionToken),
+}
+
+/// Decode a recording into its events, and the ticks they happened on.
+fn read_events(mut input: &[u8]) -> std::io::Result<Vec<(u64, Event)>> {
+  let mut events = Vec::new();
+  while !input.is_empty() {
+    let (tag, tick): (u8, u64) =
+      try!(bincode::deserialize_from(&mut input, bincode::Infinite).map_err(invalid_data));
+    let event =
+      match tag {
+        MESSAGE =>
+          Event::Message(try!(bincode::deserialize_from(&mut input, bincode::Infinite).map_err(invalid_data))),
+        GAIA_UPDATE => Event::GaiaUpdate,
+        SESSION_TOKEN =>
+          Event::SessionToken(try!(bincode::deserialize_from(&mut input, bincode::Infinite).map_err(invalid_data))),
+        _ => {
+          return Err(std::io::Error::new(std::io::ErrorKind::InvalidData, format!("Unknown event {}", tag)))
+        },
+      };
+    events.push((tick, event));
+  }
+  Ok(events)
+}
+
+/// Feed a recording back through a fresh server. Returns the final `world_hash`.
+pub fn replay(path: &std::path::Path) -> std::io::Result<u64> {
+  let mut bytes = Vec::new();
+  try!(try!(std::fs::File::open(path)).read_to_end(&mut bytes));
+  let events = try!(read_events(&bytes));
+
+  // Tokens are recorded after the messages they were handed out for, so the server gets them all up front.
+  let tokens =
+    events.iter()
+    .filter_map(|&(_, ref event)| {
+      match *event {
+        Event::SessionToken(token) => Some(token),
+        _ => None,
+      }
+    })
+    .collect();
+  let server = server::new(clock::fixed(0), server::SessionTokens::Replayed(tokens));
+  run::load_terrain(&server.terrain_loader.terrain, std::path::Path::new(run::TERRAIN_PATH));
+
+  let mut gaia_updates = VecDeque::new();
+  let peer = Peer::nowhere();
+
+  for (tick, event) in events {
+    while *server.tick.lock().unwrap() < tick {
+      server.clock.advance(server::TICK_NS);
+      update_world(&server, &mut |up| gaia_updates.push_back(up));
+    }
+
+    match event {
+      Event::Message(up) => {
+        apply_client_update(&server, &mut |up| gaia_updates.push_back(up), &peer, up);
+      },
+      Event::GaiaUpdate => {
+        match gaia_updates.pop_front() {
+          None => warn!("The recording applied a gaia update at tick {} that the replay never queued", tick),
+          Some(up) => update_gaia(&server, up),
+        }
+      },
+      Event::SessionToken(_) => {},
+    }
+  }
+
+  Ok(world_hash(&server))
+}
@@ -1,17 +1,20 @@
 use std;
 use std::convert::AsRef;
 use std::sync::Mutex;
+use std::time::Duration;
 use bincode;
 use stopwatch;
 use thread_scoped;
-use time;
 use voxel_data;
 
 use common;
 use common::closure_series;
+use common::protocol;
 use common::socket::ReceiveSocket;
 
 use client_recv_thread::apply_client_update;
+use clock;
+use recording;
 use server;
 use update_gaia;
 use update_gaia::update_gaia;
@@ -22,18 +25,40 @@ mod terrain {
 }
 
 const SAVE_TERRAIN: bool = false;
+/// Where the terrain is loaded from (and saved to, if `SAVE_TERRAIN`).
+pub const TERRAIN_PATH: &'static str = "default.terrain";
 
-#[allow(missing_docs)]
-pub fn run(listen_url: &str, quit_signal: &Mutex<bool>) {
+/// Run a server until `quit_signal` is set.
+/// Clients only get updates about entities within `entity_update_radius` of their players.
+/// If `record_path` is given, every incoming message is recorded there, to be replayed with `recording::replay`.
+pub fn run(
+  listen_url: &str,
+  client_timeout: Duration,
+  entity_update_radius: f32,
+  record_path: Option<&std::path::Path>,
+  quit_signal: &Mutex<bool>,
+) {
   let gaia_updates = Mutex::new(std::collections::VecDeque::new());
 
   let listen_socket = ReceiveSocket::new(listen_url.as_ref(), None);
   let listen_socket = Mutex::new(listen_socket);
 
-  let server = server::new();
+  let mut server = server::new(clock::real(), server::random_session_tokens());
+  server.client_timeout_ns =
+    client_timeout.as_secs() * 1_000_000_000 + client_timeout.subsec_nanos() as u64;
+  server.entity_update_radius = entity_update_radius;
+  record_path.map(|path| {
+    match recording::create(path) {
+      Ok(recorder) => {
+        println!("Recording to {}", path.to_str().unwrap());
+        *server.recorder.lock().unwrap() = Some(recorder);
+      },
+      Err(err) => warn!("Error creating recording {:?}: {:?}", path, err),
+    }
+  });
   let server = &server;
 
-  let terrain_path = std::path::Path::new("default.terrain");
+  let terrain_path = std::path::Path::new(TERRAIN_PATH);
 
   println!("Loading terrain from {}", terrain_path.to_str().unwrap());
   load_terrain(&server.terrain_loader.terrain, &terrain_path);
@@ -96,6 +121,10 @@ pub fn run(listen_url: &str, quit_signal: &Mutex<bool>) {
     tree_ram_usage(&server.terrain_loader.terrain.voxels.lock().unwrap()) as f32 / (1 << 20) as f32,
   );
 
+  if server.recorder.lock().unwrap().is_some() {
+    println!("World hash: {:x}", recording::world_hash(&server));
+  }
+
   if SAVE_TERRAIN {
     println!("Saving terrain to {}", terrain_path.to_str().unwrap());
     stopwatch::time("save_terrain", || {
@@ -123,7 +152,7 @@ fn consider_world_update<'a, ToGaia>(
   ToGaia: FnMut(update_gaia::Message) + 'a,
 {
   box move || {
-    if server.update_timer.lock().unwrap().update(time::precise_time_ns()) > 0 {
+    if server.update_timer.lock().unwrap().update(server.clock.now()) > 0 {
       update_world(
         server,
         &mut to_gaia,
@@ -143,12 +172,26 @@ fn network_listen<'a, ToGaia>(
   ToGaia: FnMut(update_gaia::Message) + 'a,
 {
   box move || {
-    match socket.lock().unwrap().try_read() {
+    match socket.lock().unwrap().try_read_from() {
       common::socket::Result::Empty => closure_series::Continue,
       common::socket::Result::Terminating => closure_series::Quit,
-      common::socket::Result::Success(up) => {
-        let up = bincode::deserialize(up.as_ref()).unwrap();
-        apply_client_update(server, &mut to_gaia, up);
+      common::socket::Result::Success((peer, up)) => {
+        let len = up.len() as u64;
+        // Count everything that comes in, including what doesn't decode.
+        server.received_from(&peer, len);
+        let up: protocol::ClientToServer =
+          match bincode::deserialize_from(&mut up.as_slice(), bincode::Bounded(protocol::MAX_CLIENT_MESSAGE_BYTES)) {
+            Ok(up) => up,
+            Err(err) => {
+              warn!("Dropping malformed {} byte message: {:?}", len, err);
+              server.drop_from(&peer);
+              return closure_series::Restart
+            },
+          };
+        server.recorder.lock().unwrap().as_mut().map(|recorder| {
+          recorder.record(*server.tick.lock().unwrap(), &up);
+        });
+        apply_client_update(server, &mut to_gaia, &peer, up);
         closure_series::Restart
       },
     }
@@ -164,6 +207,9 @@ fn consider_gaia_update<'a, Get>(
   box move || {
     match get_update() {
       Some(up) => {
+        server.recorder.lock().unwrap().as_mut().map(|recorder| {
+          recorder.record_gaia_update(*server.tick.lock().unwrap());
+        });
         update_gaia(server, up);
         closure_series::Restart
       },
@@ -172,7 +218,8 @@ fn consider_gaia_update<'a, Get>(
   }
 }
 
-fn load_terrain(terrain: &terrain::T, path: &std::path::Path) {
+#[allow(missing_docs)]
+pub fn load_terrain(terrain: &terrain::T, path: &std::path::Path) {
   let mut file =
     match std::fs::File::open(path) {
       Err(err) => {
@@ -3,44 +3,185 @@
 use cgmath::{Point3};
 use collision::{Aabb3};
 use rand;
+use std;
 use std::sync::Mutex;
-use time;
 
 use common::protocol;
 use common::fnv_map;
+use common::fnv_set;
 use common::id_allocator;
 use common::interval_timer::IntervalTimer;
-use common::socket::SendSocket;
+use common::movement;
+use common::socket::{Peer, SendSocket};
+use common::voxel;
+use common::voxel_encoding;
 
+use clock;
 use entity;
-use init_mobs::init_mobs;
 use lod;
 use mob;
+use pathfinding;
 use physics;
 use player;
+use recording;
+use snapshots;
 use sun::Sun;
 use terrain_loader;
+use voxel_subscriptions;
 
-const UPDATES_PER_SECOND: u64 = 30;
 const SUN_TICK_NS: u64 = 1600000;
+const PING_INTERVAL_NS: u64 = 1_000_000_000;
+/// How long each world update covers.
+pub const TICK_NS: u64 = 1_000_000_000 / movement::TICKS_PER_SECOND;
+
+/// How long a client can go without answering pings before it's disconnected.
+pub const DEFAULT_CLIENT_TIMEOUT_SECS: u64 = 30;
+/// How close an entity has to be to one of a client's players for the client to get updates about it.
+pub const DEFAULT_ENTITY_UPDATE_RADIUS: f32 = 256.0;
+
+/// A detached client that misses more voxel updates than this is disconnected instead of caught up;
+/// its reattach is rejected, and it starts over.
+pub const MAX_MISSED_VOXELS: usize = 1 << 16;
+
+/// How many ticks each player has to wait between brush-edits of the terrain.
+/// This is counted in ticks rather than ns so replays treat brushes the same way.
+pub const MIN_BRUSH_INTERVAL_TICKS: u64 = 8;
+
+/// Protocol features that a client must support in order to connect.
+pub const REQUIRED_FEATURES: &'static [&'static str] = &[];
+
+/// Voxels on their way to one or more clients. They're compressed at most once, however many clients
+/// want them that way.
+pub struct OutgoingVoxels<'a> {
+  voxels     : &'a [(voxel::bounds::T, voxel::T)],
+  compressed : Option<Vec<u8>>,
+}
+
+#[allow(missing_docs)]
+pub fn outgoing_voxels(voxels: &[(voxel::bounds::T, voxel::T)]) -> OutgoingVoxels {
+  OutgoingVoxels {
+    voxels     : voxels,
+    compressed : None,
+  }
+}
+
+impl<'a> OutgoingVoxels<'a> {
+  fn compressed(&mut self) -> Vec<u8> {
+    if self.compressed.is_none() {
+      self.compressed = Some(voxel_encoding::encode(self.voxels));
+    }
+    self.compressed.clone().unwrap()
+  }
+}
 
 /// Client handle
 pub struct Client {
   /// Socket to the client
   pub socket: SendSocket,
+  /// The connection this client's messages arrive on. Messages that claim to be from this client,
+  /// but arrive on some other connection, are dropped.
+  pub peer: Peer,
+  /// The players this client has added.
+  pub players: fnv_set::T<entity::id::Player>,
+  /// Where this client's spectator camera is, if it has one. It has no body, so nothing collides with it.
+  pub camera: Option<Point3<f32>>,
+  /// When we last heard from this client, in ns.
+  pub last_heard_ns: u64,
+  /// Whether this client accepts `CompressedVoxels`.
+  pub compress_voxels: bool,
+  /// Total size of the messages sent to this client.
+  pub bytes_sent: u64,
+  /// Total size of the messages received from this client.
+  pub bytes_received: u64,
+  /// How many of this client's messages were dropped as stale, unusable, or too frequent.
+  pub dropped_messages: u64,
+  /// The world snapshots sent to this client.
+  pub snapshots: snapshots::T,
+  /// The regions this client has requested voxels in, and wants to hear about changes to.
+  pub voxel_subscriptions: voxel_subscriptions::T,
+  /// The secret this client has to present to `Reattach`.
+  pub session_token: protocol::SessionToken,
+  /// If the client has stopped answering, when we gave up on its connection, in ns.
+  /// Its players stay in the world until it reattaches or `reattach_grace_ns` runs out.
+  pub detached_since: Option<u64>,
+  /// Terrain updates in subscribed regions while the client was detached, to be sent when it reattaches.
+  pub missed_voxels: Vec<(voxel::bounds::T, voxel::T)>,
+  /// Whether the client missed more than `MAX_MISSED_VOXELS` updates while detached, and can't be caught up.
+  pub missed_too_much: bool,
 }
 
 impl Client {
   #[allow(missing_docs)]
   pub fn send(&mut self, msg: protocol::ServerToClient) {
+    if self.detached_since.is_some() {
+      return
+    }
     use bincode;
     use bincode::serialize;
     let msg = serialize(&msg, bincode::Infinite).unwrap();
+    self.bytes_sent += msg.len() as u64;
     match self.socket.write(msg.as_ref()) {
       Ok(()) => {},
       Err(err) => warn!("Error sending to client: {:?}", err),
     }
   }
+
+  /// Send some voxels, in the most compact encoding this client supports.
+  pub fn send_voxels(
+    &mut self,
+    voxels: &mut OutgoingVoxels,
+    reason: protocol::VoxelReason,
+  ) {
+    if self.detached_since.is_some() {
+      match reason {
+        protocol::VoxelReason::Updated => {
+          if self.missed_voxels.len() + voxels.voxels.len() > MAX_MISSED_VOXELS {
+            self.missed_voxels = Vec::new();
+            self.missed_too_much = true;
+          } else if !self.missed_too_much {
+            self.missed_voxels.extend_from_slice(voxels.voxels);
+          }
+        },
+        // The client will ask for these again.
+        protocol::VoxelReason::Requested { .. } => {},
+      }
+      return
+    }
+    let msg =
+      if self.compress_voxels {
+        protocol::ServerToClient::CompressedVoxels {
+          voxels : voxels.compressed(),
+          reason : reason,
+        }
+      } else {
+        protocol::ServerToClient::Voxels {
+          voxels : voxels.voxels.to_vec(),
+          reason : reason,
+        }
+      };
+    self.send(msg);
+  }
+
+  /// Stop sending to this client, but hold on to its session in case it reattaches.
+  pub fn detach(&mut self, now: u64) {
+    self.detached_since = Some(now);
+  }
+
+  /// Resume a detached session over a new socket, and catch the client up on what it missed.
+  pub fn reattach(&mut self, socket: SendSocket, peer: Peer, now: u64) {
+    self.socket = socket;
+    self.peer = peer;
+    self.detached_since = None;
+    self.last_heard_ns = now;
+    // We don't know which snapshots made it through before the connection dropped.
+    self.snapshots.forget_acks();
+
+    self.send(protocol::ServerToClient::Reattached);
+    let missed = std::mem::replace(&mut self.missed_voxels, Vec::new());
+    if !missed.is_empty() {
+      self.send_voxels(&mut outgoing_voxels(&missed), protocol::VoxelReason::Updated);
+    }
+  }
 }
 
 // TODO: Audit for s/Mutex/RwLock.
@@ -48,6 +189,8 @@ impl Client {
 pub struct T {
   pub players           : Mutex<fnv_map::T<entity::id::Player, player::T>>,
   pub mobs              : Mutex<fnv_map::T<entity::id::Mob, mob::Mob>>,
+  /// The paths mobs are following.
+  pub paths             : Mutex<pathfinding::Cache>,
 
   pub player_allocator  : Mutex<id_allocator::T<entity::id::Player>>,
   pub mob_allocator     : Mutex<id_allocator::T<entity::id::Mob>>,
@@ -59,15 +202,148 @@ pub struct T {
   pub physics           : Mutex<physics::T>,
   pub terrain_loader    : terrain_loader::T,
   pub rng               : Mutex<rand::StdRng>,
+  /// Where `SessionToken`s come from. This is kept apart from `rng` so handing out tokens doesn't change the world.
+  pub session_tokens    : Mutex<SessionTokens>,
 
   pub clients           : Mutex<fnv_map::T<protocol::ClientId, Client>>,
+  /// Dropped messages that couldn't be pinned on a connected client, e.g. undecodable messages
+  /// over a transport that can't tell senders apart.
+  pub unattributed_drops : Mutex<u64>,
+  /// Size of the messages that couldn't be pinned on a connected client.
+  pub unattributed_bytes_received : Mutex<u64>,
 
   pub sun               : Mutex<Sun>,
+  /// Where all the server's timing comes from.
+  pub clock             : clock::T,
+  /// How many times the world has been updated.
+  pub tick              : Mutex<u64>,
+  /// If this session is being recorded, where incoming messages go.
+  pub recorder          : Mutex<Option<recording::Recorder>>,
   pub update_timer      : Mutex<IntervalTimer>,
+  pub ping_timer        : Mutex<IntervalTimer>,
+  /// Clients that haven't been heard from in this long are detached.
+  pub client_timeout_ns : u64,
+  /// Detached clients that haven't reattached in this long are disconnected.
+  pub reattach_grace_ns : u64,
+  /// Clients only get updates about entities within this distance of their players.
+  pub entity_update_radius : f32,
+}
+
+impl T {
+  /// Remove a player from the world, and tell all the clients it's gone.
+  pub fn remove_player(&self, player_id: entity::id::Player) {
+    let player = self.players.lock().unwrap().remove(&player_id);
+    let player =
+      match player {
+        None => {
+          warn!("Tried to remove nonexistent player {:?}", player_id);
+          return
+        },
+        Some(player) => player,
+      };
+
+    player.destroy(self);
+
+    for (_, client) in self.clients.lock().unwrap().iter_mut() {
+      client.players.remove(&player_id);
+      client.send(protocol::ServerToClient::PlayerRemoved(player_id));
+    }
+  }
+
+  /// A fresh token for a new client session. If the session is being recorded, so is the token.
+  pub fn new_session_token(&self) -> protocol::SessionToken {
+    use rand::Rng;
+    let token =
+      match *self.session_tokens.lock().unwrap() {
+        SessionTokens::Random(ref mut rng) => protocol::SessionToken(rng.next_u64()),
+        SessionTokens::Replayed(ref mut tokens) => {
+          tokens.pop_front().unwrap_or_else(|| {
+            warn!("Ran out of recorded session tokens");
+            protocol::SessionToken(0)
+          })
+        },
+      };
+    let tick = *self.tick.lock().unwrap();
+    self.recorder.lock().unwrap().as_mut().map(|recorder| recorder.record_session_token(tick, token));
+    token
+  }
+
+  /// Count a message from this client that was dropped without being applied.
+  pub fn drop_message(&self, client_id: protocol::ClientId) {
+    self.clients.lock().unwrap()
+      .get_mut(&client_id)
+      .map(|client| client.dropped_messages += 1);
+  }
+
+  /// The client whose messages arrive over `peer`'s connection, if the transport can tell.
+  pub fn client_on(&self, peer: &Peer) -> Option<protocol::ClientId> {
+    self.clients.lock().unwrap().iter()
+      .find(|&(_, client)| client.peer.same_connection(peer) == Some(true))
+      .map(|(&client_id, _)| client_id)
+  }
+
+  /// Count a message that arrived over `peer` and was dropped, against whichever client is on that connection.
+  pub fn drop_from(&self, peer: &Peer) {
+    match self.client_on(peer) {
+      None => *self.unattributed_drops.lock().unwrap() += 1,
+      Some(client_id) => self.drop_message(client_id),
+    }
+  }
+
+  /// Count `len` bytes that arrived over `peer`, against whichever client is on that connection.
+  pub fn received_from(&self, peer: &Peer, len: u64) {
+    let mut clients = self.clients.lock().unwrap();
+    let client =
+      clients.values_mut()
+      .find(|client| client.peer.same_connection(peer) == Some(true));
+    match client {
+      None => *self.unattributed_bytes_received.lock().unwrap() += len,
+      Some(client) => client.bytes_received += len,
+    }
+  }
+
+  /// Disconnect a client and remove everything it added to the world.
+  pub fn remove_client(&self, client_id: protocol::ClientId) {
+    let client = self.clients.lock().unwrap().remove(&client_id);
+    let client =
+      match client {
+        None => {
+          warn!("Tried to remove nonexistent client {:?}", client_id);
+          return
+        },
+        Some(client) => client,
+      };
+
+    info!(
+      "Client {:?} left; sent it {} bytes, received {} bytes, dropped {} messages",
+      client_id,
+      client.bytes_sent,
+      client.bytes_received,
+      client.dropped_messages,
+    );
+    for player_id in client.players {
+      self.remove_player(player_id);
+    }
+  }
+}
+
+/// Where `SessionToken`s come from.
+pub enum SessionTokens {
+  /// The operating system's random number generator, so nobody can work out one token from others,
+  /// or from a recording.
+  Random(rand::OsRng),
+  /// The tokens a recorded session handed out, in order.
+  Replayed(std::collections::VecDeque<protocol::SessionToken>),
 }
 
 #[allow(missing_docs)]
-pub fn new() -> T {
+pub fn random_session_tokens() -> SessionTokens {
+  SessionTokens::Random(rand::OsRng::new().unwrap())
+}
+
+#[allow(missing_docs)]
+pub fn new(clock: clock::T, session_tokens: SessionTokens) -> T {
+  let now = clock.now();
   let world_width: u32 = 1 << 11;
   let world_width = world_width as f32;
   let physics =
@@ -78,9 +354,10 @@ pub fn new() -> T {
       )
     );
 
-  let server = T {
+  T {
     players           : Mutex::new(fnv_map::new()),
     mobs              : Mutex::new(fnv_map::new()),
+    paths             : Mutex::new(pathfinding::new()),
 
     player_allocator  : Mutex::new(id_allocator::new()),
     mob_allocator     : Mutex::new(id_allocator::new()),
@@ -96,19 +373,20 @@ pub fn new() -> T {
       let seed: &[usize] = &seed;
       Mutex::new(rand::SeedableRng::from_seed(seed))
     },
+    session_tokens: Mutex::new(session_tokens),
 
     clients: Mutex::new(fnv_map::new()),
-    sun: Mutex::new(Sun::new(SUN_TICK_NS)),
+    unattributed_drops: Mutex::new(0),
+    unattributed_bytes_received: Mutex::new(0),
+    sun: Mutex::new(Sun::new(SUN_TICK_NS, now)),
 
-    update_timer: {
-      let now = time::precise_time_ns();
-      let nanoseconds_per_second = 1000000000;
-      Mutex::new(
-        IntervalTimer::new(nanoseconds_per_second / UPDATES_PER_SECOND, now)
-      )
-    }
-  };
-
-  init_mobs(&server);
-  server
+    clock: clock,
+    tick: Mutex::new(0),
+    recorder: Mutex::new(None),
+    update_timer: Mutex::new(IntervalTimer::new(TICK_NS, now)),
+    ping_timer: Mutex::new(IntervalTimer::new(PING_INTERVAL_NS, now)),
+    client_timeout_ns: DEFAULT_CLIENT_TIMEOUT_SECS * 1_000_000_000,
+    reattach_grace_ns: protocol::REATTACH_GRACE_SECS * 1_000_000_000,
+    entity_update_radius: DEFAULT_ENTITY_UPDATE_RADIUS,
+  }
 }
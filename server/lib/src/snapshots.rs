@@ -0,0 +1,80 @@
+//! Keep track of the world snapshots sent to a client, so each new one can be sent
+//! as changes from one the client has acknowledged.
+
+use std::collections::VecDeque;
+
+use common::protocol;
+use common::snapshot;
+
+/// How many unacknowledged snapshots to remember. Clients that fall further behind get full snapshots.
+const MAX_UNACKED: usize = 64;
+
+#[allow(missing_docs)]
+pub struct T {
+  next_sequence : snapshot::Sequence,
+  /// The latest snapshot the client has acknowledged.
+  acked         : Option<snapshot::Sequence>,
+  /// The views sent since `acked`, oldest first.
+  sent          : VecDeque<(snapshot::Sequence, snapshot::View)>,
+}
+
+#[allow(missing_docs)]
+pub fn new() -> T {
+  T {
+    next_sequence : 0,
+    acked         : None,
+    sent          : VecDeque::new(),
+  }
+}
+
+impl T {
+  /// The view in the most recent snapshot.
+  pub fn latest(&self) -> Option<&snapshot::View> {
+    self.sent.back().map(|&(_, ref view)| view)
+  }
+
+  /// The client has received a snapshot.
+  pub fn ack(&mut self, sequence: snapshot::Sequence) {
+    if self.acked.map(|acked| sequence <= acked) == Some(true) {
+      return
+    }
+    if !self.sent.iter().any(|&(s, _)| s == sequence) {
+      // Either too old to be useful, or never sent.
+      return
+    }
+
+    self.acked = Some(sequence);
+    while self.sent.front().map(|&(s, _)| s < sequence) == Some(true) {
+      self.sent.pop_front();
+    }
+  }
+
+  /// Forget which snapshots the client has, so the next one is sent in full.
+  pub fn forget_acks(&mut self) {
+    self.acked = None;
+    self.sent.clear();
+  }
+
+  /// Produce the next snapshot, relative to the last acknowledged one if we still have it.
+  pub fn next(&mut self, view: snapshot::View, time: protocol::ServerTime) -> snapshot::WorldSnapshot {
+    let sequence = self.next_sequence;
+    self.next_sequence += 1;
+
+    let snapshot = {
+      let baseline =
+        self.acked.and_then(|acked| {
+          self.sent.iter()
+            .find(|&&(s, _)| s == acked)
+            .map(|&(s, ref view)| (s, view))
+        });
+      view.snapshot(sequence, time, baseline)
+    };
+
+    self.sent.push_back((sequence, view));
+    if self.sent.len() > MAX_UNACKED {
+      self.sent.pop_front();
+    }
+
+    snapshot
+  }
+}
@@ -0,0 +1,177 @@
+//! Populate the world with mobs around players, and clear away the mobs that no player is near.
+
+use cgmath::{Point3, EuclideanSpace, InnerSpace, Vector3};
+use collision::{Aabb3};
+use rand::Rng;
+use std::f32::consts::PI;
+use std::sync::Arc;
+
+use common::movement;
+use common::surroundings_loader;
+use common::voxel;
+
+use behavior;
+use mob;
+use mob_kinds;
+use pathfinding;
+use perception;
+use server;
+use terrain;
+
+/// How often to spawn and despawn, in world updates.
+const INTERVAL_TICKS: u64 = movement::TICKS_PER_SECOND;
+/// The closest to a player that mobs spawn.
+const MIN_SPAWN_DISTANCE: f32 = 16.0;
+/// The furthest from a player that mobs spawn.
+const MAX_SPAWN_DISTANCE: f32 = 48.0;
+/// How far above and below a player to look for ground to spawn on.
+const SPAWN_HEIGHT_RANGE: i32 = 16;
+/// How many places to try spawning around each player, each interval.
+const SPAWN_ATTEMPTS: u32 = 2;
+/// Mobs further than this from every player are removed.
+const DESPAWN_DISTANCE: f32 = 96.0;
+/// The most mobs that spawn around any one player.
+const MAX_MOBS_PER_PLAYER: usize = 8;
+/// The most mobs in the world.
+const MAX_MOBS: usize = 64;
+
+fn center(bounds: &Aabb3<f32>) -> Point3<f32> {
+  (bounds.min + bounds.max.to_vec()) * 0.5
+}
+
+/// Spawn and despawn mobs, if it's time to.
+pub fn update(server: &server::T) {
+  if *server.tick.lock().unwrap() % INTERVAL_TICKS != 0 {
+    return
+  }
+
+  let players: Vec<Point3<f32>> =
+    perception::players(server).iter()
+    .map(|&(_, ref bounds)| center(bounds))
+    .collect();
+  despawn(server, &players);
+  for player in &players {
+    spawn_around(server, player);
+  }
+}
+
+fn despawn(server: &server::T, players: &[Point3<f32>]) {
+  let mut mobs = server.mobs.lock().unwrap();
+  let far_away: Vec<_> =
+    mobs.iter()
+    .filter(|&(_, mob)| players.iter().all(|p| (mob.position - *p).magnitude() > DESPAWN_DISTANCE))
+    .map(|(&id, _)| id)
+    .collect();
+  for id in far_away {
+    let mob = mobs.remove(&id).unwrap();
+    debug!("Despawning {} mob {:?}", mob.kind.name, id);
+    mob.destroy(server);
+  }
+}
+
+fn can_spawn(kind: &mob::Kind, cell: &Point3<i32>, biome: terrain::biome::Kind, is_day: bool) -> bool {
+  let spawn = &kind.spawn;
+  let (lowest, highest) = spawn.heights;
+  let is_time =
+    match spawn.time_of_day {
+      mob::TimeOfDay::Day => is_day,
+      mob::TimeOfDay::Night => !is_day,
+      mob::TimeOfDay::Always => true,
+    };
+  is_time && lowest <= cell.y && cell.y <= highest && spawn.biomes.contains(&biome)
+}
+
+fn spawn_around(server: &server::T, player: &Point3<f32>) {
+  let (nearby, total, populations) = {
+    let mobs = server.mobs.lock().unwrap();
+    let nearby = mobs.values().filter(|mob| (mob.position - *player).magnitude() <= MAX_SPAWN_DISTANCE).count();
+    let populations: Vec<usize> =
+      mob_kinds::ALL.iter()
+      .map(|kind| mobs.values().filter(|mob| mob.kind.id == kind.id).count())
+      .collect();
+    (nearby, mobs.len(), populations)
+  };
+  if nearby >= MAX_MOBS_PER_PLAYER || total >= MAX_MOBS {
+    return
+  }
+
+  let is_day = server.sun.lock().unwrap().is_day();
+  let terrain = &server.terrain_loader.terrain;
+  for _ in 0 .. SPAWN_ATTEMPTS {
+    let (angle, distance): (f32, f32) = {
+      let mut rng = server.rng.lock().unwrap();
+      (rng.gen_range(0.0, 2.0 * PI), rng.gen_range(MIN_SPAWN_DISTANCE, MAX_SPAWN_DISTANCE))
+    };
+    let x = (player.x + distance * angle.cos()).floor() as i32;
+    let z = (player.z + distance * angle.sin()).floor() as i32;
+    let top = player.y.floor() as i32 + SPAWN_HEIGHT_RANGE;
+    let found = {
+      let voxels = terrain.voxels.lock().unwrap();
+      let found =
+        (0 .. 2 * SPAWN_HEIGHT_RANGE)
+        .map(|dy| Point3::new(x, top - dy, z))
+        .filter_map(|cell| {
+          pathfinding::ground(&*voxels, &cell).map(|_| {
+            // Ground running through the cell could be anywhere in it; drop onto it from above.
+            let feet_y =
+              match voxels.get(&voxel::bounds::new(cell.x, cell.y, cell.z, 0)) {
+                Some(&voxel::Surface(_)) => cell.y + 1,
+                _ => cell.y,
+              };
+            (cell, feet_y)
+          })
+        })
+        .next();
+      found
+    };
+    let (cell, feet_y) =
+      match found {
+        None => continue,
+        Some(found) => found,
+      };
+    let biome = terrain.biome(cell.x as f32 + 0.5, cell.z as f32 + 0.5);
+
+    let candidates: Vec<&'static mob::Kind> =
+      mob_kinds::ALL.iter().zip(populations.iter())
+      .filter(|&(kind, &population)| population < kind.spawn.max_population && can_spawn(kind, &cell, biome, is_day))
+      .map(|(&kind, _)| kind)
+      .collect();
+    if candidates.is_empty() {
+      continue
+    }
+    let kind = candidates[server.rng.lock().unwrap().gen_range(0, candidates.len())];
+
+    add_mob(server, kind, &Point3::new(cell.x as f32 + 0.5, feet_y as f32, cell.z as f32 + 0.5));
+    return
+  }
+}
+
+/// Put a new mob of the given kind in the world, standing at `feet`.
+pub fn add_mob(
+  server : &server::T,
+  kind   : &'static mob::Kind,
+  feet   : &Point3<f32>,
+) {
+  let size = Vector3::new(kind.size[0], kind.size[1], kind.size[2]);
+  let low_corner = *feet + Vector3::new(-size.x / 2.0, 0.0, -size.z / 2.0);
+  let bounds = Aabb3::new(low_corner, low_corner + size);
+  let entity_id = server.mob_allocator.lock().unwrap().allocate();
+  let physics_id = server.misc_allocator.lock().unwrap().allocate();
+  debug!("Spawning {} mob {:?} at {:?}", kind.name, entity_id, feet);
+
+  let mob =
+    mob::Mob {
+      position            : center(&bounds),
+      speed               : Vector3::new(0.0, 0.0, 0.0),
+      kind                : kind,
+      behavior            : Arc::new((kind.behavior)()),
+      blackboard          : behavior::new_blackboard(),
+      entity_id           : entity_id,
+      physics_id          : physics_id,
+      owner_id            : server.owner_allocator.lock().unwrap().allocate(),
+      surroundings_loader : surroundings_loader::new(8, Vec::new()),
+    };
+
+  server.physics.lock().unwrap().insert_misc(physics_id, &bounds);
+  server.mobs.lock().unwrap().insert(entity_id, mob);
+}
@@ -1,5 +1,4 @@
 use common::interval_timer::IntervalTimer;
-use time;
 use std;
 
 pub struct Sun {
@@ -10,16 +9,16 @@ pub struct Sun {
 }
 
 impl Sun {
-  pub fn new(tick_ns: u64) -> Sun {
+  pub fn new(tick_ns: u64, now: u64) -> Sun {
     Sun {
       position: 0,
-      timer: IntervalTimer::new(tick_ns, time::precise_time_ns()),
-      print_timer: IntervalTimer::new(2e9 as u64, time::precise_time_ns()),
+      timer: IntervalTimer::new(tick_ns, now),
+      print_timer: IntervalTimer::new(2e9 as u64, now),
     }
   }
 
-  pub fn update(&mut self) -> Option<f32> {
-    let ticks = self.timer.update(time::precise_time_ns());
+  pub fn update(&mut self, now: u64) -> Option<f32> {
+    let ticks = self.timer.update(now);
 
     if ticks == 0 {
       return None;
@@ -27,15 +26,23 @@ impl Sun {
 
     self.position = (std::num::Wrapping(self.position) + std::num::Wrapping(ticks as u16)).0;
 
-    // Fraction completed of a full cycle.
-    let fraction = (self.position as f32) / 65536.0;
-    // Longer day, shorter night.
-    let fraction = fraction * fraction;
-
-    if self.print_timer.update(time::precise_time_ns()) > 0 {
+    let fraction = self.fraction();
+    if self.print_timer.update(now) > 0 {
       debug!("Sun is at {:.1}%.", fraction * 100.0);
     }
 
     Some(fraction)
   }
+
+  /// Fraction completed of a full cycle.
+  pub fn fraction(&self) -> f32 {
+    let fraction = (self.position as f32) / 65536.0;
+    // Longer day, shorter night.
+    fraction * fraction
+  }
+
+  /// Whether the sun is above the horizon.
+  pub fn is_day(&self) -> bool {
+    self.fraction() < 0.5
+  }
 }
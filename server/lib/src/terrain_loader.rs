@@ -1,10 +1,10 @@
-use collision::{Aabb3};
 use std::sync::Mutex;
 use stopwatch;
 use time;
 
 use common::fnv_map;
 use common::id_allocator;
+use common::movement;
 use common::voxel;
 
 use entity;
@@ -30,7 +30,7 @@ pub struct T {
 impl T {
   pub fn new() -> T {
     T {
-      terrain             : terrain::T::new(terrain::Seed::new(0)),
+      terrain             : terrain::T::new(0),
       in_progress_terrain : Mutex::new(in_progress_terrain::T::new()),
       lod_map             : Mutex::new(lod::Map::new()),
       loaded              : Mutex::new(fnv_map::new()),
@@ -53,6 +53,8 @@ impl T {
     let max_lod_changed: bool;
     let mut lod_map = self.lod_map.lock().unwrap();
     let mut in_progress_terrain = self.in_progress_terrain.lock().unwrap();
+    // This replaces any full load the owner was still waiting on here.
+    lod_map.cancel_request(*position, owner);
     match lod_map.get(position, owner) {
       Some((Some(prev), lods)) => {
         prev_lod = Some(prev);
@@ -94,6 +96,7 @@ impl T {
       },
       lod::Full => {
         debug!("{:?} requested from gaia", position);
+        lod_map.request(*position, owner);
         load_block(
           update_gaia::Message::Load(time::precise_time_ns(), vec!(*position), LoadDestination::Local(owner))
         );
@@ -101,20 +104,27 @@ impl T {
     };
   }
 
+  /// Load `block` into collision for `owner`, if the owner is still waiting on it.
   pub fn insert_block(
-    block               : &LoadedTerrain,
+    block               : &voxel::T,
     position            : &voxel::bounds::T,
     owner               : lod::OwnerId,
+    allocator           : &Mutex<id_allocator::T<entity::id::Terrain>>,
     physics             : &Mutex<physics::T>,
     lod_map             : &mut lod::Map,
     in_progress_terrain : &mut in_progress_terrain::T,
     loaded              : &mut fnv_map::T<voxel::bounds::T, Vec<entity::id::Terrain>>,
   ) {
+    // The owner may have let go of this position (or gone away entirely) while the load was queued,
+    // and nothing would ever release it again.
+    if !lod_map.cancel_request(*position, owner) {
+      debug!("Dropping stale load of {:?} for {:?}", position, owner);
+      return
+    }
+
     let lod = lod::Full;
     let (_, change) = lod_map.insert(*position, lod, owner);
-    // TODO: This should be an unwrap, but the preconditions of another TODO aren't
-    // satisfied in src/update_gaia.rs.
-    // (i.e. blocks sometimes get here when they're stale).
+    // No change means another owner already has this block fully loaded.
     let change = match change {
       None => return,
       Some(change) => change,
@@ -139,16 +149,48 @@ impl T {
 
     stopwatch::time("terrain_loader.load.physics", || {
       let mut physics = physics.lock().unwrap();
-      let mut ids = Vec::with_capacity(block.bounds.len());
-      for &(ref id, ref bounds) in &block.bounds {
-        physics.insert_terrain(*id, bounds);
-        ids.push(*id);
+      let mut ids = Vec::new();
+      match movement::Shape::of_voxel(position, block) {
+        None => {},
+        Some(shape) => {
+          let id = allocator.lock().unwrap().allocate();
+          physics.insert_terrain(id, &shape);
+          ids.push(id);
+        },
       }
       let prev = loaded.insert(*position, ids);
       assert!(prev.is_none());
     });
   }
 
+  /// Replace the collision shapes of a loaded block after its voxel changed.
+  /// Blocks that aren't fully loaded are left alone; they'll pick up the change when they load.
+  pub fn reload_block(
+    position  : &voxel::bounds::T,
+    voxel     : &voxel::T,
+    allocator : &Mutex<id_allocator::T<entity::id::Terrain>>,
+    physics   : &mut physics::T,
+    loaded    : &mut fnv_map::T<voxel::bounds::T, Vec<entity::id::Terrain>>,
+  ) {
+    let ids =
+      match loaded.get_mut(position) {
+        None => return,
+        Some(ids) => ids,
+      };
+
+    for id in ids.drain(..) {
+      physics.remove_terrain(id);
+    }
+    match movement::Shape::of_voxel(position, voxel) {
+      None => {},
+      Some(shape) => {
+        let id = allocator.lock().unwrap().allocate();
+        physics.insert_terrain(id, &shape);
+        ids.push(id);
+      },
+    }
+  }
+
   pub fn unload(
     &self,
     physics  : &Mutex<physics::T>,
@@ -156,9 +198,13 @@ impl T {
     owner    : lod::OwnerId,
   ) {
     let lod_change;
-    match self.lod_map.lock().unwrap().remove(*position, owner) {
-      (_, None) => return,
-      (_, Some(c)) => lod_change = c,
+    {
+      let mut lod_map = self.lod_map.lock().unwrap();
+      lod_map.cancel_request(*position, owner);
+      match lod_map.remove(*position, owner) {
+        (_, None) => return,
+        (_, Some(c)) => lod_change = c,
+      }
     }
 
     lod_change.loaded.map(|loaded_lod| {
@@ -184,8 +230,16 @@ impl T {
       }
     });
   }
-}
 
-pub struct LoadedTerrain {
-  pub bounds: Vec<(entity::id::Terrain, Aabb3<f32>)>,
+  /// Release every handle that `owner` holds.
+  pub fn unload_owner(
+    &self,
+    physics : &Mutex<physics::T>,
+    owner   : lod::OwnerId,
+  ) {
+    let positions = self.lod_map.lock().unwrap().owned_by(owner);
+    for position in positions {
+      self.unload(physics, &position, owner);
+    }
+  }
 }
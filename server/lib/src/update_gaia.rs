@@ -1,12 +1,13 @@
 //! Creator of the earth.
 
-use collision::{Aabb3};
 use stopwatch;
 
 use common;
+use common::inventory;
 use common::protocol;
 use common::voxel;
 
+use entity;
 use lod;
 use server;
 use terrain_loader;
@@ -27,8 +28,8 @@ pub enum LoadDestination {
 pub enum Message {
   /// Load some voxels
   Load(u64, Vec<voxel::bounds::T>, LoadDestination),
-  /// Apply a brush operation
-  Brush(voxel_data::brush::T<Box<voxel_data::mosaic::T<common::voxel::Material> + Send>>),
+  /// Apply a brush operation for a player, who gets what it removes and pays for what it adds.
+  Brush(entity::id::Player, voxel_data::brush::T<Box<voxel_data::mosaic::T<common::voxel::Material> + Send>>),
 }
 
 // TODO: Consider adding terrain loads to a thread pool instead of having one monolithic separate thread.
@@ -44,30 +45,97 @@ pub fn update_gaia(
           load(server, time_requested, voxel_bounds, load_reason);
         });
       },
-      Message::Brush(mut brush) => {
-        let mut updates = Vec::new();
-        server.terrain_loader.terrain.brush(
-          &mut brush,
-          |block, bounds| {
-            trace!("update bounds {:?}", bounds);
-            updates.push((*bounds, *block));
-          },
-        );
+      Message::Brush(player_id, mut brush) => {
+        let mut paid = None;
+        let updates =
+          server.terrain_loader.terrain.brush(
+            &mut brush,
+            |changes| {
+              if changes.is_empty() {
+                return false
+              }
+              let mut gained = inventory::new();
+              let mut spent = inventory::new();
+              for &(ref bounds, ref before, ref after) in changes {
+                trace!("update bounds {:?}", bounds);
+                let (material, amount) = voxel::contents(bounds, before);
+                gained.add(material, amount);
+                let (material, amount) = voxel::contents(bounds, after);
+                spent.add(material, amount);
+              }
+              // This locks players while the terrain is locked.
+              paid = pay_for_brush(server, player_id, &gained, &spent);
+              if paid.is_none() {
+                debug!("Player {:?} can't afford their brush", player_id);
+              }
+              paid.is_some()
+            },
+          );
+        let (updates, inventory) =
+          match (updates, paid) {
+            (Some(updates), Some(inventory)) => (updates, inventory),
+            _ => return,
+          };
+
+        // Hold the collision locks until the clients have been told, so nobody collides with terrain
+        // that they can't see yet (or walks through terrain they can).
+        // Locks are taken in the order loaded -> physics -> clients.
+        let mut loaded = server.terrain_loader.loaded.lock().unwrap();
+        let mut physics = server.physics.lock().unwrap();
+        stopwatch::time("update_gaia.brush.physics", || {
+          for &(ref bounds, ref voxel) in &updates {
+            terrain_loader::T::reload_block(
+              bounds,
+              voxel,
+              &server.terrain_allocator,
+              &mut *physics,
+              &mut *loaded,
+            );
+          }
+        });
 
-        let mut clients = server.clients.lock().unwrap();
-        for (_, client) in clients.iter_mut() {
-          client.send(
-            protocol::ServerToClient::Voxels {
-              voxels : updates.clone(),
-              reason : protocol::VoxelReason::Updated,
+        {
+          let mut outgoing = server::outgoing_voxels(&updates);
+          let mut clients = server.clients.lock().unwrap();
+          for (_, client) in clients.iter_mut() {
+            if client.voxel_subscriptions.overlaps(&brush.bounds) {
+              client.send_voxels(&mut outgoing, protocol::VoxelReason::Updated);
             }
-          );
+            if client.players.contains(&player_id) {
+              client.send(protocol::ServerToClient::UpdateInventory(player_id, inventory));
+            }
+          }
         }
+        drop(physics);
+        drop(loaded);
+
+        server.paths.lock().unwrap().terrain_changed(&brush.bounds);
       },
     };
   })
 }
 
+/// Credit a player with what a brush removed, and charge them for what it added.
+/// Returns their new inventory, or None if they can't afford it (or have left).
+fn pay_for_brush(
+  server: &server::T,
+  player_id: entity::id::Player,
+  gained: &inventory::T,
+  spent: &inventory::T,
+) -> Option<inventory::T> {
+  let mut players = server.players.lock().unwrap();
+  match players.get_mut(&player_id) {
+    None => None,
+    Some(player) => {
+      if player.inventory.trade(gained, spent) {
+        Some(player.inventory)
+      } else {
+        None
+      }
+    },
+  }
+}
+
 #[inline(never)]
 fn load(
   server: &server::T,
@@ -88,21 +156,11 @@ fn load(
     LoadDestination::Local(owner) => {
       for voxel_bounds in voxel_bounds {
         let block = server.terrain_loader.terrain.load(&voxel_bounds);
-        let bounds =
-          match block {
-            voxel::Volume(voxel::Material::Empty) => Vec::new(),
-            _ => {
-              let (low, high) = voxel_bounds.corners();
-              let id = server.terrain_allocator.lock().unwrap().allocate();
-              vec!((id, Aabb3::new(low, high)))
-            },
-          };
-        // TODO: Check that this block isn't stale, i.e. should still be loaded.
-        // Maybe this should just ping the original thread, same as we ping the client.
         terrain_loader::T::insert_block(
-          &terrain_loader::LoadedTerrain { bounds: bounds },
+          &block,
           &voxel_bounds,
           owner,
+          &server.terrain_allocator,
           &server.physics,
           &mut *lod_map,
           &mut *in_progress_terrain,
@@ -118,13 +176,15 @@ fn load(
       }
 
       let mut clients = server.clients.lock().unwrap();
-      let client = clients.get_mut(&id).unwrap();
-      client.send(
-        protocol::ServerToClient::Voxels {
-          voxels : voxels,
-          reason : protocol::VoxelReason::Requested { at: time_requested },
-        }
-      );
+      match clients.get_mut(&id) {
+        None => {
+          // The client left before the load completed.
+          debug!("Dropping voxels for departed client {:?}", id);
+        },
+        Some(client) => {
+          client.send_voxels(&mut server::outgoing_voxels(&voxels), protocol::VoxelReason::Requested { at: time_requested });
+        },
+      }
     },
   }
 }
@@ -1,15 +1,21 @@
-use cgmath::{Point3, Vector3};
-use std::ops::Neg;
+use cgmath::{Point3, Vector3, InnerSpace};
 use stopwatch;
 
+use common::fnv_map;
+use common::movement;
 use common::protocol;
 use common::surroundings_loader::LoadType;
 use common::voxel;
 
+use entity;
+use heartbeat::heartbeat;
+use interest::send_entity_updates;
 use lod;
 use mob;
+use physics;
 use player;
 use server;
+use spawner;
 use update_gaia;
 
 // TODO: Consider removing the IntervalTimer.
@@ -21,34 +27,68 @@ pub fn update_world<RequestBlock>(
   RequestBlock: FnMut(update_gaia::Message),
 {
   stopwatch::time("update_world", || {
-    stopwatch::time("update_world.player", || {
-      let mut updates = Vec::new();
+    let mut player_updates = Vec::new();
+    let mut mob_updates = Vec::new();
+    let mut collisions = Vec::new();
+    let mut acks = Vec::new();
+    let mut health_updates = Vec::new();
+
+    let tick = *server.tick.lock().unwrap();
+    // Players can't lock mobs, so note down how much each mob hurts ahead of time.
+    let contact_damage: fnv_map::T<entity::id::Misc, u32> =
+      server.mobs.lock().unwrap().values()
+      .map(|mob| (mob.physics_id, mob.kind.contact_damage))
+      .collect();
 
+    stopwatch::time("update_world.player", || {
       for (_, player) in server.players.lock().unwrap().iter_mut() {
-        let (bounds, collisions) = player.update(server, request_block);
-        updates.push(protocol::ServerToClient::UpdatePlayer(player.entity_id, bounds));
-        updates.extend(
-          collisions.into_iter()
+        let health = player.health;
+        let (mut bounds, player_collisions) = player.update(server, request_block);
+        for collision in &player_collisions {
+          if let player::Collision::Misc(id) = *collision {
+            if let Some(&damage) = contact_damage.get(&id) {
+              player.hurt_by_contact(damage, tick);
+            }
+          }
+        }
+        if check_health(server, player, health, &mut health_updates) {
+          bounds = player::spawn_bounds();
+        }
+        player_updates.push((
+          player.entity_id,
+          bounds,
+          (player.movement.lateral_rotation, player.movement.vertical_rotation),
+        ));
+        acks.push((
+          player.entity_id,
+          protocol::ServerToClient::AckInput {
+            player      : player.entity_id,
+            sequence    : player.last_input,
+            ticks_since : player.ticks_since_input,
+            state       : player.movement,
+            bounds      : bounds,
+          },
+        ));
+        collisions.extend(
+          player_collisions.into_iter()
           .map(|c| {
             match c {
-              player::Collision::Terrain(_) => protocol::Collision::PlayerTerrain(player.entity_id),
-              player::Collision::Misc(_)    => protocol::Collision::PlayerMisc(player.entity_id),
+              player::Collision::Terrain(_)     => protocol::Collision::PlayerTerrain(player.entity_id),
+              player::Collision::Placeholder(_) => protocol::Collision::PlayerTerrain(player.entity_id),
+              player::Collision::Misc(_)        => protocol::Collision::PlayerMisc(player.entity_id),
             }
           })
-          .map(|c| {
-            protocol::ServerToClient::Collision(c)
-          })
         );
       }
+    });
 
-      let mut clients = server.clients.lock().unwrap();
-      for (_, client) in &mut *clients {
-        for update in &updates {
-          client.send(update.clone());
-        }
-      }
+    stopwatch::time("update_world.spawner", || {
+      spawner::update(server);
     });
 
+    // Players that mobs ran into, and how much they got hurt.
+    let mut mob_hits = Vec::new();
+
     stopwatch::time("update_world.mobs", || {
       for (_, mob) in server.mobs.lock().unwrap().iter_mut() {
         let position =
@@ -70,57 +110,102 @@ pub fn update_world<RequestBlock>(
         }
 
         {
-          let behavior = mob.behavior;
-          (behavior)(server, mob);
+          let behavior = mob.behavior.clone();
+          behavior.tick(server, mob);
         }
 
         mob.speed = mob.speed + -Vector3::new(0.0, 0.1, 0.0 as f32);
 
-        // TODO: This logic is dumb (isolating along components shouldn't be a thing). Change it.
-        let delta_p = mob.speed;
-        if delta_p.x != 0.0 {
-          translate_mob(server, mob, &Vector3::new(delta_p.x, 0.0, 0.0));
+        for id in move_mob(server, mob) {
+          mob_hits.push((id, mob.kind.contact_damage));
         }
-        if delta_p.y != 0.0 {
-          translate_mob(server, mob, &Vector3::new(0.0, delta_p.y, 0.0));
-        }
-        if delta_p.z != 0.0 {
-          translate_mob(server, mob, &Vector3::new(0.0, 0.0, delta_p.z));
+
+        let bounds = *server.physics.lock().unwrap().get_bounds(mob.physics_id).unwrap();
+        mob_updates.push((mob.entity_id, bounds, mob.kind.id));
+      }
+    });
+
+    stopwatch::time("update_world.mob_hits", || {
+      for (_, player) in server.players.lock().unwrap().iter_mut() {
+        let health = player.health;
+        for &(id, damage) in &mob_hits {
+          if id == player.physics_id {
+            player.hurt_by_contact(damage, tick);
+          }
         }
+        check_health(server, player, health, &mut health_updates);
       }
     });
 
-    server.sun.lock().unwrap().update().map(|fraction| {
+    send_entity_updates(server, &player_updates, &mob_updates, &collisions);
+
+    // Let each client correct its predictions for its own players, and know how they're faring.
+    {
+      let mut clients = server.clients.lock().unwrap();
+      for (player_id, message) in acks.into_iter().chain(health_updates.into_iter()) {
+        for (_, client) in clients.iter_mut() {
+          if client.players.contains(&player_id) {
+            client.send(message.clone());
+          }
+        }
+      }
+    }
+
+    server.sun.lock().unwrap().update(server.clock.now()).map(|fraction| {
       for (_, client) in server.clients.lock().unwrap().iter_mut() {
         client.send(protocol::ServerToClient::UpdateSun(fraction));
       }
     });
+
+    heartbeat(server);
+
+    *server.tick.lock().unwrap() += 1;
   });
 }
 
-fn translate_mob(
+/// If a player's health changed from `health`, tell its client; if it died, bring it back.
+/// Returns true if it died.
+fn check_health(
   server: &server::T,
-  mob: &mut mob::Mob,
-  delta_p: &Vector3<f32>,
-) {
-  let bounds;
-  {
-    let mut physics = server.physics.lock().unwrap();
-    if physics.translate_misc(mob.physics_id, *delta_p).is_some() {
-      mob.speed += delta_p.neg();
-      return;
-    } else {
-      bounds = *physics.get_bounds(mob.physics_id).unwrap();
-    }
+  player: &mut player::T,
+  health: u32,
+  health_updates: &mut Vec<(entity::id::Player, protocol::ServerToClient)>,
+) -> bool {
+  if player.health == health {
+    return false
   }
+  let died = player.health == 0;
+  if died {
+    info!("Player {:?} died", player.entity_id);
+    player.respawn(server);
+    health_updates.push((player.entity_id, protocol::ServerToClient::PlayerDied(player.entity_id)));
+  }
+  health_updates.push((player.entity_id, protocol::ServerToClient::UpdateHealth(player.entity_id, player.health)));
+  died
+}
 
-  mob.position += *delta_p;
-
-  for (_, client) in server.clients.lock().unwrap().iter_mut() {
-    client.send(
-      protocol::ServerToClient::UpdateMob(mob.entity_id, bounds),
-    );
+/// Move a mob along by its speed, sliding along whatever it runs into.
+/// Returns the non-terrain things it ran into.
+fn move_mob(
+  server: &server::T,
+  mob: &mut mob::Mob,
+) -> Vec<entity::id::Misc> {
+  let mut physics = server.physics.lock().unwrap();
+  let mut body = physics::Body { physics: &mut *physics, id: mob.physics_id };
+  let init_bounds = movement::Collider::bounds(&body);
+  let mut hits = Vec::new();
+  for collision in movement::slide(&mut body, mob.speed, movement::MAX_STEP_HEIGHT) {
+    // Stop moving into whatever we hit.
+    let into = mob.speed.dot(collision.normal);
+    if into < 0.0 {
+      mob.speed -= collision.normal * into;
+    }
+    if let physics::Collision::Misc(id) = collision.obstacle {
+      hits.push(id);
+    }
   }
+  mob.position += movement::Collider::bounds(&body).min - init_bounds.min;
+  hits
 }
 
 pub fn load_placeholders<RequestBlock>(
@@ -0,0 +1,69 @@
+//! Keep track of the regions of the world a client has asked for voxels in,
+//! so it only gets told about terrain changes it can see.
+
+use cgmath::{Point3};
+use collision::{Aabb3};
+use std::cmp::{min, max};
+
+use common::fnv_map;
+use common::protocol;
+use common::voxel;
+
+#[allow(missing_docs)]
+pub struct T {
+  /// Each region requested, by the id the client requested it under.
+  regions: fnv_map::T<protocol::RegionId, Aabb3<i32>>,
+}
+
+#[allow(missing_docs)]
+pub fn new() -> T {
+  T {
+    regions: fnv_map::new(),
+  }
+}
+
+/// The smallest region containing all of these voxels.
+pub fn region_of(voxels: &[voxel::bounds::T]) -> Option<Aabb3<i32>> {
+  let mut corners = voxels.iter().map(|voxel| {
+    let (low, high) = voxel.corners();
+    (
+      Point3::new(low.x.floor() as i32, low.y.floor() as i32, low.z.floor() as i32),
+      Point3::new(high.x.ceil() as i32, high.y.ceil() as i32, high.z.ceil() as i32),
+    )
+  });
+  corners.next().map(|first| {
+    let (low, high) =
+      corners.fold(first, |(low, high), (l, h)| {
+        (
+          Point3::new(min(low.x, l.x), min(low.y, l.y), min(low.z, l.z)),
+          Point3::new(max(high.x, h.x), max(high.y, h.y), max(high.z, h.z)),
+        )
+      });
+    Aabb3::new(low, high)
+  })
+}
+
+fn overlaps(a: &Aabb3<i32>, b: &Aabb3<i32>) -> bool {
+  a.min.x < b.max.x && b.min.x < a.max.x &&
+  a.min.y < b.max.y && b.min.y < a.max.y &&
+  a.min.z < b.max.z && b.min.z < a.max.z
+}
+
+impl T {
+  /// Start sending updates about a region, replacing whatever was subscribed to under `id`.
+  pub fn subscribe(&mut self, id: protocol::RegionId, region: &Aabb3<i32>) {
+    self.regions.insert(id, *region);
+  }
+
+  /// Stop sending updates about the region subscribed to under `id`.
+  pub fn unsubscribe(&mut self, id: protocol::RegionId) {
+    if self.regions.remove(&id).is_none() {
+      debug!("Unsubscribing from unknown region {:?}", id);
+    }
+  }
+
+  /// Does any subscribed region overlap `bounds`?
+  pub fn overlaps(&self, bounds: &Aabb3<i32>) -> bool {
+    self.regions.values().any(|region| overlaps(region, bounds))
+  }
+}
@@ -5,6 +5,8 @@ use noise::{Seed, Brownian2, Brownian3, perlin2, perlin3};
 
 use common::voxel;
 
+use biome;
+
 #[allow(missing_docs)]
 pub struct T {
   height: Brownian2<f64, fn (&Seed, &[f64; 2]) -> f64>,
@@ -41,14 +43,26 @@ pub fn new(seed: Seed) -> T {
 }
 
 impl T {
-  fn mat_density(&self, p: &Point3<f32>) -> (f32, voxel::Material) {
-    let height = self.height.apply(&self.seed, &[p.x as f64, p.z as f64]);
-    let height = height as f32;
-    let heightmap_density = height - p.y;
+  fn height(&self, x: f32, z: f32) -> f32 {
+    self.height.apply(&self.seed, &[x as f64, z as f64]) as f32
+  }
+
+  fn mountain_height(&self, x: f32, z: f32) -> f32 {
+    (16.0 * self.mountains.apply(&self.seed, &[x as f64 - 32.0, z as f64 - 10.0]) - 32.0) as f32
+  }
 
-    let mountain_height = 16.0 * self.mountains.apply(&self.seed, &[p.x as f64 - 32.0, p.z as f64 - 10.0]) - 32.0;
-    let mountain_height = mountain_height as f32;
-    let mountain_heightmap_density = mountain_height - p.y;
+  /// Which biome the column at (x, z) is in: mountains wherever they rise above the hills.
+  pub fn biome(&self, x: f32, z: f32) -> biome::Kind {
+    if self.mountain_height(x, z) > self.height(x, z) {
+      biome::Kind::Mountains
+    } else {
+      biome::Kind::Hills
+    }
+  }
+
+  fn mat_density(&self, p: &Point3<f32>) -> (f32, voxel::Material) {
+    let heightmap_density = self.height(p.x, p.z) - p.y;
+    let mountain_heightmap_density = self.mountain_height(p.x, p.z) - p.y;
 
     let feature_density = self.features.apply(&self.seed, &[p.x as f64, p.y as f64, p.z as f64]) * 8.0;
     let feature_density = feature_density as f32;
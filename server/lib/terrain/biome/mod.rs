@@ -4,3 +4,12 @@ pub mod caves;
 pub mod demo;
 pub mod hills;
 pub mod mountains;
+
+/// The regions of the world that differ in what lives there.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Kind {
+  /// The grassy, rolling hills.
+  Hills,
+  /// The stony mountains rising out of the hills.
+  Mountains,
+}
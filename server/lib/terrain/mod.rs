@@ -37,17 +37,24 @@ use common::voxel;
 pub struct T {
   pub mosaic: Mutex<cache_mosaic::T<voxel::Material>>,
   pub voxels: Mutex<voxel::tree::T>,
+  biomes: biome::demo::T,
 }
 
 impl T {
   #[allow(missing_docs)]
-  pub fn new(terrain_seed: Seed) -> T {
+  pub fn new(terrain_seed: u32) -> T {
     T {
-      mosaic: Mutex::new(cache_mosaic::new(Box::new(biome::demo::new(terrain_seed)))),
+      mosaic: Mutex::new(cache_mosaic::new(Box::new(biome::demo::new(Seed::new(terrain_seed))))),
       voxels: Mutex::new(voxel::tree::new()),
+      biomes: biome::demo::new(Seed::new(terrain_seed)),
     }
   }
 
+  /// The biome that the column at (x, z) is in.
+  pub fn biome(&self, x: f32, z: f32) -> biome::Kind {
+    self.biomes.biome(x, z)
+  }
+
   /// Load the block of terrain at a given position.
   // TODO: Allow this to be performed in such a way that self is only briefly locked.
   pub fn load(&self, bounds: &voxel::bounds::T) -> voxel::T {
@@ -67,28 +74,161 @@ impl T {
     }
   }
 
-  /// Apply a voxel brush to the terrain.
-  pub fn brush<VoxelChanged, Mosaic>(
+  /// Generate the voxel at `bounds`, as it is before anyone changes it.
+  fn generate(&self, bounds: &voxel::bounds::T) -> voxel::T {
+    let mut mosaic = self.mosaic.lock().unwrap();
+    voxel::unwrap(voxel::of_field(&mut *mosaic, bounds))
+  }
+
+  /// The voxel at `bounds` as it is now: whatever's stored, or else whatever would be generated.
+  fn current(&self, voxels: &voxel::tree::T, bounds: &voxel::bounds::T) -> voxel::T {
+    match voxels.get(bounds) {
+      Some(voxel) => *voxel,
+      None => self.generate(bounds),
+    }
+  }
+
+  /// What a brush fills in for a voxel that isn't stored yet.
+  // TODO: Put a max size on this
+  fn generate_for_brush(&self, bounds: &voxel::bounds::T) -> Option<voxel::T> {
+    if bounds.lg_size > 3 {
+      None
+    } else {
+      Some(self.generate(bounds))
+    }
+  }
+
+  /// Apply a voxel brush to the terrain, if `approve` allows it. `approve` is called with every voxel of the
+  /// smallest size that the brush will change, as (bounds, before, after), before anything is changed.
+  /// The terrain stays locked from planning the brush until it's applied.
+  /// Returns every voxel (of any size) that changed, and what it is now, or None if `approve` refused.
+  pub fn brush<Mosaic, Approve>(
     &self,
     brush: &mut voxel::brush::T<Mosaic>,
-    mut voxel_changed: VoxelChanged,
-  ) where
-    VoxelChanged: FnMut(&voxel::T, &voxel::bounds::T),
+    approve: Approve,
+  ) -> Option<Vec<(voxel::bounds::T, voxel::T)>> where
     Mosaic: voxel::mosaic::T<voxel::Material>,
+    Approve: FnOnce(&[(voxel::bounds::T, voxel::T, voxel::T)]) -> bool,
   {
     let mut voxels = self.voxels.lock().unwrap();
-    voxels.brush(
-      brush,
-      // TODO: Put a max size on this
-      &mut |bounds| {
-        if bounds.lg_size > 3 {
-          None
-        } else {
-          let mut mosaic = self.mosaic.lock().unwrap();
-          Some(voxel::unwrap(voxel::of_field(&mut *mosaic, bounds)))
-        }
-      },
-      &mut voxel_changed,
-    );
+
+    // Plan the brush on an empty scratch tree. It asks for every voxel the brush visits, and gets exactly
+    // what the real tree would give it: the stored voxel if there is one, and the same generated one otherwise.
+    // So it makes the same changes that applying the brush to the real tree will.
+    let mut planned = Vec::new();
+    stopwatch::time("terrain.brush.plan", || {
+      let mut scratch = voxel::tree::new();
+      scratch.brush(
+        brush,
+        &mut |bounds| {
+          match voxels.get(bounds) {
+            Some(voxel) => Some(*voxel),
+            None => self.generate_for_brush(bounds),
+          }
+        },
+        &mut |voxel: &voxel::T, bounds: &voxel::bounds::T| {
+          // Bigger voxels are just coarser copies of the same terrain.
+          if bounds.lg_size == 0 {
+            planned.push((*bounds, *voxel));
+          }
+        },
+      );
+    });
+    // The real tree hasn't changed yet, so it still has what was there before.
+    let planned: Vec<_> =
+      planned.into_iter()
+      .map(|(bounds, after)| (bounds, self.current(&voxels, &bounds), after))
+      .collect();
+
+    if !approve(&planned) {
+      return None
+    }
+
+    let mut changes = Vec::new();
+    stopwatch::time("terrain.brush.apply", || {
+      voxels.brush(
+        brush,
+        &mut |bounds| self.generate_for_brush(bounds),
+        &mut |voxel: &voxel::T, bounds: &voxel::bounds::T| changes.push((*bounds, *voxel)),
+      );
+    });
+    Some(changes)
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use cgmath::{Point3, Vector3};
+  use collision::{Aabb3};
+
+  use common::inventory;
+  use common::voxel;
+
+  use super::*;
+
+  #[test]
+  // What a brush is charged for should be exactly what it changes.
+  fn brush_is_charged_for_what_it_changes() {
+    let terrain = T::new(0);
+    let region = Aabb3::new(Point3::new(-4, -4, -4), Point3::new(4, 4, 4));
+    let mut cells = Vec::new();
+    for x in region.min.x .. region.max.x {
+    for y in region.min.y .. region.max.y {
+    for z in region.min.z .. region.max.z {
+      cells.push(voxel::bounds::new(x, y, z, 0));
+    }}}
+    // Some of the region is already loaded, and some isn't.
+    for bounds in cells.iter().filter(|bounds| (bounds.x + bounds.y + bounds.z) % 2 == 0) {
+      terrain.load(bounds);
+    }
+    let before: Vec<voxel::T> = cells.iter().map(|bounds| terrain.current(&terrain.voxels.lock().unwrap(), bounds)).collect();
+
+    let mut brush =
+      voxel::brush::T {
+        bounds      : region,
+        mosaic      :
+          voxel::mosaic::solid::T {
+            field    :
+              voxel::field::translation::T {
+                translation : Vector3::new(0.0, 0.0, 0.0),
+                field       : voxel::field::sphere::T { radius: 3.0 },
+              },
+            material : voxel::Material::Stone,
+          },
+        min_lg_size : 0,
+      };
+    let mut gained = inventory::new();
+    let mut spent = inventory::new();
+    let changes =
+      terrain.brush(
+        &mut brush,
+        |changes| {
+          for &(ref bounds, ref before, ref after) in changes {
+            let (material, amount) = voxel::contents(bounds, before);
+            gained.add(material, amount);
+            let (material, amount) = voxel::contents(bounds, after);
+            spent.add(material, amount);
+          }
+          true
+        },
+      ).unwrap();
+    assert!(changes.iter().any(|&(ref bounds, _)| bounds.lg_size == 0));
+
+    let mut removed = inventory::new();
+    let mut added = inventory::new();
+    let voxels = terrain.voxels.lock().unwrap();
+    for (bounds, before) in cells.iter().zip(before.iter()) {
+      let (material, amount) = voxel::contents(bounds, before);
+      removed.add(material, amount);
+      let (material, amount) = voxel::contents(bounds, &terrain.current(&voxels, bounds));
+      added.add(material, amount);
+    }
+    for &material in &inventory::CARRIED {
+      assert_eq!(
+        gained.count(material) as i64 - spent.count(material) as i64,
+        removed.count(material) as i64 - added.count(material) as i64,
+        "{:?}", material
+      );
+    }
   }
 }
@@ -7,11 +7,11 @@
 #![feature(allocator_api)]
 
 extern crate env_logger;
-extern crate nanomsg;
 extern crate log;
 extern crate thread_scoped;
 
 extern crate client_lib;
+extern crate common;
 #[cfg(feature = "dummy-client")]
 extern crate dummy_client_lib;
 extern crate server_lib;
@@ -34,16 +34,22 @@ fn main() {
   unsafe {
     let server_thread =
       thread_scoped::scoped(|| {
-        server_lib::run(server_url.borrow(), &quit_signal);
+        server_lib::run(
+          server_url.borrow(),
+          std::time::Duration::from_secs(server_lib::server::DEFAULT_CLIENT_TIMEOUT_SECS),
+          server_lib::server::DEFAULT_ENTITY_UPDATE_RADIUS,
+          None,
+          &quit_signal,
+        );
       });
 
     #[cfg(feature = "dummy-client")]
     dummy_client_lib::run(listen_url.borrow(), server_url.borrow());
     #[cfg(not(feature = "dummy-client"))]
-    client_lib::run(listen_url.borrow(), server_url.borrow());
+    client_lib::run(listen_url.borrow(), server_url.borrow(), false);
     *quit_signal.lock().unwrap() = true;
     server_thread.join();
 
-    nanomsg::Socket::terminate();
+    common::socket::terminate();
   }
 }